@@ -0,0 +1,68 @@
+//! Compare the execution time of programs with and without predecoding.
+//! Run with `cargo bench`. Results are printed on the standard error
+//! since the machine itself prints on the standard output.
+
+use interpreter::Machine;
+use std::io;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+/// A program name, its content and the registers to set before running it.
+type Benchmark<'a> = (&'a str, &'a [u8], &'a [(usize, u32)]);
+
+/// Number of instructions executed by `program` before its exit
+/// instruction.
+fn steps_before_exit(program: &[u8], regs: &[(usize, u32)]) -> u64 {
+    let mut machine = Machine::new(program);
+    for &(reg, value) in regs {
+        machine.set_reg(reg, value).unwrap();
+    }
+    let mut steps = 0;
+    while !machine.step_on(&mut io::sink()).unwrap() {
+        steps += 1;
+    }
+    steps
+}
+
+/// Best time out of `RUNS` executions of `program` up to its exit
+/// instruction, which is left out since it prints the whole memory.
+fn measure(program: &[u8], regs: &[(usize, u32)], predecoding: bool) -> Duration {
+    let steps = steps_before_exit(program, regs);
+    (0..RUNS)
+        .map(|_| {
+            let mut machine = Machine::new(program);
+            machine.set_predecoding(predecoding);
+            for &(reg, value) in regs {
+                machine.set_reg(reg, value).unwrap();
+            }
+            let start = Instant::now();
+            machine.run_until_on(&mut io::sink(), steps).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let programs: [Benchmark; 2] = [
+        (
+            "examples/fibonacci.bin",
+            include_bytes!("../examples/fibonacci.bin"),
+            &[],
+        ),
+        (
+            "tests/multiply.bin",
+            include_bytes!("../tests/multiply.bin"),
+            &[(11, 7), (12, 200_000)],
+        ),
+    ];
+    for (name, program, regs) in programs {
+        let interpreted = measure(program, regs, false);
+        let predecoded = measure(program, regs, true);
+        eprintln!(
+            "{name}: interpreted {interpreted:?}, predecoded {predecoded:?} ({:.2}x speedup)",
+            interpreted.as_secs_f64() / predecoded.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,359 @@
+; Sing 99 bottles of beer, to be linked with print.s
+    loadimm r2 <- #4096
+    loadimm r7 <- #99
+loop:
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_ubottles_1
+    store [r2] <- r3
+    loadimm r0 <- #ubottles
+return_from_ubottles_1:
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_1
+    loadimm r11 <- #22
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_1
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_1:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_bottles_1
+    store [r2] <- r3
+    loadimm r0 <- #bottles
+return_from_bottles_1:
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_2
+    loadimm r11 <- #10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_2
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_2:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    loadimm r4 <- #0
+    sub r4 <- r7 - r4
+    loadimm r5 <- #ite_then_1
+    move r0 <- r5 if r4 != 0
+    loadimm r0 <- #no_more_bottles
+    loadimm r0 <- #ite_end_1
+ite_end_1:
+ite_then_1:
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_3
+    loadimm r11 <- #31
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_3
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_3:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    loadimm r3 <- #1
+    sub r7 <- r7 - r3
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_ubottles_2
+    store [r2] <- r3
+    loadimm r0 <- #ubottles
+return_from_ubottles_2:
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_4
+    loadimm r11 <- #25
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_4
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_4:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    loadimm r0 <- #loop
+no_more_bottles:
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_5
+    loadimm r11 <- #69
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_5
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_5:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    exit
+ubottles:
+    loadimm r8 <- #1
+    sub r8 <- r7 - r8
+    loadimm r9 <- #ite_then_2
+    move r0 <- r9 if r8 != 0
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_6
+    loadimm r11 <- #10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_6
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_6:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    loadimm r0 <- #ite_end_2
+ite_then_2:
+    loadimm r8 <- #ite_then_3
+    move r0 <- r8 if r7 != 0
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_7
+    loadimm r11 <- #15
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_7
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_7:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    loadimm r0 <- #ite_end_3
+ite_then_3:
+    out_number r7
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_8
+    loadimm r11 <- #8
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_8
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_8:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+ite_end_2:
+ite_end_3:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r0 <- [r3]
+bottles:
+    loadimm r8 <- #1
+    sub r8 <- r7 - r8
+    loadimm r9 <- #ite_then_4
+    move r0 <- r9 if r8 != 0
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_9
+    loadimm r11 <- #10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_9
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_9:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    loadimm r0 <- #ite_end_4
+ite_then_4:
+    loadimm r8 <- #ite_then_5
+    move r0 <- r8 if r7 != 0
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_10
+    loadimm r11 <- #15
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_10
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_10:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    loadimm r0 <- #ite_end_5
+ite_then_5:
+    out_number r7
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_11
+    loadimm r11 <- #8
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_11
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_11:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+ite_end_4:
+ite_end_5:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r0 <- [r3]
+str_1:
+    .ascii " of beer on the wall, "
+str_2:
+    .ascii " of beer.\n"
+str_3:
+    .ascii "Take one down, pass it around, "
+str_4:
+    .ascii " of beer on the wall...\n\n"
+str_5:
+    .ascii "Go to the store and buy some more, 99 bottles of beer on the wall...\n"
+str_6:
+    .ascii "One bottle"
+str_7:
+    .ascii "No more bottles"
+str_8:
+    .ascii " bottles"
+str_9:
+    .ascii "one bottle"
+str_10:
+    .ascii "no more bottles"
+str_11:
+    .ascii " bottles"
@@ -0,0 +1,93 @@
+; Count from 1 to 10, to be linked with print.s
+    loadimm r2 <- #4096
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_1
+    loadimm r11 <- #37
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_1
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_1:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+loop:
+    loadimm r3 <- #-1
+    sub r7 <- r7 - r3
+    out_number r7
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_2
+    loadimm r11 <- #1
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_2
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_2:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    loadimm r4 <- #10
+    sub r4 <- r7 - r4
+    loadimm r5 <- #ite_then_1
+    move r0 <- r5 if r4 != 0
+    loadimm r0 <- #ite_end_1
+ite_then_1:
+    loadimm r0 <- #loop
+ite_end_1:
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_3
+    loadimm r11 <- #1
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_3
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_3:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    exit
+str_1:
+    b'I will count from 1 to 10 (included)\n'
+str_2:
+    b' '
+str_3:
+    b'\n'
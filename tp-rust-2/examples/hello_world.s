@@ -0,0 +1,29 @@
+; Print a greeting, to be linked with print.s
+    loadimm r2 <- #4096
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r10
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- r11
+    loadimm r10 <- #str_1
+    loadimm r11 <- #14
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #return_from_print_1
+    store [r2] <- r3
+    loadimm r0 <- #print
+return_from_print_1:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r11 <- [r3]
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r10 <- [r3]
+    exit
+str_1:
+    .ascii "Hello, world!\n"
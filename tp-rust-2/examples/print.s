@@ -0,0 +1,40 @@
+; print: write the r11 bytes starting at address r10 (one byte per `out`)
+;
+; Called with the return address pushed on the stack (r2), it pops it
+; before returning. r3, r8, r10 and r11 are modified.
+.global print
+print:
+print_loop:
+    loadimm r8 <- #print_next
+    move r0 <- r8 if r11 != 0
+    loadimm r0 <- #print_end
+print_next:
+    load r3 <- [r10]
+    out r3
+    loadimm r3 <- #-1
+    sub r10 <- r10 - r3
+    loadimm r3 <- #1
+    sub r11 <- r11 - r3
+    loadimm r0 <- #print_loop
+print_end:
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load r0 <- [r3]
+
+.test print_string
+    .call print
+    .set [2000], "Hi!\n"
+    .set r10, 2000
+    .set r11, 4
+    .expect r10, 2004
+    .expect r11, 0
+    .output "Hi!\n"
+.endtest
+
+.test print_nothing
+    .call print
+    .set r11, 0
+    .output ""
+.endtest
@@ -0,0 +1,77 @@
+use crate::machine::MEMORY_SIZE;
+
+/// Register receiving the number of arguments. The command line sets it
+/// only when the program is given arguments or an environment, so it is
+/// left at 0, with no program name in argv, otherwise.
+pub const ARGC_REGISTER: usize = 13;
+/// Register receiving the address of the argument pointers.
+pub const ARGV_REGISTER: usize = 14;
+/// Register receiving the address of the environment pointers.
+pub const ENVP_REGISTER: usize = 15;
+/// Register receiving the address below which the stack can grow.
+pub const STACK_REGISTER: usize = 2;
+
+/// The argument block placed at the top of memory, and the addresses the
+/// program receives in registers.
+pub(crate) struct Arguments {
+    /// Address of the first byte of the block, where the stack starts.
+    pub(crate) start: usize,
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) argc: usize,
+    pub(crate) argv: usize,
+    pub(crate) envp: usize,
+}
+
+/// Lay out the arguments and the environment at the top of memory, from
+/// the top:
+///   - a zero word, so that the strings can be read one word at a time,
+///   - the arguments then the `NAME=VALUE` environment variables, each as
+///     a nul-terminated string padded to a multiple of 4 bytes, so that
+///     they start on a word boundary,
+///   - the addresses of the arguments, as words, followed by a zero word,
+///   - the addresses of the environment variables, followed by a zero word.
+///
+/// Returns `None` if the block does not fit in memory.
+pub(crate) fn lay_out(args: &[&str], env: &[(&str, &str)]) -> Option<Arguments> {
+    let strings: Vec<Vec<u8>> = args
+        .iter()
+        .map(|arg| arg.as_bytes().to_vec())
+        .chain(
+            env.iter()
+                .map(|(name, value)| format!("{name}={value}").into_bytes()),
+        )
+        .collect();
+    let padded = |string: &Vec<u8>| (string.len() + 1).next_multiple_of(4);
+    let strings_size = strings.iter().map(padded).sum::<usize>() + 4;
+    let pointers_size = 4 * (strings.len() + 2);
+    let start = MEMORY_SIZE.checked_sub(strings_size + pointers_size)?;
+    let envp = start;
+    let argv = envp + 4 * (env.len() + 1);
+
+    // Environment pointers, argument pointers then strings
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut addresses: Vec<usize> = Vec::new();
+    let mut address = MEMORY_SIZE - strings_size;
+    for string in &strings {
+        addresses.push(address);
+        address += padded(string);
+    }
+    let (arg_addresses, env_addresses) = addresses.split_at(args.len());
+    for addresses in [env_addresses, arg_addresses] {
+        for &address in addresses.iter().chain([&0]) {
+            bytes.extend_from_slice(&(address as u32).to_le_bytes());
+        }
+    }
+    for string in &strings {
+        bytes.extend(string);
+        bytes.resize(bytes.len() + padded(string) - string.len(), 0);
+    }
+    bytes.resize(MEMORY_SIZE - start, 0);
+    Some(Arguments {
+        start,
+        bytes,
+        argc: args.len(),
+        argv,
+        envp,
+    })
+}
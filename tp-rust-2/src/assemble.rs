@@ -0,0 +1,1696 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::arguments::STACK_REGISTER;
+use crate::expression::{Binding, EvaluationError, Expr, Op};
+use crate::machine::{IP, MEMORY_SIZE, NREGS};
+use crate::object::{LineEntry, Object, ObjectSymbol, Relocation};
+use crate::suggest::suggest;
+use crate::unit_test::UnitTest;
+
+/// A problem found by [assemble] on a line of the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    /// Line number, starting from 1.
+    pub line: usize,
+    /// Column of the offending token, in characters from 1. Errors in the
+    /// expansion of a macro or a pseudo-instruction point to its name.
+    pub column: usize,
+    pub kind: AssemblyErrorKind,
+    /// A known name close to a misspelled mnemonic, directive or label.
+    pub suggestion: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssemblyErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    /// A backslash in a string is not followed by a known escape.
+    InvalidEscape,
+    InvalidNumber(String),
+    UnknownMnemonic(String),
+    UnknownDirective(String),
+    /// The line does not follow the syntax of its instruction or
+    /// directive. The expected token is described.
+    Expected(&'static str),
+    /// A register name designates a register which does not exist.
+    InvalidRegister(String),
+    /// An immediate does not fit in the 16 signed bits of `loadimm`.
+    ImmediateOutOfRange(i64),
+    /// An expression depends on the address of a label where a constant
+    /// is needed, or on a label defined later.
+    NotConstant,
+    /// An immediate is not the address of a label plus or minus a constant,
+    /// so that it cannot be computed when linking.
+    NotRelocatable,
+    DivisionByZero,
+    /// A data byte does not fit in 8 bits.
+    ByteOutOfRange(i64),
+    /// A `.word` value does not fit in 32 bits.
+    WordOutOfRange(i64),
+    /// A character literal does not stand for exactly one byte.
+    InvalidCharacter,
+    /// The size given to `.space` or `.align`, or the offset given to
+    /// `.org`, is negative or larger than the machine memory.
+    InvalidSize(i64),
+    /// `.org` designates an offset before the current one.
+    OrgBackwards(i64),
+    DuplicateLabel(String),
+    /// A label declared with `.global` is not defined.
+    UndefinedGlobal(String),
+    DuplicateMacro(String),
+    /// A `.macro` directive appears in the definition or the expansion of
+    /// a macro.
+    NestedMacro,
+    /// A `.endm` directive does not end the definition of a macro.
+    UnmatchedEndm,
+    /// The source ends in the definition of a macro.
+    UnterminatedMacro(String),
+    /// A macro is invoked with the wrong number of arguments.
+    MacroArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// A macro is invoked from itself, directly or not.
+    MacroRecursion(String),
+    /// A pseudo-instruction is given `r3`, which its expansion overwrites
+    /// before using the operand.
+    ScratchRegister(String),
+    /// A `.test` directive appears in a test or in the expansion of a
+    /// macro.
+    NestedTest,
+    /// A `.endtest` directive does not end a test.
+    UnmatchedEndtest,
+    /// The source ends in a test.
+    UnterminatedTest(String),
+    /// A test refers to a label which is not defined in the module.
+    UndefinedLabel(String),
+    /// A test reads or writes memory outside of the machine memory.
+    InvalidAddress(i64),
+    /// `.include` names a file which is not in [LIBRARY].
+    UnknownInclude(String),
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            AssemblyErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {c:?}"),
+            AssemblyErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            AssemblyErrorKind::InvalidEscape => write!(f, "invalid escape sequence"),
+            AssemblyErrorKind::InvalidNumber(number) => write!(f, "invalid number `{number}`"),
+            AssemblyErrorKind::UnknownMnemonic(name) => write!(f, "unknown instruction `{name}`"),
+            AssemblyErrorKind::UnknownDirective(name) => write!(f, "unknown directive `{name}`"),
+            AssemblyErrorKind::Expected(what) => write!(f, "expected {what}"),
+            AssemblyErrorKind::InvalidRegister(name) => write!(f, "invalid register `{name}`"),
+            AssemblyErrorKind::ImmediateOutOfRange(value) => write!(
+                f,
+                "immediate {value} does not fit in the 16 bits of `loadimm` (-32768 to 32767)"
+            ),
+            AssemblyErrorKind::NotConstant => {
+                write!(f, "expected a constant or labels defined above")
+            }
+            AssemblyErrorKind::NotRelocatable => {
+                write!(
+                    f,
+                    "expected the address of a label plus or minus a constant"
+                )
+            }
+            AssemblyErrorKind::DivisionByZero => write!(f, "division by zero"),
+            AssemblyErrorKind::ByteOutOfRange(value) => {
+                write!(f, "byte {value} does not fit in 8 bits")
+            }
+            AssemblyErrorKind::WordOutOfRange(value) => {
+                write!(f, "word {value} does not fit in 32 bits")
+            }
+            AssemblyErrorKind::InvalidCharacter => {
+                write!(f, "a character literal must stand for one byte")
+            }
+            AssemblyErrorKind::InvalidSize(size) => write!(f, "invalid size {size}"),
+            AssemblyErrorKind::OrgBackwards(offset) => {
+                write!(f, "`.org {offset}` goes back before the current offset")
+            }
+            AssemblyErrorKind::DuplicateLabel(name) => {
+                write!(f, "label `{name}` is already defined")
+            }
+            AssemblyErrorKind::UndefinedGlobal(name) => {
+                write!(f, "global label `{name}` is not defined")
+            }
+            AssemblyErrorKind::DuplicateMacro(name) => {
+                write!(f, "macro `{name}` is already defined")
+            }
+            AssemblyErrorKind::NestedMacro => write!(f, "macros cannot be defined in macros"),
+            AssemblyErrorKind::UnmatchedEndm => write!(f, "`.endm` without `.macro`"),
+            AssemblyErrorKind::UnterminatedMacro(name) => {
+                write!(f, "macro `{name}` is not ended by `.endm`")
+            }
+            AssemblyErrorKind::MacroArguments {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "macro `{name}` takes {expected} arguments but {found} were given"
+            ),
+            AssemblyErrorKind::MacroRecursion(name) => {
+                write!(f, "macro `{name}` is invoked recursively")
+            }
+            AssemblyErrorKind::ScratchRegister(mnemonic) => {
+                write!(
+                    f,
+                    "`{mnemonic}` overwrites r3 and cannot use it as an operand"
+                )
+            }
+            AssemblyErrorKind::NestedTest => {
+                write!(f, "tests cannot be defined in tests or macros")
+            }
+            AssemblyErrorKind::UnmatchedEndtest => write!(f, "`.endtest` without `.test`"),
+            AssemblyErrorKind::UnterminatedTest(name) => {
+                write!(f, "test `{name}` is not ended by `.endtest`")
+            }
+            AssemblyErrorKind::UndefinedLabel(name) => {
+                write!(f, "label `{name}` is not defined")
+            }
+            AssemblyErrorKind::InvalidAddress(address) => {
+                write!(f, "address {address} is outside of memory")
+            }
+            AssemblyErrorKind::UnknownInclude(name) => {
+                write!(f, "no library is named `{name}`")
+            }
+        }?;
+        match &self.suggestion {
+            Some(suggestion) => write!(f, ", did you mean `{suggestion}`?"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl AssemblyError {
+    /// Describe the error for humans: `file:line:column` and the message,
+    /// then the source line with a caret under the column.
+    pub fn report(&self, file: &str, source: &str) -> String {
+        report(file, source, self.line, self.column, self)
+    }
+}
+
+/// Show `error`, which starts with its line and column, after `file:`, then
+/// its line of `source` with a caret under the column.
+pub(crate) fn report(
+    file: &str,
+    source: &str,
+    line: usize,
+    column: usize,
+    error: &dyn fmt::Display,
+) -> String {
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    // Keep tabs so that the caret lines up with the source
+    let indent: String = text
+        .chars()
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let margin = " ".repeat(line.to_string().len());
+    format!("{file}:{error}\n{margin} |\n{line} | {text}\n{margin} | {indent}^\n")
+}
+
+/// An error at a byte offset of a line, before it is known where the line
+/// is in the source.
+struct Located {
+    kind: AssemblyErrorKind,
+    offset: usize,
+}
+
+/// Instructions and pseudo-instructions, for suggestions.
+const MNEMONICS: [&str; 16] = [
+    "move",
+    "store",
+    "load",
+    "loadimm",
+    "sub",
+    "out",
+    "exit",
+    "out_number",
+    "brk",
+    "push",
+    "pop",
+    "call",
+    "ret",
+    "jmp",
+    "mov",
+    "add",
+];
+
+/// Directives without their dot, for suggestions.
+const DIRECTIVES: [&str; 13] = [
+    "global", "equ", "byte", "word", "ascii", "asciz", "space", "align", "org", "macro", "endm",
+    "test", "include",
+];
+
+/// The sources bundled with the assembler which `.include` can insert, by
+/// name.
+pub const LIBRARY: [(&str, &str); 1] = [("std.s", include_str!("../stdlib/std.s"))];
+
+/// Directives of tests, between `.test` and `.endtest`.
+const TEST_DIRECTIVES: [&str; 5] = ["call", "set", "expect", "output", "endtest"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// Mnemonics, registers, labels and keywords.
+    Word(String),
+    /// A word preceded by a dot.
+    Directive(String),
+    Number(i64),
+    /// A `b'...'`, `b"..."` or `"..."` string.
+    Bytes(Vec<u8>),
+    Punct(&'static str),
+}
+
+const PUNCTS: [&str; 13] = [
+    "<-", "!=", "#", "[", "]", "(", ")", "-", "+", "*", "/", ":", ",",
+];
+
+/// Split a line into tokens, up to an optional `;` comment. Returns the
+/// tokens and the offset of each of them in the line.
+fn lex(line: &str) -> Result<(Vec<Token>, Vec<usize>), Located> {
+    let mut tokens = Vec::new();
+    let mut starts = Vec::new();
+    let mut rest = line;
+    loop {
+        rest = rest.trim_start();
+        let Some(c) = rest.chars().next() else {
+            return Ok((tokens, starts));
+        };
+        let start = line.len() - rest.len();
+        let at = |kind| Located {
+            kind,
+            offset: start,
+        };
+        if c != ';' {
+            starts.push(start);
+        }
+        let word_length = |s: &str| {
+            s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(s.len())
+        };
+        if c == ';' {
+            return Ok((tokens, starts));
+        } else if rest.starts_with("b'") || rest.starts_with("b\"") {
+            let (bytes, length) = lex_quoted(&rest[1..]).map_err(at)?;
+            tokens.push(Token::Bytes(bytes));
+            rest = &rest[1 + length..];
+        } else if c == '"' {
+            let (bytes, length) = lex_quoted(rest).map_err(at)?;
+            tokens.push(Token::Bytes(bytes));
+            rest = &rest[length..];
+        } else if c == '\'' {
+            // A character such as 'A' or '\n', standing for its code
+            let (bytes, length) = lex_quoted(rest).map_err(at)?;
+            match bytes[..] {
+                [byte] => tokens.push(Token::Number(byte as i64)),
+                _ => return Err(at(AssemblyErrorKind::InvalidCharacter)),
+            }
+            rest = &rest[length..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let length = word_length(rest);
+            tokens.push(Token::Word(rest[..length].to_string()));
+            rest = &rest[length..];
+        } else if c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let length = 1 + word_length(&rest[1..]);
+            tokens.push(Token::Directive(rest[1..length].to_string()));
+            rest = &rest[length..];
+        } else if c.is_ascii_digit() {
+            let length = word_length(rest);
+            tokens.push(Token::Number(parse_number(&rest[..length]).map_err(at)?));
+            rest = &rest[length..];
+        } else if let Some(punct) = PUNCTS.iter().find(|p| rest.starts_with(*p)) {
+            tokens.push(Token::Punct(punct));
+            rest = &rest[punct.len()..];
+        } else {
+            return Err(at(AssemblyErrorKind::UnexpectedCharacter(c)));
+        }
+    }
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number.
+fn parse_number(text: &str) -> Result<i64, AssemblyErrorKind> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| AssemblyErrorKind::InvalidNumber(text.to_string()))
+}
+
+/// Parse the text between the quote at the beginning of `text` and the
+/// next one, with the escapes of the `.dis` listings. Returns its bytes and
+/// its length in `text`, quotes included.
+pub(crate) fn lex_quoted(text: &str) -> Result<(Vec<u8>, usize), AssemblyErrorKind> {
+    let quote = text.as_bytes()[0];
+    let mut bytes = Vec::new();
+    let mut i = 1;
+    let source = text.as_bytes();
+    loop {
+        match source.get(i) {
+            None => return Err(AssemblyErrorKind::UnterminatedString),
+            Some(&c) if c == quote => return Ok((bytes, i + 1)),
+            Some(b'\\') => {
+                let escaped = match source.get(i + 1) {
+                    Some(b'n') => b'\n',
+                    Some(b't') => b'\t',
+                    Some(b'r') => b'\r',
+                    Some(b'0') => 0,
+                    Some(&c @ (b'\\' | b'\'' | b'"')) => c,
+                    Some(b'x') => {
+                        let hex = text
+                            .get(i + 2..i + 4)
+                            .ok_or(AssemblyErrorKind::InvalidEscape)?;
+                        i += 2;
+                        u8::from_str_radix(hex, 16).map_err(|_| AssemblyErrorKind::InvalidEscape)?
+                    }
+                    _ => return Err(AssemblyErrorKind::InvalidEscape),
+                };
+                bytes.push(escaped);
+                i += 2;
+            }
+            Some(&c) => {
+                bytes.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+impl From<EvaluationError> for AssemblyErrorKind {
+    fn from(error: EvaluationError) -> Self {
+        match error {
+            EvaluationError::NotRelocatable => AssemblyErrorKind::NotRelocatable,
+            EvaluationError::DivisionByZero => AssemblyErrorKind::DivisionByZero,
+        }
+    }
+}
+
+/// The operand of `loadimm`.
+enum Immediate {
+    Value(i16),
+    /// An expression with names, computed once the module is assembled.
+    Expression(Expr),
+}
+
+/// The number of the register designated by a name such as `r12`, or by
+/// the aliases `ip` (`r0`) and `sp` (`r2`, the stack pointer). Returns
+/// `None` if the name does not look like a register.
+fn register_number(name: &str) -> Option<usize> {
+    match name {
+        "ip" => Some(IP),
+        "sp" => Some(STACK_REGISTER),
+        _ => name
+            .strip_prefix('r')
+            .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            .map(|n| n.parse().unwrap_or(usize::MAX)),
+    }
+}
+
+/// Reads the tokens of a line in order.
+struct Cursor<'a> {
+    tokens: &'a [Token],
+    /// Offset of each token in the line.
+    starts: &'a [usize],
+    /// Length of the line.
+    end: usize,
+    position: usize,
+    /// The token errors point to: the last one read unless set otherwise.
+    mark: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(tokens: &'a [Token], starts: &'a [usize], end: usize) -> Self {
+        Cursor {
+            tokens,
+            starts,
+            end,
+            position: 0,
+            mark: 0,
+        }
+    }
+
+    /// Offset in the line of the token errors point to, or of the end of
+    /// the line if it is missing.
+    fn offset(&self) -> usize {
+        self.starts.get(self.mark).copied().unwrap_or(self.end)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.mark = self.position;
+        self.position += 1;
+        self.tokens.get(self.position - 1)
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+
+    fn punct(
+        &mut self,
+        punct: &'static str,
+        expected: &'static str,
+    ) -> Result<(), AssemblyErrorKind> {
+        match self.next() {
+            Some(Token::Punct(p)) if *p == punct => Ok(()),
+            _ => Err(AssemblyErrorKind::Expected(expected)),
+        }
+    }
+
+    fn word(&mut self, expected: &'static str) -> Result<String, AssemblyErrorKind> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word.clone()),
+            _ => Err(AssemblyErrorKind::Expected(expected)),
+        }
+    }
+
+    /// Tells whether the next token is a word such as `r12` or `sp`,
+    /// designating a register rather than a label.
+    fn peek_register(&self) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if register_number(word).is_some())
+    }
+
+    fn register(&mut self) -> Result<u8, AssemblyErrorKind> {
+        let name = self.word("a register")?;
+        match register_number(&name) {
+            Some(n) if n < NREGS => Ok(n as u8),
+            Some(_) => Err(AssemblyErrorKind::InvalidRegister(name)),
+            None => Err(AssemblyErrorKind::Expected("a register")),
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, AssemblyErrorKind> {
+        let negative = self.is_punct("-");
+        if negative {
+            self.next();
+        }
+        match self.next() {
+            Some(&Token::Number(n)) if negative => Ok(-n),
+            Some(&Token::Number(n)) => Ok(n),
+            _ => Err(AssemblyErrorKind::Expected("a number")),
+        }
+    }
+
+    /// Sums and differences of terms.
+    fn expression(&mut self) -> Result<Expr, AssemblyErrorKind> {
+        let mut expression = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct("+")) => Op::Add,
+                Some(Token::Punct("-")) => Op::Sub,
+                _ => return Ok(expression),
+            };
+            self.next();
+            expression = Expr::Binary(op, Box::new(expression), Box::new(self.term()?));
+        }
+    }
+
+    /// Products and quotients of factors.
+    fn term(&mut self) -> Result<Expr, AssemblyErrorKind> {
+        let mut term = self.factor()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct("*")) => Op::Mul,
+                Some(Token::Punct("/")) => Op::Div,
+                _ => return Ok(term),
+            };
+            self.next();
+            term = Expr::Binary(op, Box::new(term), Box::new(self.factor()?));
+        }
+    }
+
+    /// A number, a name, or a negated or parenthesized expression.
+    fn factor(&mut self) -> Result<Expr, AssemblyErrorKind> {
+        match self.next().cloned() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Word(name)) => Ok(Expr::Name(name)),
+            Some(Token::Punct("-")) => Ok(Expr::Negate(Box::new(self.factor()?))),
+            Some(Token::Punct("(")) => {
+                let expression = self.expression()?;
+                self.punct(")", "`)`")?;
+                Ok(expression)
+            }
+            _ => Err(AssemblyErrorKind::Expected("an expression")),
+        }
+    }
+
+    fn immediate(&mut self) -> Result<Immediate, AssemblyErrorKind> {
+        self.signed_immediate(false)
+    }
+
+    /// Parse an immediate, or its opposite if `negate` is set. The value
+    /// written is reported if the result does not fit in 16 bits.
+    fn signed_immediate(&mut self, negate: bool) -> Result<Immediate, AssemblyErrorKind> {
+        self.punct("#", "`#`")?;
+        let start = self.position;
+        let expression = self.expression()?;
+        if !expression.is_number() {
+            return Ok(Immediate::Expression(match negate {
+                true => Expr::Negate(Box::new(expression)),
+                false => expression,
+            }));
+        }
+        self.mark = start;
+        let value = expression.evaluate(&|_| unreachable!())?.constant;
+        let result = if negate { value.wrapping_neg() } else { value };
+        i16::try_from(result)
+            .map(Immediate::Value)
+            .map_err(|_| AssemblyErrorKind::ImmediateOutOfRange(value))
+    }
+
+    fn end(&mut self) -> Result<(), AssemblyErrorKind> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => {
+                self.mark = self.position;
+                Err(AssemblyErrorKind::Expected("the end of the line"))
+            }
+        }
+    }
+}
+
+/// Parse an instruction, in the syntax of the `.dis` listings. Returns its
+/// bytes and the expression of its immediate if it has to be computed
+/// later.
+fn instruction(
+    mnemonic: &str,
+    cursor: &mut Cursor,
+) -> Result<(Vec<u8>, Option<Expr>), AssemblyErrorKind> {
+    let arrow = |cursor: &mut Cursor| cursor.punct("<-", "`<-`");
+    let bytes = match mnemonic {
+        "move" => {
+            let a = cursor.register()?;
+            arrow(cursor)?;
+            let b = cursor.register()?;
+            if cursor.word("`if`")? != "if" {
+                return Err(AssemblyErrorKind::Expected("`if`"));
+            }
+            let c = cursor.register()?;
+            cursor.punct("!=", "`!=`")?;
+            if cursor.number()? != 0 {
+                return Err(AssemblyErrorKind::Expected("`0`"));
+            }
+            vec![1, a, b, c]
+        }
+        "store" => {
+            cursor.punct("[", "`[`")?;
+            let a = cursor.register()?;
+            cursor.punct("]", "`]`")?;
+            arrow(cursor)?;
+            vec![2, a, cursor.register()?]
+        }
+        "load" => {
+            let a = cursor.register()?;
+            arrow(cursor)?;
+            cursor.punct("[", "`[`")?;
+            let b = cursor.register()?;
+            cursor.punct("]", "`]`")?;
+            vec![3, a, b]
+        }
+        "loadimm" => {
+            let a = cursor.register()?;
+            arrow(cursor)?;
+            return Ok(match cursor.immediate()? {
+                Immediate::Value(value) => {
+                    let [low, high] = value.to_le_bytes();
+                    (vec![4, a, low, high], None)
+                }
+                Immediate::Expression(expression) => (vec![4, a, 0, 0], Some(expression)),
+            });
+        }
+        "sub" => {
+            let a = cursor.register()?;
+            arrow(cursor)?;
+            let b = cursor.register()?;
+            cursor.punct("-", "`-`")?;
+            vec![5, a, b, cursor.register()?]
+        }
+        "out" => vec![6, cursor.register()?],
+        "exit" if cursor.peek().is_none() => vec![7],
+        "exit" => vec![10, cursor.register()?],
+        "out_number" => vec![8, cursor.register()?],
+        "brk" => vec![9],
+        _ => return Err(AssemblyErrorKind::UnknownMnemonic(mnemonic.to_string())),
+    };
+    Ok((bytes, None))
+}
+
+/// A macro defined with `.macro`.
+struct Macro {
+    parameters: Vec<String>,
+    /// Lines between `.macro` and `.endm`, before substitution.
+    body: Vec<String>,
+}
+
+/// How deep macros can invoke other macros, which catches recursive ones.
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// Register overwritten by the expansion of pseudo-instructions.
+const SCRATCH: u8 = 3;
+
+/// Split the arguments of a macro invocation at the commas outside of
+/// strings, up to an optional comment.
+fn split_arguments(text: &str) -> Vec<&str> {
+    let mut arguments = Vec::new();
+    let mut start = 0;
+    let mut end = text.len();
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == ',' => {
+                arguments.push(text[start..i].trim());
+                start = i + 1;
+            }
+            None if c == ';' => {
+                end = i;
+                break;
+            }
+            None => (),
+        }
+    }
+    let last = text[start..end].trim();
+    if !last.is_empty() || !arguments.is_empty() {
+        arguments.push(last);
+    }
+    arguments
+}
+
+/// Replace `\name` by the argument of the parameter `name` and `\@` by
+/// `unique` in a line of a macro body. Other backslashes, such as the
+/// escapes of strings, are kept.
+fn substitute(line: &str, parameters: &[String], arguments: &[&str], unique: usize) -> String {
+    let mut result = String::new();
+    let mut rest = line;
+    while let Some(i) = rest.find('\\') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('@') {
+            result.push_str(&unique.to_string());
+            rest = after;
+            continue;
+        }
+        let length = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        match parameters.iter().position(|p| *p == rest[..length]) {
+            Some(p) if length > 0 => {
+                result.push_str(arguments[p]);
+                rest = &rest[length..];
+            }
+            _ => {
+                // Keep escaped backslashes together
+                let length = if rest.starts_with('\\') { 1 } else { 0 };
+                result.push('\\');
+                result.push_str(&rest[..length]);
+                rest = &rest[length..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// The operand of `loadimm`, as written in the source.
+fn immediate_text(immediate: Immediate) -> String {
+    match immediate {
+        Immediate::Value(value) => value.to_string(),
+        Immediate::Expression(expression) => expression.to_string(),
+    }
+}
+
+/// The instructions of `pop rX`, as found in the `.dis` listings.
+fn pop(register: u8) -> Vec<String> {
+    vec![
+        "loadimm r3 <- #-4".to_string(),
+        "sub r2 <- r2 - r3".to_string(),
+        "loadimm r3 <- #4".to_string(),
+        "sub r3 <- r2 - r3".to_string(),
+        format!("load r{register} <- [r3]"),
+    ]
+}
+
+/// The instructions of `jmp rX`.
+fn jump_to_register(register: u8) -> Vec<String> {
+    vec![
+        "loadimm r3 <- #1".to_string(),
+        format!("move r0 <- r{register} if r3 != 0"),
+    ]
+}
+
+/// Builds an object from the source lines.
+#[derive(Default)]
+struct Assembler {
+    object: Object,
+    /// Labels declared with `.global`, with the line of the declaration and
+    /// their offset in it.
+    globals: Vec<(String, usize, usize)>,
+    macros: HashMap<String, Macro>,
+    /// The macro being defined, with the line of its `.macro` directive and
+    /// the offset of its name in it.
+    definition: Option<(String, Macro, usize, usize)>,
+    /// Number of macro expansions so far, substituted for `\@`.
+    expansions: usize,
+    /// Number of `call`s to each label so far, to name return labels.
+    calls: HashMap<String, usize>,
+    /// Words of the source and of the library which look like return
+    /// labels, whose numbers `call` skips.
+    reserved: HashSet<String>,
+    /// Constants defined with `.equ`.
+    constants: HashMap<String, i64>,
+    /// Immediates to compute once the module is assembled.
+    fixups: Vec<Fixup>,
+    /// Offset in its source line of the macro or pseudo-instruction being
+    /// expanded.
+    invocation: usize,
+    /// Tests defined so far.
+    tests: Vec<TestDraft>,
+    /// The test being defined.
+    test: Option<TestDraft>,
+}
+
+/// The immediate of a `loadimm` instruction at `offset`, to be replaced by
+/// the value of `expression`.
+struct Fixup {
+    offset: u32,
+    expression: Expr,
+    /// Line of the instruction and offset of the immediate in it, for
+    /// errors.
+    line: usize,
+    start: usize,
+}
+
+/// A value of a test, computed once the module is assembled so that it can
+/// refer to labels defined anywhere.
+struct TestValue {
+    expression: Expr,
+    /// Line of the value and offset of its first token, for errors.
+    line: usize,
+    start: usize,
+}
+
+/// What a `.set` or `.expect` directive gives or checks.
+enum Place {
+    Register(usize),
+    Memory(TestValue),
+}
+
+/// A value given to `.set` or `.expect`: a word or the bytes of a string.
+enum TestData {
+    Word(TestValue),
+    Bytes(Vec<u8>),
+}
+
+/// A test as written in the source, between `.test` and `.endtest`.
+struct TestDraft {
+    name: String,
+    /// Line of the `.test` directive and offset of the name in it.
+    line: usize,
+    start: usize,
+    /// The label of the routine to call, with its line and offset.
+    routine: Option<(String, usize, usize)>,
+    given: Vec<(Place, Vec<TestData>)>,
+    expected: Vec<(Place, Vec<TestData>)>,
+    output: Option<Vec<u8>>,
+}
+
+impl Assembler {
+    fn line(&mut self, number: usize, line: &str) -> Result<(), Located> {
+        if self.test.is_some() {
+            let (tokens, starts) = lex(line)?;
+            let mut cursor = Cursor::new(&tokens, &starts, line.len());
+            return self
+                .test_directive(number, &mut cursor)
+                .map_err(|kind| Located {
+                    kind,
+                    offset: cursor.offset(),
+                });
+        }
+        let Some((_, definition, _, _)) = self.definition.as_mut() else {
+            return self.statement(number, line, 0);
+        };
+        // The body of a macro may not be valid until its parameters are
+        // substituted
+        let (tokens, starts) = lex(line).unwrap_or_default();
+        match tokens.first() {
+            Some(Token::Directive(directive)) if directive == "endm" => {
+                let (name, definition, _, _) = self.definition.take().unwrap();
+                self.macros.insert(name, definition);
+                match starts.get(1) {
+                    None => Ok(()),
+                    Some(&offset) => Err(Located {
+                        kind: AssemblyErrorKind::Expected("the end of the line"),
+                        offset,
+                    }),
+                }
+            }
+            Some(Token::Directive(directive)) if directive == "macro" => Err(Located {
+                kind: AssemblyErrorKind::NestedMacro,
+                offset: starts[0],
+            }),
+            _ => {
+                definition.body.push(line.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// Assemble a line of the source, or of the expansion of a macro or
+    /// a pseudo-instruction when `depth` is not 0.
+    fn statement(&mut self, number: usize, line: &str, depth: usize) -> Result<(), Located> {
+        let (tokens, starts) = lex(line)?;
+        let mut cursor = Cursor::new(&tokens, &starts, line.len());
+        self.tokens(number, line, depth, &mut cursor)
+            .map_err(|kind| Located {
+                kind,
+                offset: cursor.offset(),
+            })
+    }
+
+    /// Assemble the tokens of a line, errors pointing to the mark of the
+    /// cursor.
+    fn tokens(
+        &mut self,
+        number: usize,
+        line: &str,
+        depth: usize,
+        cursor: &mut Cursor,
+    ) -> Result<(), AssemblyErrorKind> {
+        let tokens = cursor.tokens;
+
+        // Labels
+        while let [Token::Word(name), Token::Punct(":"), ..] = &tokens[cursor.position..] {
+            if self.is_defined(name) {
+                cursor.mark = cursor.position;
+                return Err(AssemblyErrorKind::DuplicateLabel(name.clone()));
+            }
+            self.object.symbols.push(ObjectSymbol {
+                name: name.clone(),
+                offset: self.object.bytes.len() as u32,
+                global: false,
+            });
+            cursor.position += 2;
+        }
+
+        match cursor.next().cloned() {
+            None => (),
+            Some(Token::Directive(directive)) => match directive.as_str() {
+                "global" => loop {
+                    let name = cursor.word("a label")?;
+                    self.globals.push((name, number, cursor.offset()));
+                    if cursor.peek().is_none() {
+                        break;
+                    }
+                    cursor.punct(",", "`,`")?;
+                },
+                "equ" => {
+                    let name = cursor.word("a name")?;
+                    let mark = cursor.mark;
+                    cursor.punct(",", "`,`")?;
+                    let value = self.operand(cursor)?;
+                    if self.is_defined(&name) {
+                        cursor.mark = mark;
+                        return Err(AssemblyErrorKind::DuplicateLabel(name));
+                    }
+                    self.constants.insert(name, value);
+                }
+                "byte" | "word" | "ascii" | "asciz" | "space" | "align" | "org" => {
+                    self.data(&directive, cursor)?
+                }
+                "macro" if depth == 0 => return self.define(number, cursor),
+                "macro" => return Err(AssemblyErrorKind::NestedMacro),
+                "endm" => return Err(AssemblyErrorKind::UnmatchedEndm),
+                "test" if depth == 0 => {
+                    let name = cursor.word("a test name")?;
+                    self.test = Some(TestDraft {
+                        name,
+                        line: number,
+                        start: cursor.offset(),
+                        routine: None,
+                        given: Vec::new(),
+                        expected: Vec::new(),
+                        output: None,
+                    });
+                }
+                "test" => return Err(AssemblyErrorKind::NestedTest),
+                "endtest" => return Err(AssemblyErrorKind::UnmatchedEndtest),
+                "include" => {
+                    let mark = cursor.mark;
+                    let name = match cursor.next() {
+                        Some(Token::Bytes(name)) => String::from_utf8_lossy(name).into_owned(),
+                        _ => return Err(AssemblyErrorKind::Expected("a string")),
+                    };
+                    cursor.end()?;
+                    let Some((_, library)) = LIBRARY.iter().find(|(n, _)| *n == name) else {
+                        return Err(AssemblyErrorKind::UnknownInclude(name));
+                    };
+                    // The library is attributed to the `.include` directive,
+                    // errors included, and its own tests are left out
+                    cursor.mark = mark;
+                    let include = cursor.offset();
+                    let (tests, fixups, globals) =
+                        (self.tests.len(), self.fixups.len(), self.globals.len());
+                    let result = library
+                        .lines()
+                        .try_for_each(|line| self.line(number, line).map_err(|error| error.kind));
+                    self.tests.truncate(tests);
+                    for fixup in &mut self.fixups[fixups..] {
+                        fixup.start = include;
+                    }
+                    for (_, _, offset) in &mut self.globals[globals..] {
+                        *offset = include;
+                    }
+                    return result;
+                }
+                _ => return Err(AssemblyErrorKind::UnknownDirective(directive)),
+            },
+            Some(Token::Bytes(bytes)) => self.object.bytes.extend(bytes),
+            Some(Token::Punct("[")) => {
+                // A list of bytes
+                let mut bytes = Vec::new();
+                while !cursor.is_punct("]") {
+                    if !bytes.is_empty() {
+                        cursor.punct(",", "`,` or `]`")?;
+                    }
+                    let start = cursor.position;
+                    let value = self.operand(cursor)?;
+                    cursor.mark = start;
+                    bytes.push(
+                        u8::try_from(value)
+                            .map_err(|_| AssemblyErrorKind::ByteOutOfRange(value))?,
+                    );
+                }
+                cursor.next();
+                self.object.bytes.extend(bytes);
+            }
+            Some(Token::Word(name)) if self.macros.contains_key(&name) => {
+                let arguments = match cursor.starts.get(cursor.position) {
+                    Some(&start) => split_arguments(&line[start..]),
+                    None => Vec::new(),
+                };
+                if depth == 0 {
+                    self.invocation = cursor.offset();
+                }
+                return self.invoke(number, &name, &arguments, depth);
+            }
+            Some(Token::Word(mnemonic)) => {
+                let mark = cursor.mark;
+                if let Some(lines) = self.pseudo_instruction(&mnemonic, cursor)? {
+                    cursor.end()?;
+                    cursor.mark = mark;
+                    if depth == 0 {
+                        self.invocation = cursor.offset();
+                    }
+                    for line in lines {
+                        self.statement(number, &line, depth + 1)
+                            .map_err(|error| error.kind)?;
+                    }
+                    return Ok(());
+                }
+                let (bytes, expression) = instruction(&mnemonic, cursor)?;
+                cursor.end()?;
+                if let Some(expression) = expression {
+                    // The immediate follows the only `#` of the line
+                    let start = match depth {
+                        0 => {
+                            cursor.starts
+                                [tokens.iter().position(|t| *t == Token::Punct("#")).unwrap() + 1]
+                        }
+                        _ => self.invocation,
+                    };
+                    self.fixups.push(Fixup {
+                        offset: self.object.bytes.len() as u32 + 2,
+                        expression,
+                        line: number,
+                        start,
+                    });
+                }
+                self.object.bytes.extend(bytes);
+            }
+            Some(_) => return Err(AssemblyErrorKind::Expected("an instruction")),
+        }
+        cursor.end()
+    }
+
+    /// Tells whether `name` is already a label or a constant.
+    fn is_defined(&self, name: &str) -> bool {
+        self.constants.contains_key(name) || self.object.symbols.iter().any(|s| s.name == name)
+    }
+
+    /// What `name` designates at this point of the module.
+    fn lookup(&self, name: &str) -> Binding {
+        if let Some(&value) = self.constants.get(name) {
+            return Binding::Constant(value);
+        }
+        match self.object.symbols.iter().find(|s| s.name == name) {
+            Some(symbol) => Binding::Local(symbol.offset as i64),
+            None => Binding::External,
+        }
+    }
+
+    /// The value of an expression which must not depend on where the
+    /// module is placed.
+    fn constant(&self, expression: &Expr) -> Result<i64, AssemblyErrorKind> {
+        let value = expression.evaluate(&|name| self.lookup(name))?;
+        value.as_constant().ok_or(AssemblyErrorKind::NotConstant)
+    }
+
+    /// Parse a constant expression, errors pointing to its beginning.
+    fn operand(&self, cursor: &mut Cursor) -> Result<i64, AssemblyErrorKind> {
+        let start = cursor.position;
+        let expression = cursor.expression()?;
+        cursor.mark = start;
+        self.constant(&expression)
+    }
+
+    /// A known name close to the one of an error.
+    fn suggestion(&self, kind: &AssemblyErrorKind) -> Option<String> {
+        match kind {
+            AssemblyErrorKind::UnknownMnemonic(name) => suggest(
+                name,
+                MNEMONICS
+                    .into_iter()
+                    .chain(self.macros.keys().map(String::as_str)),
+            ),
+            AssemblyErrorKind::UnknownDirective(name) => {
+                suggest(name, DIRECTIVES.into_iter().chain(TEST_DIRECTIVES))
+                    .map(|directive| format!(".{directive}"))
+            }
+            AssemblyErrorKind::UnknownInclude(name) => {
+                suggest(name, LIBRARY.iter().map(|(name, _)| *name))
+            }
+            AssemblyErrorKind::UndefinedGlobal(name) | AssemblyErrorKind::UndefinedLabel(name) => {
+                suggest(name, self.object.symbols.iter().map(|s| s.name.as_str()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Set the immediate of a fixup, or leave it to the linker with a
+    /// relocation.
+    fn relocate(&mut self, fixup: &Fixup) -> Result<(), AssemblyErrorKind> {
+        let value = fixup.expression.evaluate(&|name| self.lookup(name))?;
+        let external: Vec<(&String, &i64)> = value.external.iter().collect();
+        let (symbol, negated, addend) = match (value.base, &external[..]) {
+            (0, []) => {
+                let immediate = i16::try_from(value.constant)
+                    .map_err(|_| AssemblyErrorKind::ImmediateOutOfRange(value.constant))?;
+                let offset = fixup.offset as usize;
+                self.object.bytes[offset..offset + 2].copy_from_slice(&immediate.to_le_bytes());
+                return Ok(());
+            }
+            // The offset of any local label gives the address of the module
+            (base @ (1 | -1), []) => {
+                let symbol = fixup
+                    .expression
+                    .names()
+                    .into_iter()
+                    .find_map(|name| self.object.symbols.iter().find(|s| s.name == name))
+                    .unwrap();
+                let addend = value.constant - base * symbol.offset as i64;
+                (symbol.name.clone(), base == -1, addend)
+            }
+            (0, [(name, coefficient @ (1 | -1))]) => {
+                (name.to_string(), **coefficient == -1, value.constant)
+            }
+            _ => return Err(AssemblyErrorKind::NotRelocatable),
+        };
+        let addend =
+            i32::try_from(addend).map_err(|_| AssemblyErrorKind::ImmediateOutOfRange(addend))?;
+        self.object.relocations.push(Relocation {
+            offset: fixup.offset,
+            symbol,
+            addend,
+            negated,
+        });
+        Ok(())
+    }
+
+    /// Assemble a data directive, after the directive itself.
+    fn data(&mut self, directive: &str, cursor: &mut Cursor) -> Result<(), AssemblyErrorKind> {
+        let size = |value: i64| match usize::try_from(value) {
+            Ok(size) if size <= MEMORY_SIZE => Ok(size),
+            _ => Err(AssemblyErrorKind::InvalidSize(value)),
+        };
+        // Values with the position of their first token, which errors about
+        // them point to
+        let mut values = Vec::new();
+        if directive != "ascii" && directive != "asciz" {
+            loop {
+                values.push((cursor.position, self.operand(cursor)?));
+                let limit = match directive {
+                    "byte" | "word" => usize::MAX,
+                    "space" => 2,
+                    _ => 1,
+                };
+                if values.len() == limit || cursor.peek().is_none() {
+                    break;
+                }
+                cursor.punct(",", "`,`")?;
+            }
+        }
+        let bytes = &mut self.object.bytes;
+        match directive {
+            "byte" => {
+                for &(position, value) in &values {
+                    cursor.mark = position;
+                    match value {
+                        -128..=255 => bytes.push(value as u8),
+                        _ => return Err(AssemblyErrorKind::ByteOutOfRange(value)),
+                    }
+                }
+            }
+            "word" => {
+                for &(position, value) in &values {
+                    cursor.mark = position;
+                    match value {
+                        -0x8000_0000..=0xffff_ffff => {
+                            bytes.extend_from_slice(&(value as u32).to_le_bytes())
+                        }
+                        _ => return Err(AssemblyErrorKind::WordOutOfRange(value)),
+                    }
+                }
+            }
+            "ascii" | "asciz" => loop {
+                match cursor.next() {
+                    Some(Token::Bytes(string)) => bytes.extend(string),
+                    _ => return Err(AssemblyErrorKind::Expected("a string")),
+                }
+                if directive == "asciz" {
+                    bytes.push(0);
+                }
+                if cursor.peek().is_none() {
+                    break;
+                }
+                cursor.punct(",", "`,`")?;
+            },
+            "space" => {
+                let (position, value) = values[0];
+                cursor.mark = position;
+                let length = size(value)?;
+                let fill = match values.get(1) {
+                    Some(&(position, value)) => {
+                        cursor.mark = position;
+                        u8::try_from(value).map_err(|_| AssemblyErrorKind::ByteOutOfRange(value))?
+                    }
+                    None => 0,
+                };
+                bytes.resize(bytes.len() + length, fill);
+            }
+            "align" => {
+                let (position, value) = values[0];
+                cursor.mark = position;
+                let alignment = size(value)?;
+                if alignment == 0 {
+                    return Err(AssemblyErrorKind::InvalidSize(value));
+                }
+                bytes.resize(bytes.len().next_multiple_of(alignment), 0);
+                self.object.alignment = self.object.alignment.max(alignment as u32);
+            }
+            "org" => {
+                let (position, value) = values[0];
+                cursor.mark = position;
+                let offset = size(value)?;
+                if offset < bytes.len() {
+                    return Err(AssemblyErrorKind::OrgBackwards(value));
+                }
+                bytes.resize(offset, 0);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Start the definition of a macro, after `.macro`.
+    fn define(&mut self, number: usize, cursor: &mut Cursor) -> Result<(), AssemblyErrorKind> {
+        let name = cursor.word("a macro name")?;
+        let mark = cursor.mark;
+        let offset = cursor.offset();
+        let mut parameters = Vec::new();
+        while cursor.peek().is_some() {
+            if !parameters.is_empty() {
+                cursor.punct(",", "`,`")?;
+            }
+            parameters.push(cursor.word("a parameter name")?);
+        }
+        let duplicate = self.macros.contains_key(&name);
+        let definition = Macro {
+            parameters,
+            body: Vec::new(),
+        };
+        self.definition = Some((name.clone(), definition, number, offset));
+        cursor.mark = mark;
+        match duplicate {
+            true => Err(AssemblyErrorKind::DuplicateMacro(name)),
+            false => Ok(()),
+        }
+    }
+
+    /// Read a line of the test being defined.
+    fn test_directive(
+        &mut self,
+        number: usize,
+        cursor: &mut Cursor,
+    ) -> Result<(), AssemblyErrorKind> {
+        let directive = match cursor.next().cloned() {
+            None => return Ok(()),
+            Some(Token::Directive(directive)) => directive,
+            Some(_) => return Err(AssemblyErrorKind::Expected("a test directive")),
+        };
+        let test = self.test.as_mut().unwrap();
+        match directive.as_str() {
+            "call" => {
+                let label = cursor.word("a label")?;
+                test.routine = Some((label, number, cursor.offset()));
+            }
+            "set" | "expect" => {
+                let (place, mut data) = if cursor.is_punct("[") {
+                    cursor.next();
+                    let address = test_value(number, cursor)?;
+                    cursor.punct("]", "`]`")?;
+                    (Place::Memory(address), Vec::new())
+                } else {
+                    let register = cursor.register()? as usize;
+                    cursor.punct(",", "`,`")?;
+                    let value = TestData::Word(test_value(number, cursor)?);
+                    (Place::Register(register), vec![value])
+                };
+                if let Place::Memory(_) = place {
+                    loop {
+                        cursor.punct(",", "`,`")?;
+                        data.push(match cursor.peek() {
+                            Some(Token::Bytes(bytes)) => {
+                                let bytes = bytes.clone();
+                                cursor.next();
+                                TestData::Bytes(bytes)
+                            }
+                            _ => TestData::Word(test_value(number, cursor)?),
+                        });
+                        if cursor.peek().is_none() {
+                            break;
+                        }
+                    }
+                }
+                match directive.as_str() {
+                    "set" => test.given.push((place, data)),
+                    _ => test.expected.push((place, data)),
+                }
+            }
+            "output" => loop {
+                match cursor.next() {
+                    Some(Token::Bytes(string)) => {
+                        test.output.get_or_insert_with(Vec::new).extend(string)
+                    }
+                    _ => return Err(AssemblyErrorKind::Expected("a string")),
+                }
+                if cursor.peek().is_none() {
+                    break;
+                }
+                cursor.punct(",", "`,`")?;
+            },
+            "endtest" => {
+                let test = self.test.take().unwrap();
+                self.tests.push(test);
+            }
+            "test" => return Err(AssemblyErrorKind::NestedTest),
+            _ => return Err(AssemblyErrorKind::UnknownDirective(directive)),
+        }
+        cursor.end()
+    }
+
+    /// The value of a test, the module being placed at address 0.
+    fn test_value(&self, value: &TestValue) -> Result<i64, AssemblyErrorKind> {
+        let result = value.expression.evaluate(&|name| self.lookup(name))?;
+        match result.external.keys().next() {
+            Some(name) => Err(AssemblyErrorKind::UndefinedLabel(name.clone())),
+            None => Ok(result.constant),
+        }
+    }
+
+    /// Compute the values of a test, once the module is assembled. Returns
+    /// the first error with its line and offset.
+    fn unit_test(&self, draft: &TestDraft) -> Result<UnitTest, (usize, usize, AssemblyErrorKind)> {
+        let at = |value: &TestValue| {
+            let (line, start) = (value.line, value.start);
+            move |kind| (line, start, kind)
+        };
+        let word = |value: &TestValue| match self.test_value(value).map_err(at(value))? {
+            word @ -0x8000_0000..=0xffff_ffff => Ok(word as u32),
+            word => Err(at(value)(AssemblyErrorKind::WordOutOfRange(word))),
+        };
+        let mut test = UnitTest {
+            name: draft.name.clone(),
+            line: draft.line,
+            routine: None,
+            registers: Vec::new(),
+            memory: Vec::new(),
+            expected_registers: Vec::new(),
+            expected_memory: Vec::new(),
+            expected_output: draft.output.clone(),
+        };
+        if let Some((label, line, start)) = &draft.routine {
+            if !self.object.symbols.iter().any(|s| s.name == *label) {
+                let kind = AssemblyErrorKind::UndefinedLabel(label.clone());
+                return Err((*line, *start, kind));
+            }
+            test.routine = Some(label.clone());
+        }
+        for (places, expected) in [(&draft.given, false), (&draft.expected, true)] {
+            for (place, data) in places {
+                let mut bytes = Vec::new();
+                for item in data {
+                    match item {
+                        TestData::Word(value) => bytes.extend(word(value)?.to_le_bytes()),
+                        TestData::Bytes(string) => bytes.extend(string),
+                    }
+                }
+                match place {
+                    Place::Register(register) => {
+                        let value = u32::from_le_bytes(bytes.try_into().unwrap());
+                        match expected {
+                            false => test.registers.push((*register, value)),
+                            true => test.expected_registers.push((*register, value)),
+                        }
+                    }
+                    Place::Memory(address) => {
+                        let start = self.test_value(address).map_err(at(address))?;
+                        let start = match usize::try_from(start) {
+                            Ok(a) if a + bytes.len() <= MEMORY_SIZE => a,
+                            _ => return Err(at(address)(AssemblyErrorKind::InvalidAddress(start))),
+                        };
+                        match expected {
+                            false => test.memory.push((start, bytes)),
+                            true => test.expected_memory.push((start, bytes)),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(test)
+    }
+
+    /// Assemble the body of a macro with its parameters substituted.
+    fn invoke(
+        &mut self,
+        number: usize,
+        name: &str,
+        arguments: &[&str],
+        depth: usize,
+    ) -> Result<(), AssemblyErrorKind> {
+        if depth == MAX_MACRO_DEPTH {
+            return Err(AssemblyErrorKind::MacroRecursion(name.to_string()));
+        }
+        let definition = &self.macros[name];
+        if arguments.len() != definition.parameters.len() {
+            return Err(AssemblyErrorKind::MacroArguments {
+                name: name.to_string(),
+                expected: definition.parameters.len(),
+                found: arguments.len(),
+            });
+        }
+        let lines: Vec<String> = definition
+            .body
+            .iter()
+            .map(|line| substitute(line, &definition.parameters, arguments, self.expansions))
+            .collect();
+        self.expansions += 1;
+        for line in lines {
+            self.statement(number, &line, depth + 1)
+                .map_err(|error| error.kind)?;
+        }
+        Ok(())
+    }
+
+    /// The instructions a pseudo-instruction expands to, or `None` if
+    /// `mnemonic` is not one. Like in the `.dis` listings, `r2` is the stack
+    /// pointer and `r3` is overwritten.
+    fn pseudo_instruction(
+        &mut self,
+        mnemonic: &str,
+        cursor: &mut Cursor,
+    ) -> Result<Option<Vec<String>>, AssemblyErrorKind> {
+        let not_scratch = |register: u8| match register {
+            SCRATCH => Err(AssemblyErrorKind::ScratchRegister(mnemonic.to_string())),
+            _ => Ok(register),
+        };
+        let arrow = |cursor: &mut Cursor| cursor.punct("<-", "`<-`");
+        let lines = match mnemonic {
+            "push" => {
+                let register = not_scratch(cursor.register()?)?;
+                vec![
+                    "loadimm r3 <- #4".to_string(),
+                    "sub r2 <- r2 - r3".to_string(),
+                    format!("store [r2] <- r{register}"),
+                ]
+            }
+            "pop" => pop(cursor.register()?),
+            "ret" => pop(0),
+            "call" => {
+                let target = cursor.word("a label")?;
+                let count = self.calls.entry(target.clone()).or_insert(0);
+                let label = loop {
+                    *count += 1;
+                    let label = format!("return_from_{target}_{count}");
+                    if !self.reserved.contains(&label) {
+                        break label;
+                    }
+                };
+                vec![
+                    "loadimm r3 <- #4".to_string(),
+                    "sub r2 <- r2 - r3".to_string(),
+                    format!("loadimm r3 <- #{label}"),
+                    "store [r2] <- r3".to_string(),
+                    format!("loadimm r0 <- #{target}"),
+                    format!("{label}:"),
+                ]
+            }
+            "jmp" if cursor.peek_register() => jump_to_register(not_scratch(cursor.register()?)?),
+            "jmp" => vec![format!("loadimm r0 <- #{}", cursor.expression()?)],
+            "mov" => {
+                let a = cursor.register()?;
+                arrow(cursor)?;
+                if cursor.is_punct("#") {
+                    vec![format!(
+                        "loadimm r{a} <- #{}",
+                        immediate_text(cursor.immediate()?)
+                    )]
+                } else {
+                    match (a, cursor.register()?) {
+                        (a, b) if a == b => Vec::new(),
+                        (0, b) => jump_to_register(not_scratch(b)?),
+                        (a, b) => vec![
+                            format!("loadimm r{a} <- #0"),
+                            format!("sub r{a} <- r{b} - r{a}"),
+                        ],
+                    }
+                }
+            }
+            "add" => {
+                let a = cursor.register()?;
+                arrow(cursor)?;
+                let b = not_scratch(cursor.register()?)?;
+                cursor.punct("+", "`+`")?;
+                if cursor.is_punct("#") {
+                    // The opposite of the value is subtracted
+                    let value = immediate_text(cursor.signed_immediate(true)?);
+                    vec![
+                        format!("loadimm r3 <- #{value}"),
+                        format!("sub r{a} <- r{b} - r3"),
+                    ]
+                } else {
+                    let c = not_scratch(cursor.register()?)?;
+                    vec![
+                        "loadimm r3 <- #0".to_string(),
+                        format!("sub r3 <- r3 - r{c}"),
+                        format!("sub r{a} <- r{b} - r3"),
+                    ]
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(lines))
+    }
+}
+
+/// Parse a value of a test, computed once the module is assembled.
+fn test_value(number: usize, cursor: &mut Cursor) -> Result<TestValue, AssemblyErrorKind> {
+    let start = cursor
+        .starts
+        .get(cursor.position)
+        .copied()
+        .unwrap_or(cursor.end);
+    Ok(TestValue {
+        expression: cursor.expression()?,
+        line: number,
+        start,
+    })
+}
+
+/// Assemble a module written in the syntax of the `.dis` listings, without
+/// the address column:
+///   - instructions such as `loadimm r3 <- #4` or `move r0 <- r5 if r4 != 0`,
+///     plus `exit r1` (exit with the code in `r1`) and `brk`,
+///   - labels such as `loop:`, alone or before an instruction,
+///   - data as a string such as `b'Hello\n'` or a list such as `[0, 1]`,
+///   - the data directives `.byte` and `.word` followed by values,
+///     `.ascii` and `.asciz` (which adds a nul byte) followed by strings
+///     such as `"Hello\n"`, `.space size[, byte]`, `.align alignment` and
+///     `.org offset`, offsets being counted from the start of the module,
+///   - characters such as `'A'` wherever a number is expected, as in
+///     `loadimm r1 <- #'A'`,
+///   - expressions with `+`, `-`, `*`, `/` and parentheses wherever a
+///     value is expected, such as `#label+4` or `#(SIZE*4)`,
+///   - `.equ NAME, value` to define a constant,
+///   - the register aliases `ip` for `r0` and `sp` for `r2`,
+///   - `.global name, ...` to let other modules refer to labels,
+///   - `.include "name"` to insert a source of [LIBRARY], such as
+///     `std.s`, the standard library whose routines and calling convention
+///     are described in `stdlib/std.s`,
+///   - comments starting with `;`.
+///
+/// The pseudo-instructions below expand to several instructions, using
+/// `r2` as the stack pointer and overwriting `r3` like in the listings:
+///   - `push rX` and `pop rX`,
+///   - `call label`, which pushes the return address and jumps to `label`,
+///     and `ret`, which pops it into the IP. The return address is labelled
+///     `return_from_label_N`, N counting the calls to `label` and skipping
+///     the labels of this form written in the source,
+///   - `jmp value` and `jmp rX`,
+///   - `mov rA <- rB` and `mov rA <- #value`,
+///   - `add rA <- rB + rC` and `add rA <- rB + #value`.
+///
+/// Macros are defined between `.macro name param, ...` and `.endm`, and
+/// invoked as `name argument, ...`. In their body, `\param` is replaced
+/// by the argument and `\@` by a number unique to the expansion, to make
+/// labels such as `loop\@:` local to it.
+///
+/// Tests may be written between `.test name` and `.endtest`, as described
+/// in [assemble_with_tests]. They are checked but left out of the object.
+///
+/// Data and sizes must be constants, computed from numbers and constants
+/// or labels defined above. Immediates may also be the address of a label
+/// plus or minus a constant, such as `#label+4`, or its opposite, such as
+/// `#-label`, which are left to [link](crate::link) since it places the
+/// module in memory. They must fit in the 16 signed bits of `loadimm`.
+/// A label defined in no module, possibly misspelled, is thus reported by
+/// [link](crate::link), along with the line recorded for the immediate.
+///
+/// The object records the line of each instruction and piece of data, its
+/// `source` file name being left empty. Every line with an error is
+/// reported, by line and column, with the closest known name when a
+/// mnemonic, a directive or a label of a constant expression seems
+/// misspelled.
+pub fn assemble(source: &str) -> Result<Object, Vec<AssemblyError>> {
+    assemble_with_tests(source).map(|(object, _)| object)
+}
+
+/// Assemble a module like [assemble], along with the tests written in it
+/// to be run by [run_test](crate::run_test). A test starts with
+/// `.test name` and ends with `.endtest`, with these directives between
+/// them:
+///   - `.call label` to call the routine at `label` like `call` does and
+///     stop when it returns, instead of running the module from its
+///     beginning,
+///   - `.set rX, value` and `.set [address], value, ...` to set a register
+///     or the memory before running, values being words and strings their
+///     bytes, as in `.set [buffer], "abc", 0`,
+///   - `.expect rX, value` and `.expect [address], value, ...` to check a
+///     register or the memory afterwards,
+///   - `.output "text", ...` to check everything printed.
+///
+/// Values are expressions which may use the labels of the module, defined
+/// anywhere, the module being placed at address 0.
+pub fn assemble_with_tests(source: &str) -> Result<(Object, Vec<UnitTest>), Vec<AssemblyError>> {
+    let lines: Vec<&str> = source.lines().collect();
+    let error = |line: usize, offset: usize, kind| AssemblyError {
+        line,
+        column: lines[line - 1]
+            .get(..offset)
+            .map_or(1, |before| before.chars().count() + 1),
+        kind,
+        suggestion: None,
+    };
+    let mut assembler = Assembler::default();
+    let sources = std::iter::once(source).chain(LIBRARY.iter().map(|(_, library)| *library));
+    assembler.reserved = sources
+        .flat_map(|text| text.split(|c: char| !c.is_ascii_alphanumeric() && c != '_'))
+        .filter(|word| word.starts_with("return_from_"))
+        .map(str::to_string)
+        .collect();
+    let mut errors = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let offset = assembler.object.bytes.len() as u32;
+        match assembler.line(i + 1, line) {
+            Ok(()) if assembler.object.bytes.len() as u32 > offset => {
+                assembler.object.lines.push(LineEntry {
+                    offset,
+                    line: i as u32 + 1,
+                })
+            }
+            Ok(()) => (),
+            Err(Located { kind, offset }) => errors.push(error(i + 1, offset, kind)),
+        }
+    }
+    for fixup in std::mem::take(&mut assembler.fixups) {
+        if let Err(kind) = assembler.relocate(&fixup) {
+            errors.push(error(fixup.line, fixup.start, kind));
+        }
+    }
+    if let Some((name, _, line, offset)) = assembler.definition.take() {
+        errors.push(error(
+            line,
+            offset,
+            AssemblyErrorKind::UnterminatedMacro(name),
+        ));
+    }
+    for (name, line, offset) in &assembler.globals {
+        match assembler
+            .object
+            .symbols
+            .iter_mut()
+            .find(|s| s.name == *name)
+        {
+            Some(symbol) => symbol.global = true,
+            None => errors.push(error(
+                *line,
+                *offset,
+                AssemblyErrorKind::UndefinedGlobal(name.clone()),
+            )),
+        }
+    }
+    if let Some(test) = assembler.test.take() {
+        errors.push(error(
+            test.line,
+            test.start,
+            AssemblyErrorKind::UnterminatedTest(test.name),
+        ));
+    }
+    let mut tests = Vec::new();
+    for draft in &assembler.tests {
+        match assembler.unit_test(draft) {
+            Ok(test) => tests.push(test),
+            Err((line, offset, kind)) => errors.push(error(line, offset, kind)),
+        }
+    }
+    if errors.is_empty() {
+        Ok((assembler.object, tests))
+    } else {
+        for error in &mut errors {
+            error.suggestion = assembler.suggestion(&error.kind);
+        }
+        errors.sort_by_key(|e| (e.line, e.column));
+        Err(errors)
+    }
+}
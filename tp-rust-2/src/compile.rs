@@ -0,0 +1,1214 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fmt::Write;
+
+use crate::assemble::{lex_quoted, report, AssemblyErrorKind};
+use crate::machine::MEMORY_SIZE;
+use crate::suggest::suggest;
+
+/// A problem found by [compile] in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    /// Line number, starting from 1.
+    pub line: usize,
+    /// Column of the offending token, in characters from 1.
+    pub column: usize,
+    pub kind: CompileErrorKind,
+    /// A known name close to a misspelled variable or function.
+    pub suggestion: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    /// A backslash in a string is not followed by a known escape.
+    InvalidEscape,
+    /// A number is malformed or does not fit in 32 signed bits.
+    InvalidNumber(String),
+    /// The source does not follow the syntax of the language. The expected
+    /// token is described.
+    Expected(&'static str),
+    UndefinedVariable(String),
+    UndefinedFunction(String),
+    /// A variable or a parameter is declared twice in a function.
+    DuplicateVariable(String),
+    DuplicateFunction(String),
+    /// A function is called with the wrong number of arguments.
+    Arguments {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// There is no `main` function to run.
+    MissingMain,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            CompileErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {c:?}"),
+            CompileErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            CompileErrorKind::InvalidEscape => write!(f, "invalid escape sequence"),
+            CompileErrorKind::InvalidNumber(number) => write!(f, "invalid number `{number}`"),
+            CompileErrorKind::Expected(what) => write!(f, "expected {what}"),
+            CompileErrorKind::UndefinedVariable(name) => {
+                write!(f, "variable `{name}` is not defined")
+            }
+            CompileErrorKind::UndefinedFunction(name) => {
+                write!(f, "function `{name}` is not defined")
+            }
+            CompileErrorKind::DuplicateVariable(name) => {
+                write!(f, "variable `{name}` is already defined")
+            }
+            CompileErrorKind::DuplicateFunction(name) => {
+                write!(f, "function `{name}` is already defined")
+            }
+            CompileErrorKind::Arguments {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "function `{name}` takes {expected} arguments but {found} were given"
+            ),
+            CompileErrorKind::MissingMain => write!(f, "there is no `main` function"),
+        }?;
+        match &self.suggestion {
+            Some(suggestion) => write!(f, ", did you mean `{suggestion}`?"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl CompileError {
+    fn new(line: usize, column: usize, kind: CompileErrorKind) -> Self {
+        CompileError {
+            line,
+            column,
+            kind,
+            suggestion: None,
+        }
+    }
+
+    /// Describe the error for humans: `file:line:column` and the message,
+    /// then the source line with a caret under the column.
+    pub fn report(&self, file: &str, source: &str) -> String {
+        report(file, source, self.line, self.column, self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// Names and keywords.
+    Word(String),
+    Number(i64),
+    String(Vec<u8>),
+    Punct(&'static str),
+}
+
+const PUNCTS: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||", "(", ")", "{", "}", ",", ";", "=", "+", "-", "*", "/", "%",
+    "<", ">", "!",
+];
+
+const KEYWORDS: [&str; 7] = ["fn", "var", "if", "else", "while", "return", "print"];
+
+/// A token with its line and column.
+type Located = (Token, usize, usize);
+
+/// Split the source into tokens, up to optional `//` comments.
+fn lex(source: &str) -> Result<Vec<Located>, CompileError> {
+    let mut tokens = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let mut rest = line;
+        loop {
+            rest = rest.trim_start();
+            let Some(c) = rest.chars().next() else {
+                break;
+            };
+            if rest.starts_with("//") {
+                break;
+            }
+            let column = line[..line.len() - rest.len()].chars().count() + 1;
+            let error = |kind| CompileError::new(i + 1, column, kind);
+            let (token, length) = if c.is_ascii_alphabetic() || c == '_' {
+                let length = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                (Token::Word(rest[..length].to_string()), length)
+            } else if c.is_ascii_digit() {
+                let length = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric())
+                    .unwrap_or(rest.len());
+                let text = &rest[..length];
+                match text.parse::<i32>() {
+                    Ok(n) => (Token::Number(n as i64), length),
+                    Err(_) => return Err(error(CompileErrorKind::InvalidNumber(text.to_string()))),
+                }
+            } else if c == '"' {
+                match lex_quoted(rest) {
+                    Ok((bytes, length)) => (Token::String(bytes), length),
+                    Err(AssemblyErrorKind::InvalidEscape) => {
+                        return Err(error(CompileErrorKind::InvalidEscape))
+                    }
+                    Err(_) => return Err(error(CompileErrorKind::UnterminatedString)),
+                }
+            } else if let Some(punct) = PUNCTS.iter().find(|p| rest.starts_with(**p)) {
+                (Token::Punct(punct), punct.len())
+            } else {
+                return Err(error(CompileErrorKind::UnexpectedCharacter(c)));
+            };
+            tokens.push((token, i + 1, column));
+            rest = &rest[length..];
+        }
+    }
+    Ok(tokens)
+}
+
+/// A variable or a function, where it appears in the source.
+#[derive(Debug, Clone)]
+struct Name {
+    name: String,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+    Number(i64),
+    Variable(Name),
+    Call(Name, Vec<Expression>),
+    /// `-` or `!` applied to an expression.
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    String(Vec<u8>),
+    Expression(Expression),
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    /// Line where the statement starts.
+    line: usize,
+    kind: StatementKind,
+}
+
+#[derive(Debug, Clone)]
+enum StatementKind {
+    Var(Name, Expression),
+    Assign(Name, Expression),
+    If(Expression, Vec<Statement>, Vec<Statement>),
+    While(Expression, Vec<Statement>),
+    Return(Option<Expression>),
+    Print(Vec<Item>),
+    Expression(Expression),
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    name: Name,
+    parameters: Vec<Name>,
+    body: Vec<Statement>,
+}
+
+/// Binary operators from the loosest to the tightest.
+const PRECEDENCE: [&[&str]; 5] = [
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<", "<=", ">", ">="],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Reads the tokens of the source in order.
+struct Parser {
+    tokens: Vec<Located>,
+    position: usize,
+    /// Line and column of the end of the source.
+    end: (usize, usize),
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.position += 1;
+        self.tokens
+            .get(self.position - 1)
+            .map(|(token, _, _)| token.clone())
+    }
+
+    /// Line and column of the next token.
+    fn here(&self) -> (usize, usize) {
+        match self.tokens.get(self.position) {
+            Some(&(_, line, column)) => (line, column),
+            None => self.end,
+        }
+    }
+
+    /// An error about the next token.
+    fn expected(&self, what: &'static str) -> CompileError {
+        let (line, column) = self.here();
+        CompileError::new(line, column, CompileErrorKind::Expected(what))
+    }
+
+    fn is(&self, punct_or_keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Punct(p)) => *p == punct_or_keyword,
+            Some(Token::Word(w)) => w == punct_or_keyword,
+            _ => false,
+        }
+    }
+
+    /// Skip the given punctuation or keyword, described as `what` in errors.
+    fn expect(&mut self, punct_or_keyword: &str, what: &'static str) -> Result<(), CompileError> {
+        if !self.is(punct_or_keyword) {
+            return Err(self.expected(what));
+        }
+        self.next();
+        Ok(())
+    }
+
+    fn name(&mut self, what: &'static str) -> Result<Name, CompileError> {
+        let (line, column) = self.here();
+        match self.peek() {
+            Some(Token::Word(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.next();
+                Ok(Name { name, line, column })
+            }
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, CompileError> {
+        self.expect("fn", "`fn`")?;
+        let name = self.name("a function name")?;
+        self.expect("(", "`(`")?;
+        let mut parameters = Vec::new();
+        while !self.is(")") {
+            if !parameters.is_empty() {
+                self.expect(",", "`,` or `)`")?;
+            }
+            parameters.push(self.name("a parameter name")?);
+        }
+        self.next();
+        Ok(Function {
+            name,
+            parameters,
+            body: self.block()?,
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, CompileError> {
+        self.expect("{", "`{`")?;
+        let mut statements = Vec::new();
+        while !self.is("}") {
+            if self.peek().is_none() {
+                return Err(self.expected("`}`"));
+            }
+            statements.push(self.statement()?);
+        }
+        self.next();
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, CompileError> {
+        let (line, _) = self.here();
+        let keyword = match self.peek() {
+            Some(Token::Word(word)) => word.clone(),
+            _ => String::new(),
+        };
+        let kind = match keyword.as_str() {
+            "var" => {
+                self.next();
+                let name = self.name("a variable name")?;
+                self.expect("=", "`=`")?;
+                StatementKind::Var(name, self.expression()?)
+            }
+            "if" => return self.if_statement(),
+            "while" => {
+                self.next();
+                let condition = self.expression()?;
+                return Ok(Statement {
+                    line,
+                    kind: StatementKind::While(condition, self.block()?),
+                });
+            }
+            "return" => {
+                self.next();
+                match self.is(";") {
+                    true => StatementKind::Return(None),
+                    false => StatementKind::Return(Some(self.expression()?)),
+                }
+            }
+            "print" => {
+                self.next();
+                let mut items = Vec::new();
+                loop {
+                    match self.peek() {
+                        Some(Token::String(bytes)) => {
+                            items.push(Item::String(bytes.clone()));
+                            self.next();
+                        }
+                        _ => items.push(Item::Expression(self.expression()?)),
+                    }
+                    if !self.is(",") {
+                        break;
+                    }
+                    self.next();
+                }
+                StatementKind::Print(items)
+            }
+            _ => {
+                let expression = self.expression()?;
+                match expression {
+                    Expression::Variable(name) if self.is("=") => {
+                        self.next();
+                        StatementKind::Assign(name, self.expression()?)
+                    }
+                    expression => StatementKind::Expression(expression),
+                }
+            }
+        };
+        self.expect(";", "`;`")?;
+        Ok(Statement { line, kind })
+    }
+
+    fn if_statement(&mut self) -> Result<Statement, CompileError> {
+        let (line, _) = self.here();
+        self.next();
+        let condition = self.expression()?;
+        let then = self.block()?;
+        let otherwise = match self.is("else") {
+            true => {
+                self.next();
+                match self.is("if") {
+                    true => vec![self.if_statement()?],
+                    false => self.block()?,
+                }
+            }
+            false => Vec::new(),
+        };
+        Ok(Statement {
+            line,
+            kind: StatementKind::If(condition, then, otherwise),
+        })
+    }
+
+    fn expression(&mut self) -> Result<Expression, CompileError> {
+        self.binary(0)
+    }
+
+    /// An expression with binary operators of the given precedence level
+    /// or tighter, which associate to the left.
+    fn binary(&mut self, level: usize) -> Result<Expression, CompileError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut expression = self.binary(level + 1)?;
+        while let Some(&Token::Punct(op)) = self.peek() {
+            if !PRECEDENCE[level].contains(&op) {
+                break;
+            }
+            self.next();
+            let right = self.binary(level + 1)?;
+            expression = Expression::Binary(op, Box::new(expression), Box::new(right));
+        }
+        Ok(expression)
+    }
+
+    fn unary(&mut self) -> Result<Expression, CompileError> {
+        match self.peek() {
+            Some(&Token::Punct(op @ ("-" | "!"))) => {
+                self.next();
+                Ok(Expression::Unary(op, Box::new(self.unary()?)))
+            }
+            Some(&Token::Number(n)) => {
+                self.next();
+                Ok(Expression::Number(n))
+            }
+            Some(Token::Punct("(")) => {
+                self.next();
+                let expression = self.expression()?;
+                self.expect(")", "`)`")?;
+                Ok(expression)
+            }
+            _ => {
+                let name = self.name("an expression")?;
+                if !self.is("(") {
+                    return Ok(Expression::Variable(name));
+                }
+                self.next();
+                let mut arguments = Vec::new();
+                while !self.is(")") {
+                    if !arguments.is_empty() {
+                        self.expect(",", "`,` or `)`")?;
+                    }
+                    arguments.push(self.expression()?);
+                }
+                self.next();
+                Ok(Expression::Call(name, arguments))
+            }
+        }
+    }
+}
+
+/// Register holding the value of expressions, the arguments of runtime
+/// routines and the result of functions.
+const ACCUMULATOR: &str = "r4";
+/// Register holding the frame pointer.
+const FRAME: &str = "r12";
+
+/// Routines of the runtime, emitted after the functions when used.
+const RUNTIME: [(&str, &str); 6] = [
+    (
+        "__print",
+        "; __print: write the r11 bytes starting at address r10
+__print:
+    loadimm r8 <- #__print_next
+    move r0 <- r8 if r11 != 0
+    ret
+__print_next:
+    load r3 <- [r10]
+    out r3
+    add r10 <- r10 + #1
+    add r11 <- r11 + #-1
+    jmp __print
+",
+    ),
+    (
+        "__negative",
+        "; __negative: r4 = 1 if r4 is negative, 0 otherwise. r4 is stored below
+; the stack, followed by a zero word, so that its top byte can be loaded
+; alone. It indexes a table whose words are not zero from 128.
+__negative:
+    loadimm r3 <- #8
+    sub r6 <- r2 - r3
+    store [r6] <- r4
+    loadimm r4 <- #0
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    store [r3] <- r4
+    loadimm r3 <- #-3
+    sub r6 <- r6 - r3
+    load r6 <- [r6]
+    sub r6 <- r4 - r6
+    loadimm r3 <- #__sign_table
+    sub r3 <- r3 - r6
+    load r3 <- [r3]
+    loadimm r6 <- #1
+    move r4 <- r6 if r3 != 0
+    ret
+",
+    ),
+    (
+        "__less",
+        "; __less: r4 = 1 if r4 < r5, 0 otherwise. When the signs differ, r4 is
+; less if it is negative. Otherwise, r4 - r5 cannot overflow and is
+; checked instead. __greater swaps r4 and r5 first, for r4 > r5.
+__greater:
+    mov r6 <- r4
+    mov r4 <- r5
+    mov r5 <- r6
+__less:
+    mov r7 <- r4
+    call __negative
+    mov r8 <- r4
+    mov r4 <- r5
+    call __negative
+    sub r9 <- r8 - r4
+    mov r4 <- r8
+    loadimm r6 <- #__less_end
+    move r0 <- r6 if r9 != 0
+    sub r4 <- r7 - r5
+    call __negative
+__less_end:
+    ret
+",
+    ),
+    (
+        "__multiply",
+        "; __multiply: r4 = r4 * r5, adding the larger absolute value as many
+; times as the smaller one. r9 is 1 when the result is negative.
+__multiply:
+    mov r7 <- r4
+    mov r8 <- r5
+    call __negative
+    mov r9 <- r4
+    loadimm r6 <- #0
+    sub r6 <- r6 - r7
+    move r7 <- r6 if r4 != 0
+    mov r4 <- r8
+    call __negative
+    loadimm r6 <- #1
+    sub r6 <- r6 - r9
+    move r9 <- r6 if r4 != 0
+    loadimm r6 <- #0
+    sub r6 <- r6 - r8
+    move r8 <- r6 if r4 != 0
+    sub r4 <- r7 - r8
+    call __negative
+    mov r6 <- r7
+    move r7 <- r8 if r4 != 0
+    move r8 <- r6 if r4 != 0
+    loadimm r4 <- #0
+__multiply_loop:
+    loadimm r6 <- #__multiply_next
+    move r0 <- r6 if r8 != 0
+    loadimm r6 <- #0
+    sub r6 <- r6 - r4
+    move r4 <- r6 if r9 != 0
+    ret
+__multiply_next:
+    add r4 <- r4 + r7
+    add r8 <- r8 + #-1
+    jmp __multiply_loop
+",
+    ),
+    (
+        "__divide",
+        "; __divide: r4 = r4 / r5 rounded towards 0 and r5 = r4 % r5, which has
+; the sign of r4, by long division of the absolute values. The multiples
+; of the divisor by powers of 2 are pushed until the next one would exceed
+; the dividend, then subtracted from the largest one while the quotient is
+; doubled. r9 is 1 when the quotient is negative, r10 when the remainder
+; is. The absolute value of -2147483648 is itself: as a divisor, the
+; quotient is 1 for the same dividend and 0 otherwise, and as a dividend,
+; r13 is 1 and 2147483647 is divided instead, the remainder being fixed at
+; the end. -2147483648 / -1 wraps around to -2147483648. Division by 0
+; stops on a breakpoint, then exits with -1.
+__divide:
+    loadimm r6 <- #__divide_start
+    move r0 <- r6 if r5 != 0
+    brk
+    loadimm r4 <- #-1
+    exit r4
+__divide_start:
+    mov r7 <- r4
+    mov r8 <- r5
+    call __negative
+    mov r9 <- r4
+    mov r10 <- r4
+    loadimm r6 <- #0
+    sub r6 <- r6 - r7
+    move r7 <- r6 if r4 != 0
+    mov r4 <- r8
+    call __negative
+    loadimm r6 <- #1
+    sub r6 <- r6 - r9
+    move r9 <- r6 if r4 != 0
+    loadimm r6 <- #0
+    sub r6 <- r6 - r8
+    move r8 <- r6 if r4 != 0
+    loadimm r11 <- #0
+    mov r4 <- r8
+    call __negative
+    loadimm r6 <- #__divide_min
+    move r0 <- r6 if r4 != 0
+    mov r4 <- r7
+    call __negative
+    mov r13 <- r4
+    sub r7 <- r7 - r13
+    ; The multiples are pushed above a 0
+    push r11
+    mov r5 <- r8
+__divide_double:
+    push r5
+    sub r4 <- r7 - r5
+    call __negative
+    loadimm r6 <- #__divide_loop
+    move r0 <- r6 if r4 != 0
+    sub r4 <- r7 - r5
+    sub r4 <- r4 - r5
+    call __negative
+    loadimm r6 <- #__divide_loop
+    move r0 <- r6 if r4 != 0
+    add r5 <- r5 + r5
+    jmp __divide_double
+__divide_loop:
+    pop r5
+    loadimm r6 <- #__divide_next
+    move r0 <- r6 if r5 != 0
+    ; Add back what was left out of -2147483648
+    add r7 <- r7 + r13
+    sub r4 <- r7 - r8
+    loadimm r6 <- #__divide_end
+    move r0 <- r6 if r4 != 0
+    mov r7 <- r4
+    add r11 <- r11 + #1
+    jmp __divide_end
+__divide_next:
+    add r11 <- r11 + r11
+    sub r4 <- r7 - r5
+    call __negative
+    loadimm r6 <- #__divide_loop
+    move r0 <- r6 if r4 != 0
+    sub r7 <- r7 - r5
+    add r11 <- r11 + #1
+    jmp __divide_loop
+__divide_min:
+    mov r4 <- r7
+    call __negative
+    mov r11 <- r4
+    loadimm r6 <- #0
+    move r7 <- r6 if r4 != 0
+__divide_end:
+    mov r4 <- r11
+    loadimm r6 <- #0
+    sub r6 <- r6 - r4
+    move r4 <- r6 if r9 != 0
+    mov r5 <- r7
+    loadimm r6 <- #0
+    sub r6 <- r6 - r5
+    move r5 <- r6 if r10 != 0
+    ret
+",
+    ),
+    (
+        "__check_stack",
+        "; __check_stack: stop on a breakpoint, then exit with -1, when the stack
+; pointer is less than 256 bytes above the end of the program. These bytes
+; are left for the values of expressions and the runtime routines.
+__check_stack:
+    loadimm r3 <- #__end + 256
+    sub r4 <- r2 - r3
+    call __negative
+    loadimm r6 <- #__stack_overflow
+    move r0 <- r6 if r4 != 0
+    ret
+__stack_overflow:
+    brk
+    loadimm r4 <- #-1
+    exit r4
+",
+    ),
+];
+
+/// Write `bytes` as a string of the assembler.
+fn quote(bytes: &[u8]) -> String {
+    let mut string = "\"".to_string();
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => {
+                string.push('\\');
+                string.push(b as char);
+            }
+            b' '..=b'~' => string.push(b as char),
+            b'\n' => string.push_str("\\n"),
+            _ => write!(string, "\\x{b:02x}").unwrap(),
+        }
+    }
+    string.push('"');
+    string
+}
+
+/// The number of `var` statements in a list of statements, at any depth.
+fn count_variables(statements: &[Statement]) -> usize {
+    statements
+        .iter()
+        .map(|statement| match &statement.kind {
+            StatementKind::Var(..) => 1,
+            StatementKind::If(_, then, otherwise) => {
+                count_variables(then) + count_variables(otherwise)
+            }
+            StatementKind::While(_, body) => count_variables(body),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Writes the assembly source of the program.
+#[derive(Default)]
+struct Generator<'a> {
+    source: Vec<&'a str>,
+    /// Number of parameters of each function.
+    functions: HashMap<String, usize>,
+    code: String,
+    /// Number of labels made so far, to make them unique.
+    labels: usize,
+    strings: Vec<Vec<u8>>,
+    /// Numbers which do not fit in the immediate of `loadimm`.
+    constants: Vec<i64>,
+    runtime: BTreeSet<&'static str>,
+    errors: Vec<CompileError>,
+    /// Offset of the variables of the current function from the frame
+    /// pointer.
+    variables: HashMap<String, i64>,
+    /// Number of local variables of the current function declared so far.
+    locals: usize,
+    /// Label of the end of the current function.
+    return_label: String,
+    /// Last line of the source shown as a comment.
+    commented: usize,
+}
+
+impl Generator<'_> {
+    fn emit(&mut self, instruction: &str) {
+        writeln!(self.code, "    {instruction}").unwrap();
+    }
+
+    fn label(&mut self, label: &str) {
+        writeln!(self.code, "{label}:").unwrap();
+    }
+
+    fn new_label(&mut self, kind: &str) -> String {
+        self.labels += 1;
+        format!("__{kind}_{}", self.labels)
+    }
+
+    fn call(&mut self, routine: &'static str) {
+        // `__greater` is an entry of `__less`
+        self.runtime.insert(match routine {
+            "__greater" => "__less",
+            _ => routine,
+        });
+        if routine != "__print" {
+            self.runtime.insert("__negative");
+        }
+        self.emit(&format!("call {routine}"));
+    }
+
+    /// Jump to `label` if the accumulator is 0.
+    fn jump_if_zero(&mut self, label: &str) {
+        let next = self.new_label("next");
+        self.emit(&format!("loadimm r3 <- #{next}"));
+        self.emit(&format!("move r0 <- r3 if {ACCUMULATOR} != 0"));
+        self.emit(&format!("jmp {label}"));
+        self.label(&next);
+    }
+
+    /// Set the accumulator to 1 if it is not 0.
+    fn normalize(&mut self) {
+        self.emit("loadimm r3 <- #1");
+        self.emit(&format!("move {ACCUMULATOR} <- r3 if {ACCUMULATOR} != 0"));
+    }
+
+    /// Put the address of a variable in r3, or report it undefined.
+    fn address(&mut self, name: &Name) -> bool {
+        let Some(&offset) = self.variables.get(&name.name) else {
+            let mut error = CompileError::new(
+                name.line,
+                name.column,
+                CompileErrorKind::UndefinedVariable(name.name.clone()),
+            );
+            error.suggestion = suggest(&name.name, self.variables.keys().map(String::as_str));
+            self.errors.push(error);
+            return false;
+        };
+        self.emit(&format!("loadimm r3 <- #{}", -offset));
+        self.emit(&format!("sub r3 <- {FRAME} - r3"));
+        true
+    }
+
+    fn function(&mut self, function: &Function) {
+        let parameters = function.parameters.len() as i64;
+        self.variables.clear();
+        for (i, parameter) in function.parameters.iter().enumerate() {
+            // Above the saved frame pointer and the return address, the last
+            // argument being pushed last
+            let offset = 8 + 4 * (parameters - 1 - i as i64);
+            if self
+                .variables
+                .insert(parameter.name.clone(), offset)
+                .is_some()
+            {
+                self.errors.push(CompileError::new(
+                    parameter.line,
+                    parameter.column,
+                    CompileErrorKind::DuplicateVariable(parameter.name.clone()),
+                ));
+            }
+        }
+        self.locals = 0;
+        self.return_label = self.new_label("return");
+
+        let names: Vec<&str> = function
+            .parameters
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        writeln!(
+            self.code,
+            "\n; fn {}({})",
+            function.name.name,
+            names.join(", ")
+        )
+        .unwrap();
+        self.label(&function.name.name);
+        self.emit(&format!("push {FRAME}"));
+        self.emit(&format!("mov {FRAME} <- r2"));
+        let size = 4 * count_variables(&function.body);
+        if size > 0 {
+            self.emit(&format!("loadimm r3 <- #{size}"));
+            self.emit("sub r2 <- r2 - r3");
+        }
+        self.call("__check_stack");
+        self.block(&function.body);
+        // Falling off the end returns 0
+        self.emit(&format!("loadimm {ACCUMULATOR} <- #0"));
+        let label = self.return_label.clone();
+        self.label(&label);
+        self.emit(&format!("mov r2 <- {FRAME}"));
+        self.emit(&format!("pop {FRAME}"));
+        self.emit("ret");
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        if statement.line != self.commented {
+            self.commented = statement.line;
+            let text = self.source[statement.line - 1].trim();
+            writeln!(self.code, "    ; {text}").unwrap();
+        }
+        match &statement.kind {
+            StatementKind::Var(name, value) => {
+                self.expression(value);
+                if self.variables.contains_key(&name.name) {
+                    self.errors.push(CompileError::new(
+                        name.line,
+                        name.column,
+                        CompileErrorKind::DuplicateVariable(name.name.clone()),
+                    ));
+                    return;
+                }
+                self.locals += 1;
+                let offset = -4 * self.locals as i64;
+                self.variables.insert(name.name.clone(), offset);
+                self.address(name);
+                self.emit(&format!("store [r3] <- {ACCUMULATOR}"));
+            }
+            StatementKind::Assign(name, value) => {
+                self.expression(value);
+                if self.address(name) {
+                    self.emit(&format!("store [r3] <- {ACCUMULATOR}"));
+                }
+            }
+            StatementKind::If(condition, then, otherwise) => {
+                let (other, end) = (self.new_label("else"), self.new_label("end"));
+                self.expression(condition);
+                self.jump_if_zero(&other);
+                self.block(then);
+                self.emit(&format!("jmp {end}"));
+                self.label(&other);
+                self.block(otherwise);
+                self.label(&end);
+            }
+            StatementKind::While(condition, body) => {
+                let (start, end) = (self.new_label("while"), self.new_label("end"));
+                self.label(&start);
+                self.expression(condition);
+                self.jump_if_zero(&end);
+                self.block(body);
+                self.emit(&format!("jmp {start}"));
+                self.label(&end);
+            }
+            StatementKind::Return(value) => {
+                match value {
+                    Some(value) => self.expression(value),
+                    None => self.emit(&format!("loadimm {ACCUMULATOR} <- #0")),
+                }
+                let label = self.return_label.clone();
+                self.emit(&format!("jmp {label}"));
+            }
+            StatementKind::Print(items) => {
+                for item in items {
+                    match item {
+                        Item::String(bytes) => {
+                            self.strings.push(bytes.clone());
+                            self.emit(&format!("loadimm r10 <- #__string_{}", self.strings.len()));
+                            self.emit(&format!("loadimm r11 <- #{}", bytes.len()));
+                            self.call("__print");
+                        }
+                        Item::Expression(value) => {
+                            self.expression(value);
+                            self.emit(&format!("out_number {ACCUMULATOR}"));
+                        }
+                    }
+                }
+            }
+            StatementKind::Expression(value) => self.expression(value),
+        }
+    }
+
+    /// Compute an expression into the accumulator. Intermediate values are
+    /// kept on the stack, since calls overwrite the registers.
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Number(n) => self.number(*n),
+            Expression::Variable(name) => {
+                if self.address(name) {
+                    self.emit(&format!("load {ACCUMULATOR} <- [r3]"));
+                }
+            }
+            Expression::Call(name, arguments) => {
+                match self.functions.get(&name.name) {
+                    None => {
+                        let mut error = CompileError::new(
+                            name.line,
+                            name.column,
+                            CompileErrorKind::UndefinedFunction(name.name.clone()),
+                        );
+                        error.suggestion =
+                            suggest(&name.name, self.functions.keys().map(String::as_str));
+                        self.errors.push(error);
+                    }
+                    Some(&expected) if expected != arguments.len() => {
+                        self.errors.push(CompileError::new(
+                            name.line,
+                            name.column,
+                            CompileErrorKind::Arguments {
+                                name: name.name.clone(),
+                                expected,
+                                found: arguments.len(),
+                            },
+                        ))
+                    }
+                    Some(_) => (),
+                }
+                for argument in arguments {
+                    self.expression(argument);
+                    self.emit(&format!("push {ACCUMULATOR}"));
+                }
+                self.emit(&format!("call {}", name.name));
+                if !arguments.is_empty() {
+                    self.emit(&format!("add r2 <- r2 + #{}", 4 * arguments.len()));
+                }
+            }
+            Expression::Unary("-", value) => match **value {
+                Expression::Number(n) => self.number(-n),
+                _ => {
+                    self.expression(value);
+                    self.emit("loadimm r5 <- #0");
+                    self.emit(&format!("sub {ACCUMULATOR} <- r5 - {ACCUMULATOR}"));
+                }
+            },
+            Expression::Unary(_, value) => {
+                self.expression(value);
+                self.normalize();
+                self.emit("loadimm r5 <- #1");
+                self.emit(&format!("sub {ACCUMULATOR} <- r5 - {ACCUMULATOR}"));
+            }
+            Expression::Binary(op @ ("&&" | "||"), left, right) => {
+                // The right operand is only computed when needed
+                let (skip, end) = (self.new_label("skip"), self.new_label("end"));
+                self.expression(left);
+                self.normalize();
+                match *op {
+                    "&&" => self.jump_if_zero(&end),
+                    _ => {
+                        self.jump_if_zero(&skip);
+                        self.emit(&format!("jmp {end}"));
+                        self.label(&skip);
+                    }
+                }
+                self.expression(right);
+                self.normalize();
+                self.label(&end);
+            }
+            Expression::Binary(op, left, right) => {
+                self.expression(left);
+                self.emit(&format!("push {ACCUMULATOR}"));
+                self.expression(right);
+                self.emit(&format!("mov r5 <- {ACCUMULATOR}"));
+                self.emit(&format!("pop {ACCUMULATOR}"));
+                self.operation(op);
+            }
+        }
+    }
+
+    fn number(&mut self, n: i64) {
+        match i16::try_from(n) {
+            Ok(n) => self.emit(&format!("loadimm {ACCUMULATOR} <- #{n}")),
+            Err(_) => {
+                self.constants.push(n);
+                self.emit(&format!(
+                    "loadimm r3 <- #__constant_{}",
+                    self.constants.len()
+                ));
+                self.emit(&format!("load {ACCUMULATOR} <- [r3]"));
+            }
+        }
+    }
+
+    /// Apply a binary operator to the accumulator and r5.
+    fn operation(&mut self, op: &str) {
+        let a = ACCUMULATOR;
+        match op {
+            "+" => self.emit(&format!("add {a} <- {a} + r5")),
+            "-" => self.emit(&format!("sub {a} <- {a} - r5")),
+            "*" => self.call("__multiply"),
+            "/" => self.call("__divide"),
+            "%" => {
+                self.call("__divide");
+                self.emit(&format!("mov {a} <- r5"));
+            }
+            "==" | "!=" => {
+                let (equal, different) = if op == "==" { (1, 0) } else { (0, 1) };
+                self.emit(&format!("sub r5 <- {a} - r5"));
+                self.emit(&format!("loadimm {a} <- #{equal}"));
+                self.emit(&format!("loadimm r3 <- #{different}"));
+                self.emit(&format!("move {a} <- r3 if r5 != 0"));
+            }
+            // `a < r5` or `a > r5`, negated for `>=` and `<=`
+            _ => {
+                match op {
+                    "<" | ">=" => self.call("__less"),
+                    _ => self.call("__greater"),
+                }
+                if op == "<=" || op == ">=" {
+                    self.emit("loadimm r5 <- #1");
+                    self.emit(&format!("sub {a} <- r5 - {a}"));
+                }
+            }
+        }
+    }
+}
+
+/// Compile a program written in a small imperative language into the
+/// source of a module for [assemble](crate::assemble), which runs its
+/// `main` function and exits with its result.
+///
+/// A program is a list of functions such as
+///
+/// ```text
+/// fn fact(n) {
+///     if n <= 1 { return 1; }
+///     return n * fact(n - 1);
+/// }
+/// ```
+///
+/// whose statements are:
+///   - `var name = expression;` to declare a variable in the function,
+///   - `name = expression;`,
+///   - `if expression { ... } else { ... }`, with an optional `else`
+///     followed by a block or another `if`,
+///   - `while expression { ... }`,
+///   - `return expression;` or `return;`, which returns 0 like reaching the
+///     end of the function,
+///   - `print` followed by strings such as `"fact("` and expressions,
+///     separated by commas, the numbers being printed in decimal,
+///   - an expression followed by `;`, such as a call.
+///
+/// Values are 32-bit integers, with the operators `+`, `-`, `*`, `/`, `%`,
+/// the comparisons `==`, `!=`, `<`, `<=`, `>` and `>=` which give 1 or 0,
+/// and `!`, `&&` and `||`, for which any value but 0 is true. Comments
+/// start with `//`.
+///
+/// The code uses the stack like the `.dis` listings: `r2` is the stack
+/// pointer, set to the end of memory unless the machine set it, and `call`
+/// pushes the return address. Arguments are pushed before it, then the
+/// function saves the frame pointer `r12` and makes room for its variables
+/// below it. Results and the value of expressions are in `r4`, and the
+/// other registers are overwritten. `*` calls a routine which runs in time
+/// proportional to the smaller operand, `/` and `%` one which runs in time
+/// proportional to the number of bits of the quotient. Each function checks
+/// on entry that the stack does not come close to the end of the program,
+/// and stops on a breakpoint then exits with -1 otherwise, as a division
+/// by 0 does.
+///
+/// All the names which are not defined are reported, but only the first
+/// syntax error is.
+pub fn compile(source: &str) -> Result<String, Vec<CompileError>> {
+    let end = match source.lines().enumerate().last() {
+        Some((i, line)) => (i + 1, line.chars().count() + 1),
+        None => (1, 1),
+    };
+    let mut parser = Parser {
+        tokens: lex(source).map_err(|error| vec![error])?,
+        position: 0,
+        end,
+    };
+    let mut functions = Vec::new();
+    while parser.peek().is_some() {
+        functions.push(parser.function().map_err(|error| vec![error])?);
+    }
+
+    let mut generator = Generator {
+        source: source.lines().collect(),
+        ..Generator::default()
+    };
+    for function in &functions {
+        let name = &function.name;
+        if generator
+            .functions
+            .insert(name.name.clone(), function.parameters.len())
+            .is_some()
+        {
+            generator.errors.push(CompileError::new(
+                name.line,
+                name.column,
+                CompileErrorKind::DuplicateFunction(name.name.clone()),
+            ));
+        }
+    }
+    if !generator.functions.contains_key("main") {
+        generator.errors.push(CompileError::new(
+            end.0,
+            end.1,
+            CompileErrorKind::MissingMain,
+        ));
+    }
+
+    generator.code.push_str(&format!(
+        "; Set up the stack unless the machine did, then run main
+    loadimm r3 <- #__main
+    move r0 <- r3 if r2 != 0
+    loadimm r2 <- #{MEMORY_SIZE}
+__main:
+    call main
+    exit {ACCUMULATOR}
+"
+    ));
+    for function in &functions {
+        generator.function(function);
+    }
+    for (name, code) in RUNTIME {
+        if generator.runtime.contains(name) {
+            writeln!(generator.code, "\n{code}").unwrap();
+        }
+    }
+    for (i, string) in generator.strings.iter().enumerate() {
+        writeln!(
+            generator.code,
+            "__string_{}:\n    .ascii {}",
+            i + 1,
+            quote(string)
+        )
+        .unwrap();
+    }
+    for (i, constant) in generator.constants.iter().enumerate() {
+        writeln!(
+            generator.code,
+            "__constant_{}:\n    .word {constant}",
+            i + 1
+        )
+        .unwrap();
+    }
+    if generator.runtime.contains("__negative") {
+        // Words read at the top byte of a value, not zero from 128
+        generator
+            .code
+            .push_str("__sign_table:\n    .space 131\n    .space 128, 1\n");
+    }
+    generator.code.push_str("__end:\n");
+
+    if generator.errors.is_empty() {
+        Ok(generator.code)
+    } else {
+        generator.errors.sort_by_key(|e| (e.line, e.column));
+        Err(generator.errors)
+    }
+}
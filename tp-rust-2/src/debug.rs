@@ -0,0 +1,129 @@
+use std::fmt;
+use std::fmt::Write;
+
+use crate::image::Symbol;
+
+/// What tools such as debuggers need to know about a linked program: the
+/// address of every label, global or not, the source line of each address
+/// and the bytes patched with the address of a label.
+///
+/// It is stored in a text file next to the image, usually with the `.sym`
+/// extension, with one entry per line:
+///   - `label ADDRESS NAME` for a label,
+///   - `line ADDRESS LINE FILE` for the bytes from `ADDRESS` up to the next
+///     line entry, which come from line `LINE` of the source file `FILE`,
+///   - `reloc ADDRESS` for the 2 bytes at `ADDRESS`, set by the linker to
+///     the address of a label.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    /// Labels, by increasing address.
+    pub labels: Vec<Symbol>,
+    /// Line entries, by increasing address.
+    pub lines: Vec<SourceLine>,
+    /// Addresses of the relocated bytes, increasing.
+    pub relocations: Vec<u32>,
+}
+
+/// The bytes starting at `address` come from line `line` of `file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub address: u32,
+    pub file: String,
+    pub line: u32,
+}
+
+/// A line of a debug information file which could not be read, numbered
+/// from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugInfoError {
+    pub line: usize,
+}
+
+impl fmt::Display for DebugInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: invalid debug information", self.line)
+    }
+}
+
+impl DebugInfo {
+    /// Read a debug information file.
+    pub fn parse(text: &str) -> Result<DebugInfo, DebugInfoError> {
+        let mut info = DebugInfo::default();
+        for (i, line) in text.lines().enumerate() {
+            let error = DebugInfoError { line: i + 1 };
+            let mut fields = line.splitn(4, ' ');
+            let kind = fields.next().unwrap();
+            let address: u32 = fields.next().and_then(|a| a.parse().ok()).ok_or(error)?;
+            match (kind, fields.next(), fields.next()) {
+                ("label", Some(name), None) if !name.is_empty() => info.labels.push(Symbol {
+                    name: name.to_string(),
+                    address,
+                }),
+                ("line", Some(number), Some(file)) => info.lines.push(SourceLine {
+                    address,
+                    file: file.to_string(),
+                    line: number.parse().map_err(|_| error)?,
+                }),
+                ("reloc", None, None) => info.relocations.push(address),
+                _ => return Err(error),
+            }
+        }
+        info.labels.sort_by_key(|l| l.address);
+        info.lines.sort_by_key(|l| l.address);
+        info.relocations.sort();
+        Ok(info)
+    }
+
+    /// The debug information file content.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for label in &self.labels {
+            writeln!(text, "label {} {}", label.address, label.name).unwrap();
+        }
+        for line in &self.lines {
+            writeln!(text, "line {} {} {}", line.address, line.line, line.file).unwrap();
+        }
+        for address in &self.relocations {
+            writeln!(text, "reloc {address}").unwrap();
+        }
+        text
+    }
+
+    /// The closest label at or before `address`, along with the offset of
+    /// `address` from it. The first one is chosen among labels sharing an
+    /// address.
+    pub fn label(&self, address: usize) -> Option<(&str, usize)> {
+        let count = self
+            .labels
+            .partition_point(|l| l.address as usize <= address);
+        let last = self.labels[..count].last()?;
+        let label = self.labels.iter().find(|l| l.address == last.address)?;
+        Some((&label.name, address - label.address as usize))
+    }
+
+    /// Whether the 2 bytes at `address` hold the address of a label.
+    pub fn relocated(&self, address: usize) -> bool {
+        self.relocations.binary_search(&(address as u32)).is_ok()
+    }
+
+    /// The source line of the byte at `address`.
+    pub fn line(&self, address: usize) -> Option<&SourceLine> {
+        let count = self
+            .lines
+            .partition_point(|l| l.address as usize <= address);
+        self.lines[..count].last()
+    }
+
+    /// Describe `address` for humans, such as `loop+4 (count.s:31)`, or
+    /// with the address on 4 digits when there is no label before it.
+    pub fn locate(&self, address: usize) -> String {
+        let mut location = match self.label(address) {
+            Some((name, offset)) => format!("{name}+{offset}"),
+            None => format!("{address:04}"),
+        };
+        if let Some(line) = self.line(address) {
+            write!(location, " ({}:{})", line.file, line.line).unwrap();
+        }
+        location
+    }
+}
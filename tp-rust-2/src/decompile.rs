@@ -0,0 +1,1466 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+
+use crate::arguments::STACK_REGISTER;
+use crate::debug::DebugInfo;
+use crate::disassemble::immediate_label;
+use crate::machine::{InstructionType, IP, MEMORY_SIZE, NREGS};
+use crate::verify::{explore, load_program, register_operands, Diagnostic, Program};
+
+const SP: usize = STACK_REGISTER;
+
+/// Registers, one bit each.
+type Registers = u32;
+
+/// What is known about the value of a register or of a stack slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    Unknown,
+    Const(u32),
+    /// The address at this offset from the stack pointer at the entry of
+    /// the function.
+    Stack(i32),
+    /// The value of the register at the entry of the function.
+    Entry(usize),
+}
+
+fn subtract(x: Value, y: Value) -> Value {
+    match (x, y) {
+        (Value::Const(x), Value::Const(y)) => Value::Const(x.wrapping_sub(y)),
+        (Value::Stack(x), Value::Const(y)) => Value::Stack(x.wrapping_sub(y as i32)),
+        (Value::Stack(x), Value::Stack(y)) => Value::Const(x.wrapping_sub(y) as u32),
+        _ => Value::Unknown,
+    }
+}
+
+/// What is known about the machine before an instruction of a function.
+#[derive(Clone, PartialEq, Eq)]
+struct State {
+    regs: [Value; NREGS],
+    /// Content of the stack slots written by the function, by offset.
+    slots: BTreeMap<i32, Value>,
+}
+
+impl State {
+    fn entry() -> State {
+        let mut regs = [Value::Unknown; NREGS];
+        for (r, value) in regs.iter_mut().enumerate() {
+            *value = Value::Entry(r);
+        }
+        regs[SP] = Value::Stack(0);
+        State {
+            regs,
+            slots: BTreeMap::new(),
+        }
+    }
+
+    /// Keep only what is known in both states. Returns `true` if `self`
+    /// has changed.
+    fn merge(&mut self, other: &State) -> bool {
+        let before = self.clone();
+        for (mine, theirs) in self.regs.iter_mut().zip(&other.regs) {
+            if mine != theirs {
+                *mine = Value::Unknown;
+            }
+        }
+        self.slots
+            .retain(|offset, value| other.slots.get(offset) == Some(value));
+        *self != before
+    }
+
+    fn store(&mut self, offset: i32, value: Value) {
+        // The 4 bytes written overlap the neighbouring slots
+        self.slots.retain(|o, _| o.abs_diff(offset) >= 4);
+        self.slots.insert(offset, value);
+    }
+}
+
+/// The name of the stack slot at this offset from the stack pointer at the
+/// entry of the function: `argN` above the return address, `localN` below.
+fn slot(offset: i32) -> String {
+    match offset {
+        0 => "return_address".to_string(),
+        o if o % 4 != 0 => format!("stack[{o}]"),
+        o if o > 0 => format!("arg{}", o / 4),
+        o => format!("local{}", -o / 4),
+    }
+}
+
+fn register(r: usize) -> String {
+    match r {
+        SP => "sp".to_string(),
+        r => format!("r{r}"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Register(usize),
+    Number(i32),
+    /// A stack slot or its address.
+    Name(String),
+    Load(Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn registers(&self) -> Registers {
+        match self {
+            Expr::Register(r) => 1 << r,
+            Expr::Number(_) | Expr::Name(_) => 0,
+            Expr::Load(e) => e.registers(),
+            Expr::Sub(l, r) => l.registers() | r.registers(),
+        }
+    }
+
+    /// The number of times `register` is read.
+    fn count(&self, register: usize) -> usize {
+        match self {
+            Expr::Register(r) => (*r == register) as usize,
+            Expr::Number(_) | Expr::Name(_) => 0,
+            Expr::Load(e) => e.count(register),
+            Expr::Sub(l, r) => l.count(register) + r.count(register),
+        }
+    }
+
+    fn substitute(&mut self, register: usize, value: &Expr) {
+        match self {
+            Expr::Register(r) if *r == register => *self = value.clone(),
+            Expr::Register(_) | Expr::Number(_) | Expr::Name(_) => (),
+            Expr::Load(e) => e.substitute(register, value),
+            Expr::Sub(l, r) => {
+                l.substitute(register, value);
+                r.substitute(register, value);
+            }
+        }
+    }
+
+    /// The expression as text, and whether it can be an operand without
+    /// parentheses. Subtractions of negative numbers and of negations are
+    /// shown as additions.
+    fn render(&self) -> (String, bool) {
+        match self {
+            Expr::Register(r) => (register(*r), true),
+            Expr::Number(n) => (n.to_string(), true),
+            Expr::Name(name) => (name.clone(), true),
+            Expr::Load(e) => (format!("mem[{}]", e.render().0), true),
+            Expr::Sub(l, r) => {
+                let (left, _) = l.render();
+                let operand = |e: &Expr| match e.render() {
+                    (text, true) => text,
+                    (text, false) => format!("({text})"),
+                };
+                match (&**l, &**r) {
+                    (_, Expr::Number(0)) => l.render(),
+                    (Expr::Number(0), _) => (format!("-{}", operand(r)), false),
+                    (_, &Expr::Number(n)) if n < 0 && n != i32::MIN => {
+                        (format!("{left} + {}", n.unsigned_abs()), false)
+                    }
+                    (_, Expr::Sub(zero, negated)) if **zero == Expr::Number(0) => {
+                        (format!("{left} + {}", operand(negated)), false)
+                    }
+                    _ => (format!("{left} - {}", operand(r)), false),
+                }
+            }
+        }
+    }
+
+    fn text(&self) -> String {
+        self.render().0
+    }
+}
+
+/// `value` compared to 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Condition {
+    value: Expr,
+    /// Whether the condition holds when `value` is 0 rather than when it
+    /// is not.
+    zero: bool,
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        Condition {
+            zero: !self.zero,
+            ..self
+        }
+    }
+
+    /// A difference compared to 0 is shown as a comparison of its operands.
+    fn text(&self) -> String {
+        let op = if self.zero { "==" } else { "!=" };
+        match &self.value {
+            Expr::Sub(l, r) if **r != Expr::Number(0) && **l != Expr::Number(0) => {
+                format!("{} {op} {}", l.text(), r.text())
+            }
+            value => format!("{} {op} 0", value.text()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Place {
+    Register(usize),
+    Slot(i32),
+    Memory(Expr),
+}
+
+/// A statement of the pseudo-code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Assign(Place, Expr),
+    /// Set the register to the value when the condition is not 0. For the
+    /// IP, it is a jump to an address computed at run time.
+    AssignIf(usize, Expr, Expr),
+    /// A call to the function at this address.
+    Call(usize),
+    /// An operation of the machine such as `print`.
+    Builtin(&'static str, Option<Expr>),
+}
+
+/// Where control goes after an instruction.
+enum Flow {
+    Next,
+    Goto(usize),
+    /// To the address if the expression is not 0, to the next instruction
+    /// otherwise.
+    Branch(Expr, usize),
+    Return,
+    /// To an address computed at run time.
+    Jump(Expr),
+    Exit(Option<Expr>),
+    Invalid,
+}
+
+/// How an instruction uses a stack slot, to hide the registers saved on
+/// entry and restored before returning.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Use {
+    /// The value of a register at the entry of the function is stored.
+    Save(i32),
+    /// A register gets back its value at the entry of the function.
+    Restore(i32),
+    Load(i32),
+}
+
+/// The effect of an instruction.
+struct Step {
+    next: usize,
+    line: Option<Line>,
+    flow: Flow,
+    slot: Option<Use>,
+    /// Whether it may write to the stack slots of the caller.
+    clobbers: bool,
+}
+
+/// What is known of a function at its call sites.
+#[derive(Clone, PartialEq, Eq)]
+struct Summary {
+    /// Registers which get back their value before returning.
+    preserved: [bool; NREGS],
+    /// Whether the return address is popped and the rest of the stack left
+    /// as it was found.
+    balanced: bool,
+    clobbers: bool,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Summary {
+            preserved: [true; NREGS],
+            balanced: true,
+            clobbers: false,
+        }
+    }
+}
+
+/// How a basic block ends.
+#[derive(Debug, Clone)]
+enum Terminator {
+    Goto(usize),
+    /// To the first block if the condition holds, to the second otherwise.
+    Branch(Condition, usize, usize),
+    Return,
+    Jump(Expr),
+    Exit(Option<Expr>),
+    Invalid(usize),
+}
+
+impl Terminator {
+    fn successors(&self) -> Vec<usize> {
+        match self {
+            Terminator::Goto(target) => vec![*target],
+            Terminator::Branch(_, taken, other) => vec![*taken, *other],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Block {
+    lines: Vec<Line>,
+    exit: Terminator,
+}
+
+/// The blocks of a function, by address.
+type Blocks = BTreeMap<usize, Block>;
+
+struct Decompiler<'a> {
+    memory: [u8; MEMORY_SIZE],
+    /// Address where the program starts.
+    entry: usize,
+    lengths: BTreeMap<usize, usize>,
+    /// Jump targets found by [explore].
+    targets: BTreeSet<usize>,
+    calls: BTreeMap<usize, usize>,
+    /// Addresses following the calls, stored as return addresses.
+    returns: BTreeSet<u32>,
+    summaries: BTreeMap<usize, Summary>,
+    debug_info: Option<&'a DebugInfo>,
+}
+
+impl Decompiler<'_> {
+    fn label(&self, address: usize) -> Option<&str> {
+        let labels = &self.debug_info?.labels;
+        let label = labels.iter().find(|l| l.address as usize == address)?;
+        Some(&label.name)
+    }
+
+    fn function_name(&self, address: usize) -> String {
+        match self.label(address) {
+            Some(label) => label.to_string(),
+            None if address == self.entry => "start".to_string(),
+            None => format!("function_{address:04}"),
+        }
+    }
+
+    /// Execute the instruction at `address` on `state`.
+    fn step(&self, address: usize, state: &mut State) -> Step {
+        let mut step = Step {
+            next: address,
+            line: None,
+            flow: Flow::Invalid,
+            slot: None,
+            clobbers: false,
+        };
+        let Some(&length) = self.lengths.get(&address) else {
+            return step;
+        };
+        let Ok(instruction_type) = InstructionType::from_opcode(self.memory[address]) else {
+            return step;
+        };
+        if length != instruction_type.length() || address + length > MEMORY_SIZE {
+            return step;
+        }
+        let instruction = &self.memory[address..address + length];
+        let operands = register_operands(instruction_type);
+        if operands.iter().any(|&i| instruction[i] as usize >= NREGS) {
+            return step;
+        }
+        step.next = address + length;
+        step.flow = Flow::Next;
+        state.regs[IP] = Value::Const(step.next as u32);
+
+        let operand = |i: usize| instruction.get(i).copied().unwrap_or(0) as usize;
+        let (a, b, c) = (operand(1), operand(2), operand(3));
+        let expr = |state: &State, r: usize| match state.regs[r] {
+            Value::Const(k) => Expr::Number(k as i32),
+            Value::Stack(offset) => Expr::Name(format!("&{}", slot(offset))),
+            _ => Expr::Register(r),
+        };
+        match instruction_type {
+            InstructionType::MoveIf => match state.regs[c] {
+                Value::Const(0) => (),
+                Value::Const(_) | Value::Stack(_) if a == IP => {
+                    step.flow = self.jump(address, state.regs[b], expr(state, b), state, &mut step)
+                }
+                Value::Const(_) | Value::Stack(_) => {
+                    step.line = Some(Line::Assign(Place::Register(a), expr(state, b)));
+                    state.regs[a] = state.regs[b];
+                }
+                _ if a == IP => match state.regs[b] {
+                    Value::Const(target) => {
+                        step.flow = Flow::Branch(expr(state, c), target as usize)
+                    }
+                    _ => step.line = Some(Line::AssignIf(IP, expr(state, b), expr(state, c))),
+                },
+                _ => {
+                    step.line = Some(Line::AssignIf(a, expr(state, b), expr(state, c)));
+                    if state.regs[a] != state.regs[b] {
+                        state.regs[a] = Value::Unknown;
+                    }
+                }
+            },
+            InstructionType::Store => {
+                let value = state.regs[b];
+                let place = match state.regs[a] {
+                    Value::Stack(offset) => {
+                        if let Value::Entry(_) = value {
+                            step.slot = Some(Use::Save(offset));
+                        }
+                        step.clobbers = offset >= 0;
+                        state.store(offset, value);
+                        Place::Slot(offset)
+                    }
+                    Value::Const(address) => Place::Memory(Expr::Number(address as i32)),
+                    _ => {
+                        // It may write anywhere in the stack
+                        step.clobbers = true;
+                        state.slots.clear();
+                        Place::Memory(Expr::Register(a))
+                    }
+                };
+                let return_address = matches!(value, Value::Const(v) if self.returns.contains(&v));
+                if !return_address {
+                    step.line = Some(Line::Assign(place, expr(state, b)));
+                }
+            }
+            InstructionType::Load => match state.regs[b] {
+                Value::Stack(0) if a == IP => step.flow = Flow::Return,
+                _ if a == IP => step.flow = Flow::Jump(Expr::Load(Box::new(expr(state, b)))),
+                Value::Stack(offset) => {
+                    let value = state.slots.get(&offset).copied().unwrap_or(Value::Unknown);
+                    step.slot = Some(match value == Value::Entry(a) {
+                        true => Use::Restore(offset),
+                        false => Use::Load(offset),
+                    });
+                    step.line = Some(Line::Assign(Place::Register(a), Expr::Name(slot(offset))));
+                    state.regs[a] = value;
+                }
+                _ => {
+                    let address = Expr::Load(Box::new(expr(state, b)));
+                    step.line = Some(Line::Assign(Place::Register(a), address));
+                    state.regs[a] = Value::Unknown;
+                }
+            },
+            InstructionType::LoadImm => {
+                let imm = i16::from_le_bytes([instruction[2], instruction[3]]) as i32;
+                let value = Value::Const(imm as u32);
+                match a {
+                    IP => {
+                        step.flow = self.jump(address, value, Expr::Number(imm), state, &mut step)
+                    }
+                    // The first half of `mov sp <- rB`, that is `sub sp <- rB - sp`
+                    SP if self.is_mov_to_sp(step.next) => state.regs[SP] = value,
+                    SP => {
+                        // A new stack, whose slots are named from this address
+                        step.line = Some(Line::Assign(Place::Register(SP), Expr::Number(imm)));
+                        state.regs[SP] = Value::Stack(0);
+                        state.slots.clear();
+                    }
+                    _ => {
+                        let label =
+                            immediate_label(instruction, address, self.debug_info, &self.targets);
+                        let imm = match label {
+                            Some(label) => Expr::Name(label.to_string()),
+                            None => Expr::Number(imm),
+                        };
+                        step.line = Some(Line::Assign(Place::Register(a), imm));
+                        state.regs[a] = value;
+                    }
+                }
+            }
+            InstructionType::Sub => {
+                let value = subtract(state.regs[b], state.regs[c]);
+                let difference = Expr::Sub(Box::new(expr(state, b)), Box::new(expr(state, c)));
+                match (a, value) {
+                    (IP, _) => step.flow = self.jump(address, value, difference, state, &mut step),
+                    // Pushing and popping
+                    (SP, Value::Stack(_)) => (),
+                    (_, Value::Const(k)) => {
+                        step.line = Some(Line::Assign(Place::Register(a), Expr::Number(k as i32)))
+                    }
+                    (_, Value::Stack(offset)) => {
+                        let address = Expr::Name(format!("&{}", slot(offset)));
+                        step.line = Some(Line::Assign(Place::Register(a), address));
+                    }
+                    _ => step.line = Some(Line::Assign(Place::Register(a), difference)),
+                }
+                state.regs[a] = value;
+            }
+            InstructionType::Out => {
+                step.line = Some(Line::Builtin("putchar", Some(expr(state, a))))
+            }
+            InstructionType::OutNumber => {
+                step.line = Some(Line::Builtin("print", Some(expr(state, a))))
+            }
+            InstructionType::Brk => step.line = Some(Line::Builtin("breakpoint", None)),
+            InstructionType::Exit => step.flow = Flow::Exit(None),
+            InstructionType::ExitWith => step.flow = Flow::Exit(Some(expr(state, a))),
+        }
+        step
+    }
+
+    fn is_mov_to_sp(&self, address: usize) -> bool {
+        let instruction = &self.memory[address..(address + 4).min(MEMORY_SIZE)];
+        matches!(
+            InstructionType::from_opcode(instruction[0]),
+            Ok(InstructionType::Sub)
+        ) && instruction.get(1) == Some(&(SP as u8))
+            && instruction.get(3) == Some(&(SP as u8))
+    }
+
+    /// The flow of an instruction setting the IP to `target`. The calls
+    /// found by the verifier continue with the next instruction.
+    fn jump(
+        &self,
+        address: usize,
+        target: Value,
+        expr: Expr,
+        state: &mut State,
+        step: &mut Step,
+    ) -> Flow {
+        let Value::Const(target) = target else {
+            return Flow::Jump(expr);
+        };
+        let target = target as usize;
+        if self.calls.get(&address) != Some(&target) {
+            return Flow::Goto(target);
+        }
+        let summary = self.summaries.get(&target).cloned().unwrap_or_default();
+        for (r, value) in state.regs.iter_mut().enumerate() {
+            if r != SP && !summary.preserved[r] {
+                *value = Value::Unknown;
+            }
+        }
+        state.regs[SP] = match summary.balanced {
+            true => subtract(state.regs[SP], Value::Const(-4i32 as u32)),
+            false => Value::Unknown,
+        };
+        if summary.clobbers {
+            state.slots.clear();
+        }
+        step.clobbers = summary.clobbers;
+        step.line = Some(Line::Call(target));
+        Flow::Next
+    }
+
+    /// The state before each instruction of the function starting at
+    /// `entry`, calls excluded.
+    fn analyze(&self, entry: usize) -> BTreeMap<usize, State> {
+        let mut states = BTreeMap::from([(entry, State::entry())]);
+        let mut worklist = VecDeque::from([entry]);
+        let mut visited = BTreeSet::new();
+        while let Some(address) = worklist.pop_front() {
+            visited.insert(address);
+            let mut state = states[&address].clone();
+            let step = self.step(address, &mut state);
+            let successors = match step.flow {
+                Flow::Next => vec![step.next],
+                Flow::Goto(target) => vec![target],
+                Flow::Branch(_, target) => vec![target, step.next],
+                _ => Vec::new(),
+            };
+            for target in successors {
+                let changed = match states.get_mut(&target) {
+                    Some(known) => known.merge(&state),
+                    None => {
+                        states.insert(target, state.clone());
+                        true
+                    }
+                };
+                if changed || !visited.contains(&target) {
+                    worklist.push_back(target);
+                }
+            }
+        }
+        states
+    }
+
+    fn summarize(&self, states: &BTreeMap<usize, State>) -> Summary {
+        let mut summary = Summary::default();
+        for (&address, state) in states {
+            let mut after = state.clone();
+            let step = self.step(address, &mut after);
+            summary.clobbers |= step.clobbers;
+            if let Flow::Return = step.flow {
+                for (r, preserved) in summary.preserved.iter_mut().enumerate() {
+                    *preserved &= state.regs[r] == Value::Entry(r);
+                }
+                summary.balanced &= state.regs[SP] == Value::Stack(4);
+            }
+        }
+        summary
+    }
+
+    /// The basic blocks of the function starting at `entry`.
+    fn blocks(&self, entry: usize) -> Blocks {
+        let states = self.analyze(entry);
+        let steps: BTreeMap<usize, Step> = states
+            .iter()
+            .map(|(&address, state)| (address, self.step(address, &mut state.clone())))
+            .collect();
+        // Slots only used to save registers are hidden
+        let loaded: BTreeSet<i32> = steps
+            .values()
+            .filter_map(|step| match step.slot {
+                Some(Use::Load(offset)) => Some(offset),
+                _ => None,
+            })
+            .collect();
+        let hidden = |step: &Step| match step.slot {
+            Some(Use::Save(offset) | Use::Restore(offset)) => {
+                !loaded.iter().any(|l| l.abs_diff(offset) < 4)
+            }
+            _ => false,
+        };
+
+        // Blocks start at the entry, at jump targets and where paths join
+        let mut predecessors: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut leaders = BTreeSet::from([entry]);
+        for step in steps.values() {
+            let successors = match step.flow {
+                Flow::Next => vec![step.next],
+                Flow::Goto(target) => vec![target],
+                Flow::Branch(_, target) => vec![target, step.next],
+                _ => Vec::new(),
+            };
+            if !matches!(step.flow, Flow::Next) {
+                leaders.extend(&successors);
+            }
+            for target in successors {
+                *predecessors.entry(target).or_default() += 1;
+            }
+        }
+        leaders.extend(predecessors.iter().filter(|(_, &n)| n > 1).map(|(&a, _)| a));
+
+        let mut blocks = Blocks::new();
+        for &leader in &leaders {
+            let mut lines = Vec::new();
+            let mut address = leader;
+            let exit = loop {
+                let step = &steps[&address];
+                if !hidden(step) {
+                    lines.extend(step.line.clone());
+                }
+                match &step.flow {
+                    Flow::Next if leaders.contains(&step.next) => {
+                        break Terminator::Goto(step.next)
+                    }
+                    Flow::Next => address = step.next,
+                    Flow::Goto(target) => break Terminator::Goto(*target),
+                    Flow::Branch(value, target) => {
+                        let condition = Condition {
+                            value: value.clone(),
+                            zero: false,
+                        };
+                        break Terminator::Branch(condition, *target, step.next);
+                    }
+                    Flow::Return => break Terminator::Return,
+                    Flow::Jump(target) => break Terminator::Jump(target.clone()),
+                    Flow::Exit(code) => break Terminator::Exit(code.clone()),
+                    Flow::Invalid => break Terminator::Invalid(address),
+                }
+            };
+            blocks.insert(leader, Block { lines, exit });
+        }
+        blocks
+    }
+}
+
+fn exit_uses(exit: &Terminator, outputs: Registers) -> Registers {
+    match exit {
+        Terminator::Goto(_) | Terminator::Invalid(_) => 0,
+        Terminator::Branch(condition, _, _) => condition.value.registers(),
+        Terminator::Return => outputs,
+        // It may go anywhere
+        Terminator::Jump(_) => Registers::MAX,
+        Terminator::Exit(code) => code.as_ref().map_or(0, Expr::registers),
+    }
+}
+
+/// What the functions of the program read and write, for liveness.
+struct Interface {
+    /// Registers read by each function before writing them.
+    inputs: BTreeMap<usize, Registers>,
+    /// Registers written by each function which its callers read after
+    /// it returns.
+    outputs: BTreeMap<usize, Registers>,
+    /// Registers written by each function on every path to a return.
+    kills: BTreeMap<usize, Registers>,
+}
+
+impl Interface {
+    /// Registers read and written by a statement. Calls read the inputs of
+    /// the function.
+    fn uses(&self, line: &Line) -> (Registers, Registers) {
+        match line {
+            Line::Assign(Place::Register(r), value) => (value.registers(), 1 << r),
+            Line::Assign(Place::Memory(address), value) => {
+                (address.registers() | value.registers(), 0)
+            }
+            Line::Assign(Place::Slot(_), value) => (value.registers(), 0),
+            Line::AssignIf(_, value, condition) => (value.registers() | condition.registers(), 0),
+            Line::Call(function) => {
+                let inputs = self.inputs.get(function).copied().unwrap_or(0);
+                (inputs, self.kills[function])
+            }
+            Line::Builtin(_, value) => (value.as_ref().map_or(0, Expr::registers), 0),
+        }
+    }
+
+    /// Find the registers written on every path to a return, assuming
+    /// at first that functions write all of them.
+    fn find_kills(&mut self, functions: &BTreeMap<usize, Blocks>) {
+        self.kills = functions.keys().map(|&f| (f, Registers::MAX)).collect();
+        loop {
+            let mut changed = false;
+            for (&function, blocks) in functions {
+                let mut written: BTreeMap<usize, Registers> =
+                    blocks.keys().map(|&b| (b, Registers::MAX)).collect();
+                written.insert(function, 0);
+                let mut kills = Registers::MAX;
+                loop {
+                    let mut shrunk = false;
+                    for (&address, block) in blocks {
+                        let mut after = written[&address];
+                        for line in &block.lines {
+                            after |= self.uses(line).1;
+                        }
+                        if let Terminator::Return = block.exit {
+                            kills &= after;
+                        }
+                        for successor in block.exit.successors() {
+                            let before = written[&successor] & after;
+                            // The entry is also reached from the caller
+                            if successor != function && before != written[&successor] {
+                                written.insert(successor, before);
+                                shrunk = true;
+                            }
+                        }
+                    }
+                    if !shrunk {
+                        break;
+                    }
+                    kills = Registers::MAX;
+                }
+                kills &= !(1 << SP);
+                changed |= self.kills.insert(function, kills) != Some(kills);
+            }
+            if !changed {
+                return;
+            }
+        }
+    }
+
+    /// Registers live before each line of a block, then before its exit,
+    /// given those live after it.
+    fn liveness(&self, function: usize, block: &Block, live_out: Registers) -> Vec<Registers> {
+        let outputs = self.outputs.get(&function).copied().unwrap_or(0);
+        let mut live = live_out | exit_uses(&block.exit, outputs);
+        let mut result = vec![live];
+        for line in block.lines.iter().rev() {
+            let (read, written) = self.uses(line);
+            live = live & !written | read;
+            result.push(live);
+        }
+        result.reverse();
+        result
+    }
+
+    /// Registers live at the beginning of each block.
+    fn live_in(&self, function: usize, blocks: &Blocks) -> BTreeMap<usize, Registers> {
+        let mut live: BTreeMap<usize, Registers> = blocks.keys().map(|&b| (b, 0)).collect();
+        loop {
+            let mut changed = false;
+            for (&address, block) in blocks.iter().rev() {
+                let out = self.live_out(block, &live);
+                let first = self.liveness(function, block, out)[0];
+                if live[&address] != first {
+                    live.insert(address, first);
+                    changed = true;
+                }
+            }
+            if !changed {
+                return live;
+            }
+        }
+    }
+
+    fn live_out(&self, block: &Block, live_in: &BTreeMap<usize, Registers>) -> Registers {
+        block
+            .exit
+            .successors()
+            .iter()
+            .map(|s| live_in.get(s).copied().unwrap_or(0))
+            .fold(0, |a, b| a | b)
+    }
+
+    /// Compute the inputs and outputs of the functions.
+    fn new(functions: &BTreeMap<usize, Blocks>) -> Interface {
+        // Registers written by each function or the functions it calls
+        let mut writes: BTreeMap<usize, Registers> = BTreeMap::new();
+        loop {
+            let mut changed = false;
+            for (&function, blocks) in functions {
+                let mut written = 0;
+                for line in blocks.values().flat_map(|b| &b.lines) {
+                    written |= match line {
+                        Line::Assign(Place::Register(r), _) | Line::AssignIf(r, _, _) => 1 << r,
+                        Line::Call(callee) => writes.get(callee).copied().unwrap_or(0),
+                        _ => 0,
+                    };
+                }
+                changed |= writes.insert(function, written) != Some(written);
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut interface = Interface {
+            inputs: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+            kills: BTreeMap::new(),
+        };
+        interface.find_kills(functions);
+        loop {
+            let mut changed = false;
+            for (&function, blocks) in functions {
+                let live_in = interface.live_in(function, blocks);
+                let inputs = live_in[&function] & !(1 << IP);
+                changed |= interface.inputs.insert(function, inputs) != Some(inputs);
+                for block in blocks.values() {
+                    let out = interface.live_out(block, &live_in);
+                    let live = interface.liveness(function, block, out);
+                    for (line, &live) in block.lines.iter().zip(&live[1..]) {
+                        if let Line::Call(callee) = line {
+                            // The others are kept as they are through the call
+                            let live = live & writes[callee];
+                            let outputs = interface.outputs.entry(*callee).or_default();
+                            changed |= *outputs | live != *outputs;
+                            *outputs |= live;
+                        }
+                    }
+                }
+            }
+            if !changed {
+                return interface;
+            }
+        }
+    }
+}
+
+/// Remove the assignments to registers which are not read afterwards, and
+/// substitute the value of the registers only read by the next statement.
+/// Returns `true` if something has changed.
+fn simplify(function: usize, blocks: &mut Blocks, interface: &Interface) -> bool {
+    let live_in = interface.live_in(function, blocks);
+    let mut changed = false;
+    for block in blocks.values_mut() {
+        let out = interface.live_out(block, &live_in);
+        let before = block.lines.len();
+        let mut after = interface.liveness(function, block, out).into_iter().skip(1);
+        block.lines.retain(|line| {
+            let live = after.next().unwrap();
+            !matches!(line, Line::Assign(Place::Register(r), _) if *r != SP && live & (1 << r) == 0)
+        });
+        changed |= block.lines.len() != before;
+
+        'substitution: loop {
+            let live = interface.liveness(function, block, out);
+            for i in 0..block.lines.len() {
+                let Line::Assign(Place::Register(r), value) = &block.lines[i] else {
+                    continue;
+                };
+                let (r, value) = (*r, value.clone());
+                if r == SP {
+                    continue;
+                }
+                // The old value must not be needed after the next statement
+                let next = block.lines.get(i + 1);
+                let redefined =
+                    matches!(next, Some(Line::Assign(Place::Register(d), _)) if *d == r);
+                let live = match next {
+                    Some(_) => live[i + 2],
+                    None => out,
+                };
+                if live & (1 << r) != 0 && !redefined {
+                    continue;
+                }
+                let substituted = match block.lines.get_mut(i + 1) {
+                    Some(line) => substitute_line(line, r, &value),
+                    None => substitute_exit(&mut block.exit, r, &value),
+                };
+                if substituted {
+                    block.lines.remove(i);
+                    changed = true;
+                    continue 'substitution;
+                }
+            }
+            break;
+        }
+    }
+    changed
+}
+
+/// Replace the only read of `register` by `value`, if there is exactly one.
+fn substitute_line(line: &mut Line, register: usize, value: &Expr) -> bool {
+    let mut exprs: Vec<&mut Expr> = match line {
+        Line::Assign(Place::Memory(address), e) => vec![address, e],
+        Line::Assign(_, e) => vec![e],
+        Line::AssignIf(_, e, condition) => vec![e, condition],
+        Line::Builtin(_, Some(e)) => vec![e],
+        Line::Call(_) | Line::Builtin(_, None) => return false,
+    };
+    substitute_exprs(&mut exprs, register, value)
+}
+
+fn substitute_exit(exit: &mut Terminator, register: usize, value: &Expr) -> bool {
+    let mut exprs: Vec<&mut Expr> = match exit {
+        Terminator::Branch(condition, _, _) => vec![&mut condition.value],
+        Terminator::Jump(e) | Terminator::Exit(Some(e)) => vec![e],
+        _ => return false,
+    };
+    substitute_exprs(&mut exprs, register, value)
+}
+
+fn substitute_exprs(exprs: &mut [&mut Expr], register: usize, value: &Expr) -> bool {
+    if exprs.iter().map(|e| e.count(register)).sum::<usize>() != 1 {
+        return false;
+    }
+    for e in exprs {
+        e.substitute(register, value);
+    }
+    true
+}
+
+/// A statement of the structured pseudo-code.
+#[derive(Debug, Clone)]
+enum Node {
+    Line(String),
+    If(Condition, Vec<Node>, Vec<Node>),
+    Loop(Vec<Node>),
+    While(Condition, Vec<Node>),
+    Break,
+    Continue,
+    Goto(usize),
+    Label(usize),
+    /// The end of the function: `return`, `exit` or a computed jump.
+    End(String),
+}
+
+/// Rebuilds loops and conditionals from the blocks of a function.
+struct Structurer<'a> {
+    blocks: &'a BTreeMap<usize, (Vec<String>, Terminator)>,
+    /// The nearest block that every path from a block goes through, if any.
+    join: BTreeMap<usize, usize>,
+    /// The blocks of each loop, by header.
+    loops: BTreeMap<usize, BTreeSet<usize>>,
+    emitted: BTreeSet<usize>,
+    /// The loops being emitted, innermost last, with the block following
+    /// each one.
+    stack: Vec<(usize, Option<usize>)>,
+    /// Renders the end of a function.
+    end: &'a dyn Fn(&Terminator) -> String,
+}
+
+impl Structurer<'_> {
+    /// Emit the code from `start` to `stop`, excluded.
+    fn region(&mut self, start: usize, stop: Option<usize>, out: &mut Vec<Node>) {
+        let mut next = Some(start);
+        while let Some(block) = next {
+            if Some(block) == stop {
+                return;
+            }
+            if let Some(node) = self.jump(block) {
+                out.push(node);
+                return;
+            }
+            if self.loops.contains_key(&block) {
+                let follow = self.follow(block);
+                self.stack.push((block, follow));
+                let mut body = Vec::new();
+                if let Some(after) = self.block(block, &mut body) {
+                    self.region(after, None, &mut body);
+                }
+                self.stack.pop();
+                out.push(Node::Loop(body));
+                next = follow;
+            } else {
+                next = self.block(block, out);
+            }
+        }
+    }
+
+    /// How to get to a block which is not emitted next.
+    fn jump(&self, block: usize) -> Option<Node> {
+        let innermost = self.stack.len().wrapping_sub(1);
+        for (i, &(header, follow)) in self.stack.iter().enumerate().rev() {
+            if block == header {
+                return Some(if i == innermost {
+                    Node::Continue
+                } else {
+                    Node::Goto(block)
+                });
+            }
+            if Some(block) == follow {
+                return Some(if i == innermost {
+                    Node::Break
+                } else {
+                    Node::Goto(block)
+                });
+            }
+        }
+        self.emitted.contains(&block).then_some(Node::Goto(block))
+    }
+
+    /// The block following a loop: where its header goes when leaving it,
+    /// or its first exit.
+    fn follow(&self, header: usize) -> Option<usize> {
+        let body = &self.loops[&header];
+        let exits: BTreeSet<usize> = body
+            .iter()
+            .flat_map(|b| self.blocks[b].1.successors())
+            .filter(|s| !body.contains(s))
+            .collect();
+        match self.join.get(&header) {
+            Some(join) if exits.contains(join) => Some(*join),
+            _ => exits.first().copied(),
+        }
+    }
+
+    /// Emit a block and its conditional, and return the block following them.
+    fn block(&mut self, block: usize, out: &mut Vec<Node>) -> Option<usize> {
+        self.emitted.insert(block);
+        out.push(Node::Label(block));
+        let (lines, exit) = &self.blocks[&block];
+        out.extend(lines.iter().cloned().map(Node::Line));
+        match exit {
+            Terminator::Goto(target) => Some(*target),
+            Terminator::Branch(condition, taken, other) => {
+                // Both branches end where they join, unless it is out of the loop
+                let join = self
+                    .join
+                    .get(&block)
+                    .copied()
+                    .filter(|j| match self.stack.last() {
+                        Some((header, _)) => self.loops[header].contains(j),
+                        None => true,
+                    });
+                let (condition, taken, other) = (condition.clone(), *taken, *other);
+                let mut then = Vec::new();
+                self.region(taken, join, &mut then);
+                let mut otherwise = Vec::new();
+                self.region(other, join, &mut otherwise);
+                out.push(Node::If(condition, then, otherwise));
+                join
+            }
+            exit => {
+                out.push(Node::End((self.end)(exit)));
+                None
+            }
+        }
+    }
+}
+
+/// The nearest post-dominator of each block which has one.
+fn joins(blocks: &BTreeMap<usize, (Vec<String>, Terminator)>) -> BTreeMap<usize, usize> {
+    const END: usize = usize::MAX;
+    let all: BTreeSet<usize> = blocks.keys().copied().chain([END]).collect();
+    let mut post: BTreeMap<usize, BTreeSet<usize>> =
+        blocks.keys().map(|&b| (b, all.clone())).collect();
+    post.insert(END, BTreeSet::from([END]));
+    loop {
+        let mut changed = false;
+        for (&b, (_, exit)) in blocks.iter().rev() {
+            let mut successors = exit.successors();
+            if successors.is_empty() {
+                successors.push(END);
+            }
+            let mut set = successors
+                .iter()
+                .map(|s| post[s].clone())
+                .reduce(|a, b| a.intersection(&b).copied().collect())
+                .unwrap();
+            set.insert(b);
+            if post[&b] != set {
+                post.insert(b, set);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    let mut joins = BTreeMap::new();
+    for (&b, set) in &post {
+        // Blocks which never reach the end keep every block
+        if b == END || set.len() == all.len() {
+            continue;
+        }
+        let mut strict = set.clone();
+        strict.remove(&b);
+        if let Some(&join) = strict.iter().find(|d| post[d] == strict) {
+            if join != END {
+                joins.insert(b, join);
+            }
+        }
+    }
+    joins
+}
+
+/// The blocks of each loop, by header: the blocks from which a jump back
+/// goes to the header, found by depth-first search, and those leading to
+/// them.
+fn loops(
+    entry: usize,
+    blocks: &BTreeMap<usize, (Vec<String>, Terminator)>,
+) -> BTreeMap<usize, BTreeSet<usize>> {
+    let mut predecessors: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (&b, (_, exit)) in blocks {
+        for s in exit.successors() {
+            predecessors.entry(s).or_default().push(b);
+        }
+    }
+    let mut back_edges = Vec::new();
+    let mut visited = BTreeSet::from([entry]);
+    let mut on_stack = BTreeSet::from([entry]);
+    let mut stack = vec![(entry, 0)];
+    while let Some((b, i)) = stack.pop() {
+        let successors = blocks[&b].1.successors();
+        match successors.get(i) {
+            None => {
+                on_stack.remove(&b);
+            }
+            Some(&s) => {
+                stack.push((b, i + 1));
+                if on_stack.contains(&s) {
+                    back_edges.push((b, s));
+                } else if visited.insert(s) {
+                    on_stack.insert(s);
+                    stack.push((s, 0));
+                }
+            }
+        }
+    }
+    let mut loops: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+    for (source, header) in back_edges {
+        let body = loops
+            .entry(header)
+            .or_insert_with(|| BTreeSet::from([header]));
+        let mut worklist = vec![source];
+        while let Some(b) = worklist.pop() {
+            if body.insert(b) {
+                worklist.extend(predecessors.get(&b).into_iter().flatten());
+            }
+        }
+    }
+    loops
+}
+
+fn goto_targets(nodes: &[Node], targets: &mut BTreeSet<usize>) {
+    for node in nodes {
+        match node {
+            Node::Goto(target) => {
+                targets.insert(*target);
+            }
+            Node::If(_, then, otherwise) => {
+                goto_targets(then, targets);
+                goto_targets(otherwise, targets);
+            }
+            Node::Loop(body) | Node::While(_, body) => goto_targets(body, targets),
+            _ => (),
+        }
+    }
+}
+
+/// Remove the unused labels and make conditionals and loops read naturally:
+/// a loop starting by leaving it on a condition becomes a `while`.
+fn tidy(nodes: Vec<Node>, targets: &BTreeSet<usize>) -> Vec<Node> {
+    let mut result = Vec::new();
+    for node in nodes {
+        match node {
+            Node::Label(block) if !targets.contains(&block) => (),
+            Node::If(condition, then, otherwise) => {
+                let (then, otherwise) = (tidy(then, targets), tidy(otherwise, targets));
+                match (then.is_empty(), otherwise.is_empty()) {
+                    (true, true) => (),
+                    (true, false) => result.push(Node::If(condition.negate(), otherwise, then)),
+                    _ => result.push(Node::If(condition, then, otherwise)),
+                }
+            }
+            Node::Loop(body) => {
+                let mut body = tidy(body, targets);
+                let node = match body.first() {
+                    Some(Node::If(_, then, otherwise))
+                        if matches!(otherwise[..], [Node::Break])
+                            || matches!(then[..], [Node::Break]) =>
+                    {
+                        let Node::If(condition, then, otherwise) = body.remove(0) else {
+                            unreachable!()
+                        };
+                        let (condition, mut inside) = match then[..] {
+                            [Node::Break] => (condition.negate(), otherwise),
+                            _ => (condition, then),
+                        };
+                        inside.extend(body);
+                        Node::While(condition, strip_continue(inside))
+                    }
+                    _ => Node::Loop(strip_continue(body)),
+                };
+                result.push(node);
+            }
+            node => result.push(node),
+        }
+    }
+    result
+}
+
+/// Remove the `continue` statements at the end of a loop body.
+fn strip_continue(mut body: Vec<Node>) -> Vec<Node> {
+    match body.pop() {
+        Some(Node::Continue) => return strip_continue(body),
+        Some(Node::If(condition, then, otherwise)) => {
+            let (then, otherwise) = (strip_continue(then), strip_continue(otherwise));
+            match (then.is_empty(), otherwise.is_empty()) {
+                (true, true) => (),
+                (true, false) => body.push(Node::If(condition.negate(), otherwise, then)),
+                _ => body.push(Node::If(condition, then, otherwise)),
+            }
+        }
+        Some(node) => body.push(node),
+        None => (),
+    }
+    body
+}
+
+fn print(nodes: &[Node], depth: usize, label: &dyn Fn(usize) -> String, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    for node in nodes {
+        match node {
+            Node::Line(line) | Node::End(line) => writeln!(out, "{indent}{line}").unwrap(),
+            Node::If(condition, then, otherwise) => {
+                writeln!(out, "{indent}if {} {{", condition.text()).unwrap();
+                print(then, depth + 1, label, out);
+                let mut otherwise = otherwise;
+                // `else if` chains
+                while let [Node::If(condition, then, rest)] = &otherwise[..] {
+                    writeln!(out, "{indent}}} else if {} {{", condition.text()).unwrap();
+                    print(then, depth + 1, label, out);
+                    otherwise = rest;
+                }
+                if !otherwise.is_empty() {
+                    writeln!(out, "{indent}}} else {{").unwrap();
+                    print(otherwise, depth + 1, label, out);
+                }
+                writeln!(out, "{indent}}}").unwrap();
+            }
+            Node::Loop(body) => {
+                writeln!(out, "{indent}loop {{").unwrap();
+                print(body, depth + 1, label, out);
+                writeln!(out, "{indent}}}").unwrap();
+            }
+            Node::While(condition, body) => {
+                writeln!(out, "{indent}while {} {{", condition.text()).unwrap();
+                print(body, depth + 1, label, out);
+                writeln!(out, "{indent}}}").unwrap();
+            }
+            Node::Break => writeln!(out, "{indent}break;").unwrap(),
+            Node::Continue => writeln!(out, "{indent}continue;").unwrap(),
+            Node::Goto(block) => writeln!(out, "{indent}goto {};", label(*block)).unwrap(),
+            Node::Label(block) => writeln!(out, "{}:", label(*block)).unwrap(),
+        }
+    }
+}
+
+fn names(registers: Registers) -> Vec<String> {
+    (0..NREGS)
+        .filter(|r| registers & (1 << r) != 0)
+        .map(register)
+        .collect()
+}
+
+/// Decompile a program into structured pseudo-code, to understand what it
+/// does without a listing.
+///
+/// Like for [verify](crate::verify), the program is the content of an
+/// image file or a raw program. It is split into functions: the code
+/// starting at its entry point, and the targets of the calls found by
+/// `verify`. Each one is shown with the registers it reads as parameters,
+/// and after `->` the registers it writes which its callers read. In a
+/// function:
+///   - `sp` is the stack pointer `r2`, and the stack slots are named from
+///     the stack pointer on entry: `arg1`, `arg2`... above the return
+///     address, `local1`, `local2`... below. Setting `sp` to a constant
+///     starts naming slots again from there,
+///   - pushing and popping are not shown, nor the registers saved on
+///     entry and restored before returning,
+///   - the loads of constants and the intermediate results are folded into
+///     the expressions which use them, and the values which are never read
+///     are not shown,
+///   - `mem[address]` is the word of memory at `address`, `print` and
+///     `putchar` are `out_number` and `out`,
+///   - the jumps become `if`, `while` and `loop` statements when possible,
+///     and `goto` statements otherwise.
+///
+/// Calls are assumed to leave the stack as they found it, and functions not
+/// to write to the stack of their callers unless through an address they
+/// compute.
+///
+/// With debug information, functions and `goto` targets are named after
+/// their label, and so are the constants of `loadimm` which are relocated
+/// or jump targets.
+pub fn decompile(
+    program: &[u8],
+    debug_info: Option<&DebugInfo>,
+) -> Result<String, Vec<Diagnostic>> {
+    let Program { memory, entry, .. } = load_program(program)?;
+    let exploration = explore(&memory, entry);
+    let returns = exploration
+        .calls
+        .keys()
+        .map(|&address| (address + exploration.lengths[&address]) as u32)
+        .collect();
+    let entries: BTreeSet<usize> = [entry]
+        .into_iter()
+        .chain(exploration.calls.values().copied())
+        .collect();
+    let mut decompiler = Decompiler {
+        memory,
+        entry,
+        lengths: exploration.lengths,
+        targets: exploration.targets,
+        calls: exploration.calls,
+        returns,
+        summaries: BTreeMap::new(),
+        debug_info,
+    };
+
+    // What calls preserve, assuming the best at first
+    loop {
+        let mut changed = false;
+        for &entry in &entries {
+            let found = decompiler.summarize(&decompiler.analyze(entry));
+            let summary = decompiler.summaries.entry(entry).or_default();
+            let mut merged = summary.clone();
+            for (known, found) in merged.preserved.iter_mut().zip(found.preserved) {
+                *known &= found;
+            }
+            merged.balanced &= found.balanced;
+            merged.clobbers |= found.clobbers;
+            if merged != *summary {
+                *summary = merged;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let mut functions: BTreeMap<usize, Blocks> = entries
+        .iter()
+        .map(|&entry| (entry, decompiler.blocks(entry)))
+        .collect();
+    let interface = loop {
+        let interface = Interface::new(&functions);
+        let mut changed = false;
+        for (&function, blocks) in &mut functions {
+            changed |= simplify(function, blocks, &interface);
+        }
+        if !changed {
+            break interface;
+        }
+    };
+
+    let render = |line: &Line| match line {
+        Line::Assign(place, value) => {
+            let place = match place {
+                Place::Register(r) => register(*r),
+                Place::Slot(offset) => slot(*offset),
+                Place::Memory(address) => format!("mem[{}]", address.text()),
+            };
+            format!("{place} = {};", value.text())
+        }
+        Line::AssignIf(r, value, condition) => {
+            let condition = Condition {
+                value: condition.clone(),
+                zero: false,
+            };
+            match *r {
+                IP => format!("if {} {{ goto *{}; }}", condition.text(), value.text()),
+                r => format!(
+                    "if {} {{ {} = {}; }}",
+                    condition.text(),
+                    register(r),
+                    value.text()
+                ),
+            }
+        }
+        Line::Call(function) => {
+            let inputs = names(interface.inputs[function] & !(1 << SP));
+            format!(
+                "{}({});",
+                decompiler.function_name(*function),
+                inputs.join(", ")
+            )
+        }
+        Line::Builtin(name, value) => {
+            let value = value.as_ref().map(Expr::text).unwrap_or_default();
+            format!("{name}({value});")
+        }
+    };
+    let end = |exit: &Terminator| match exit {
+        Terminator::Return => "return;".to_string(),
+        Terminator::Jump(target) => format!("goto *{};", target.text()),
+        Terminator::Exit(None) => "exit(0);".to_string(),
+        Terminator::Exit(Some(code)) => format!("exit({});", code.text()),
+        Terminator::Invalid(address) => format!("// invalid instruction at {address:04}"),
+        Terminator::Goto(_) | Terminator::Branch(..) => unreachable!(),
+    };
+    let label = |block: usize| match decompiler.label(block) {
+        Some(label) => label.to_string(),
+        None => format!("L{block:04}"),
+    };
+
+    let mut source = String::new();
+    for (&function, blocks) in &functions {
+        let rendered: BTreeMap<usize, (Vec<String>, Terminator)> = blocks
+            .iter()
+            .map(|(&b, block)| {
+                (
+                    b,
+                    (block.lines.iter().map(render).collect(), block.exit.clone()),
+                )
+            })
+            .collect();
+        let mut structurer = Structurer {
+            blocks: &rendered,
+            join: joins(&rendered),
+            loops: loops(function, &rendered),
+            emitted: BTreeSet::new(),
+            stack: Vec::new(),
+            end: &end,
+        };
+        let mut nodes = Vec::new();
+        structurer.region(function, None, &mut nodes);
+        let mut targets = BTreeSet::new();
+        goto_targets(&nodes, &mut targets);
+        // The entry is not a target of the body
+        targets.remove(&function);
+        let nodes = tidy(nodes, &targets);
+
+        if !source.is_empty() {
+            writeln!(source).unwrap();
+        }
+        let inputs = names(interface.inputs[&function] & !(1 << SP));
+        let outputs = interface.outputs.get(&function).copied().unwrap_or(0);
+        let outputs = names(outputs & !(1 << SP));
+        write!(
+            source,
+            "fn {}({})",
+            decompiler.function_name(function),
+            inputs.join(", ")
+        )
+        .unwrap();
+        if !outputs.is_empty() {
+            write!(source, " -> {}", outputs.join(", ")).unwrap();
+        }
+        writeln!(source, " {{  // {function:04}").unwrap();
+        print(&nodes, 1, &label, &mut source);
+        writeln!(source, "}}").unwrap();
+    }
+    Ok(source)
+}
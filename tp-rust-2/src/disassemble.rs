@@ -0,0 +1,159 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::debug::DebugInfo;
+use crate::machine::InstructionType;
+use crate::verify::{explore, load_program, Diagnostic, Program};
+
+/// The label naming the immediate of the `loadimm` at `address`, when it
+/// is relocated or is one of the jump `targets`. Other immediates are
+/// numbers which merely happen to equal the address of a label.
+pub(crate) fn immediate_label<'a>(
+    instruction: &[u8],
+    address: usize,
+    debug_info: Option<&'a DebugInfo>,
+    targets: &BTreeSet<usize>,
+) -> Option<&'a str> {
+    let info = debug_info?;
+    let imm = i16::from_le_bytes([*instruction.get(2)?, *instruction.get(3)?]);
+    if !info.relocated(address + 2) && !targets.contains(&(imm as usize)) {
+        return None;
+    }
+    let label = info
+        .labels
+        .iter()
+        .find(|l| l.address as i64 == imm as i64)?;
+    Some(&label.name)
+}
+
+/// Returns the `.dis` syntax of an instruction, showing the immediate of
+/// `loadimm` as `label` when there is one.
+pub(crate) fn describe(
+    instruction_type: InstructionType,
+    instruction: &[u8],
+    label: Option<&str>,
+) -> String {
+    let r = |i: usize| format!("r{}", instruction[i]);
+    match instruction_type {
+        InstructionType::MoveIf => format!("move {} <- {} if {} != 0", r(1), r(2), r(3)),
+        InstructionType::Store => format!("store [{}] <- {}", r(1), r(2)),
+        InstructionType::Load => format!("load {} <- [{}]", r(1), r(2)),
+        InstructionType::LoadImm => {
+            let imm = i16::from_le_bytes([instruction[2], instruction[3]]);
+            match label {
+                Some(label) => format!("loadimm {} <- #{label}", r(1)),
+                None => format!("loadimm {} <- #{imm}", r(1)),
+            }
+        }
+        InstructionType::Sub => format!("sub {} <- {} - {}", r(1), r(2), r(3)),
+        InstructionType::Out => format!("out {}", r(1)),
+        InstructionType::Exit => "exit".to_string(),
+        InstructionType::ExitWith => format!("exit {}", r(1)),
+        InstructionType::OutNumber => format!("out_number {}", r(1)),
+        InstructionType::Brk => "brk".to_string(),
+    }
+}
+
+/// Returns the `.dis` syntax of the instruction at the beginning of
+/// `bytes`, found at `address`, or `None` if they do not start with a whole
+/// instruction. Unlike in [disassemble], the jump targets are not known and
+/// only relocated immediates are shown as labels.
+pub fn disassemble_instruction(
+    bytes: &[u8],
+    address: usize,
+    debug_info: Option<&DebugInfo>,
+) -> Option<String> {
+    let instruction_type = InstructionType::from_opcode(*bytes.first()?).ok()?;
+    let instruction = bytes.get(..instruction_type.length())?;
+    let label = immediate_label(instruction, address, debug_info, &BTreeSet::new());
+    Some(describe(instruction_type, instruction, label))
+}
+
+/// Returns the `.dis` syntax of data: a string such as `b'Hello\n'` when
+/// it is text, a list of bytes such as `[0, 1]` otherwise.
+fn describe_data(bytes: &[u8]) -> String {
+    let text = bytes
+        .iter()
+        .all(|&b| b.is_ascii_graphic() || b" \n\t\r".contains(&b));
+    if !text {
+        return format!("{bytes:?}");
+    }
+    let mut string = "b'".to_string();
+    for &b in bytes {
+        match b {
+            b'\n' => string.push_str("\\n"),
+            b'\t' => string.push_str("\\t"),
+            b'\r' => string.push_str("\\r"),
+            b'\\' | b'\'' => {
+                string.push('\\');
+                string.push(b as char);
+            }
+            _ => string.push(b as char),
+        }
+    }
+    string.push('\'');
+    string
+}
+
+/// List a program in the syntax of the `.dis` listings, from address 0 to
+/// the end of its last section. Like for [verify](crate::verify), the
+/// program is the content of an image file or a raw program. The
+/// instructions are those found by `verify`, the other bytes are shown as
+/// data with `????` as address.
+///
+/// With debug information, the labels are listed before the line they
+/// designate, the immediates which are relocated or are jump targets are
+/// shown as labels and data is
+/// split along source lines, so that the listing can be assembled back
+/// into the same program.
+pub fn disassemble(
+    program: &[u8],
+    debug_info: Option<&DebugInfo>,
+) -> Result<String, Vec<Diagnostic>> {
+    let Program { memory, end, entry } = load_program(program)?;
+    let program = &memory[..end];
+    let exploration = explore(&memory, entry);
+    let lengths = exploration.lengths;
+    let instruction_at = |address: usize| {
+        let length = *lengths.get(&address)?;
+        let instruction_type = InstructionType::from_opcode(program[address]).ok()?;
+        (address + length <= program.len()).then_some(instruction_type)
+    };
+    let boundary = |address: usize| match debug_info {
+        Some(info) => {
+            info.labels.iter().any(|l| l.address as usize == address)
+                || info.lines.iter().any(|l| l.address as usize == address)
+        }
+        None => false,
+    };
+
+    let mut listing = String::new();
+    let mut address = 0;
+    while address < program.len() {
+        if let Some(info) = debug_info {
+            for label in info.labels.iter().filter(|l| l.address as usize == address) {
+                writeln!(listing, "{}:", label.name).unwrap();
+            }
+        }
+        if let Some(instruction_type) = instruction_at(address) {
+            let instruction = &program[address..address + instruction_type.length()];
+            let label = immediate_label(instruction, address, debug_info, &exploration.targets);
+            let text = describe(instruction_type, instruction, label);
+            writeln!(listing, "  {address:04}   {text}").unwrap();
+            address += instruction.len();
+            continue;
+        }
+        let start = address;
+        address += 1;
+        while address < program.len() && instruction_at(address).is_none() && !boundary(address) {
+            address += 1;
+        }
+        writeln!(
+            listing,
+            "  ???? {}",
+            describe_data(&program[start..address])
+        )
+        .unwrap();
+    }
+    Ok(listing)
+}
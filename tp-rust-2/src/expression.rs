@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// An arithmetic expression of the assembler, such as `label+4` or
+/// `(SIZE*4)`. Names are looked up when it is evaluated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Expr {
+    Number(i64),
+    /// A label or a constant defined with `.equ`.
+    Name(String),
+    Negate(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Why an expression cannot be evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EvaluationError {
+    /// Addresses are multiplied or divided.
+    NotRelocatable,
+    DivisionByZero,
+}
+
+/// What a name designates when evaluating an expression.
+pub(crate) enum Binding {
+    Constant(i64),
+    /// A label of the module being assembled, at this offset.
+    Local(i64),
+    /// A label of another module.
+    External,
+}
+
+/// The value of an expression, once the names are known: `constant`, plus
+/// `base` times the address of the module, plus the addresses of the
+/// `external` labels times their coefficient (which is never 0).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Value {
+    pub(crate) constant: i64,
+    pub(crate) base: i64,
+    pub(crate) external: BTreeMap<String, i64>,
+}
+
+impl Value {
+    fn constant(constant: i64) -> Value {
+        Value {
+            constant,
+            ..Value::default()
+        }
+    }
+
+    /// The value if it does not depend on the placement of modules.
+    pub(crate) fn as_constant(&self) -> Option<i64> {
+        (self.base == 0 && self.external.is_empty()).then_some(self.constant)
+    }
+
+    fn scale(mut self, factor: i64) -> Value {
+        self.constant = self.constant.wrapping_mul(factor);
+        self.base = self.base.wrapping_mul(factor);
+        self.external
+            .values_mut()
+            .for_each(|c| *c = c.wrapping_mul(factor));
+        self.external.retain(|_, c| *c != 0);
+        self
+    }
+
+    fn add(mut self, other: Value) -> Value {
+        self.constant = self.constant.wrapping_add(other.constant);
+        self.base = self.base.wrapping_add(other.base);
+        for (name, coefficient) in other.external {
+            let sum = self.external.entry(name).or_insert(0);
+            *sum = sum.wrapping_add(coefficient);
+        }
+        self.external.retain(|_, c| *c != 0);
+        self
+    }
+}
+
+impl Expr {
+    /// Tells whether the expression is made of numbers only.
+    pub(crate) fn is_number(&self) -> bool {
+        match self {
+            Expr::Number(_) => true,
+            Expr::Name(_) => false,
+            Expr::Negate(e) => e.is_number(),
+            Expr::Binary(_, l, r) => l.is_number() && r.is_number(),
+        }
+    }
+
+    /// The names in the expression, from left to right.
+    pub(crate) fn names(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Name(name) => vec![name],
+            Expr::Negate(e) => e.names(),
+            Expr::Binary(_, l, r) => [l.names(), r.names()].concat(),
+        }
+    }
+
+    /// Evaluate the expression, with `lookup` telling what names designate.
+    pub(crate) fn evaluate(
+        &self,
+        lookup: &dyn Fn(&str) -> Binding,
+    ) -> Result<Value, EvaluationError> {
+        Ok(match self {
+            Expr::Number(n) => Value::constant(*n),
+            Expr::Name(name) => match lookup(name) {
+                Binding::Constant(value) => Value::constant(value),
+                Binding::Local(offset) => Value {
+                    constant: offset,
+                    base: 1,
+                    ..Value::default()
+                },
+                Binding::External => Value {
+                    external: BTreeMap::from([(name.clone(), 1)]),
+                    ..Value::default()
+                },
+            },
+            Expr::Negate(e) => e.evaluate(lookup)?.scale(-1),
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.evaluate(lookup)?, r.evaluate(lookup)?);
+                match (op, l.as_constant(), r.as_constant()) {
+                    (Op::Add, _, _) => l.add(r),
+                    (Op::Sub, _, _) => l.add(r.scale(-1)),
+                    (Op::Mul, Some(factor), _) => r.scale(factor),
+                    (Op::Mul, _, Some(factor)) => l.scale(factor),
+                    (Op::Div, Some(_), Some(0)) => return Err(EvaluationError::DivisionByZero),
+                    (Op::Div, Some(l), Some(r)) => Value::constant(l.wrapping_div(r)),
+                    _ => return Err(EvaluationError::NotRelocatable),
+                }
+            }
+        })
+    }
+}
+
+/// Writes the expression in the syntax of the assembler, with parentheses
+/// around every operation.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{n}"),
+            Expr::Name(name) => write!(f, "{name}"),
+            Expr::Negate(e) => write!(f, "-({e})"),
+            Expr::Binary(op, l, r) => {
+                let op = match op {
+                    Op::Add => "+",
+                    Op::Sub => "-",
+                    Op::Mul => "*",
+                    Op::Div => "/",
+                };
+                write!(f, "({l} {op} {r})")
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::fmt;
+
+use crate::machine::MEMORY_SIZE;
+
+/// First bytes of an image file. Files not starting with them are raw
+/// programs.
+pub const IMAGE_MAGIC: &[u8; 4] = b"TPVM";
+/// Version of the image format written by [Image::to_bytes].
+pub const IMAGE_VERSION: u16 = 1;
+
+/// A program along with where to load it, as stored in an image file.
+///
+/// The file starts with a header, all numbers being little-endian:
+///   - [IMAGE_MAGIC], then the version on 2 bytes,
+///   - the number of sections on 2 bytes,
+///   - the entry point on 4 bytes,
+///   - the number of symbols on 4 bytes.
+///
+/// Each section follows with its kind on 1 byte (0 for code, 1 for data,
+/// 2 for bss), 3 zero bytes, its load address and its size on 4 bytes each,
+/// then its content unless it is a bss section. Each symbol follows with
+/// its address on 4 bytes, the length of its name on 2 bytes and its
+/// UTF-8 name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    /// Initial value of the IP.
+    pub entry: u32,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    /// Instructions.
+    Code,
+    /// Initialized data.
+    Data,
+    /// Data initialized to zero, whose content is not stored.
+    Bss,
+}
+
+/// Bytes to load at a given address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub address: u32,
+    /// Size of the section in memory.
+    pub size: u32,
+    /// Content of the section, empty for bss sections.
+    pub bytes: Vec<u8>,
+}
+
+/// A name given to an address, for tools such as debuggers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+}
+
+/// Why an image or object file could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageError {
+    /// The file does not start with the expected magic bytes.
+    InvalidMagic,
+    /// The file ends in the middle of the header, a section or a symbol.
+    Truncated,
+    UnsupportedVersion(u16),
+    InvalidSectionKind(u8),
+    /// A section does not fit in the machine memory.
+    SectionOutOfMemory {
+        address: u32,
+        size: u32,
+    },
+    /// The content of a section is not as long as its size, or a bss
+    /// section has a content.
+    SectionContentMismatch {
+        address: u32,
+        size: u32,
+        stored: usize,
+    },
+    /// Two sections share some addresses.
+    OverlappingSections {
+        first: u32,
+        second: u32,
+    },
+    InvalidSymbolName,
+    /// A raw program does not fit in the machine memory.
+    ProgramTooLarge(usize),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::InvalidMagic => write!(f, "not an image file"),
+            ImageError::Truncated => write!(f, "truncated image"),
+            ImageError::UnsupportedVersion(version) => {
+                write!(f, "unsupported image version {version}")
+            }
+            ImageError::InvalidSectionKind(kind) => write!(f, "invalid section kind {kind}"),
+            ImageError::SectionOutOfMemory { address, size } => write!(
+                f,
+                "section of {size} bytes at address {address} does not fit in memory"
+            ),
+            ImageError::SectionContentMismatch {
+                address,
+                size,
+                stored,
+            } => write!(
+                f,
+                "section of {size} bytes at address {address} has {stored} bytes of content"
+            ),
+            ImageError::OverlappingSections { first, second } => {
+                write!(f, "sections at addresses {first} and {second} overlap")
+            }
+            ImageError::InvalidSymbolName => write!(f, "symbol name is not valid UTF-8"),
+            ImageError::ProgramTooLarge(size) => write!(
+                f,
+                "program is {size} bytes long but memory is {MEMORY_SIZE} bytes"
+            ),
+        }
+    }
+}
+
+impl SectionKind {
+    fn from_byte(kind: u8) -> Result<SectionKind, ImageError> {
+        match kind {
+            0 => Ok(SectionKind::Code),
+            1 => Ok(SectionKind::Data),
+            2 => Ok(SectionKind::Bss),
+            _ => Err(ImageError::InvalidSectionKind(kind)),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            SectionKind::Code => 0,
+            SectionKind::Data => 1,
+            SectionKind::Bss => 2,
+        }
+    }
+}
+
+impl Section {
+    /// The content of the section once loaded. Bss sections are filled
+    /// with zeros.
+    pub fn content(&self) -> Vec<u8> {
+        let mut content = self.bytes.clone();
+        content.resize(self.size as usize, 0);
+        content
+    }
+}
+
+/// Reads the numbers and bytes of an image or object file in order.
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, length: usize) -> Result<&'a [u8], ImageError> {
+        if self.bytes.len() < length {
+            return Err(ImageError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, ImageError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, ImageError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, ImageError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A name preceded by its length on 2 bytes.
+    pub(crate) fn name(&mut self) -> Result<String, ImageError> {
+        let length = self.u16()?;
+        let name = std::str::from_utf8(self.take(length as usize)?)
+            .map_err(|_| ImageError::InvalidSymbolName)?;
+        Ok(name.to_string())
+    }
+}
+
+/// Append a name preceded by its length on 2 bytes.
+pub(crate) fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+impl Image {
+    /// An image loading `program` at address 0 and starting there, as a
+    /// raw program is run.
+    pub fn from_raw(program: &[u8]) -> Result<Image, ImageError> {
+        if program.len() > MEMORY_SIZE {
+            return Err(ImageError::ProgramTooLarge(program.len()));
+        }
+        Ok(Image {
+            entry: 0,
+            sections: vec![Section {
+                kind: SectionKind::Code,
+                address: 0,
+                size: program.len() as u32,
+                bytes: program.to_vec(),
+            }],
+            symbols: Vec::new(),
+        })
+    }
+
+    /// Read an image file, or a raw program if `bytes` does not start with
+    /// [IMAGE_MAGIC].
+    pub fn load(bytes: &[u8]) -> Result<Image, ImageError> {
+        if bytes.starts_with(IMAGE_MAGIC) {
+            Image::parse(bytes)
+        } else {
+            Image::from_raw(bytes)
+        }
+    }
+
+    /// Read an image file, which must pass [check](Image::check).
+    pub fn parse(bytes: &[u8]) -> Result<Image, ImageError> {
+        let mut reader = Reader { bytes };
+        if !bytes.starts_with(IMAGE_MAGIC) {
+            return Err(ImageError::InvalidMagic);
+        }
+        reader.take(4)?;
+        let version = reader.u16()?;
+        if version != IMAGE_VERSION {
+            return Err(ImageError::UnsupportedVersion(version));
+        }
+        let section_count = reader.u16()?;
+        let entry = reader.u32()?;
+        let symbol_count = reader.u32()?;
+
+        let mut sections = Vec::new();
+        for _ in 0..section_count {
+            let kind = SectionKind::from_byte(reader.u8()?)?;
+            reader.take(3)?;
+            let address = reader.u32()?;
+            let size = reader.u32()?;
+            let bytes = match kind {
+                SectionKind::Bss => Vec::new(),
+                _ => reader.take(size as usize)?.to_vec(),
+            };
+            sections.push(Section {
+                kind,
+                address,
+                size,
+                bytes,
+            });
+        }
+        let mut symbols = Vec::new();
+        for _ in 0..symbol_count {
+            let address = reader.u32()?;
+            let name = reader.name()?;
+            symbols.push(Symbol { name, address });
+        }
+
+        let image = Image {
+            entry,
+            sections,
+            symbols,
+        };
+        image.check()?;
+        Ok(image)
+    }
+
+    /// Check that the sections fit in memory without overlapping, and that
+    /// their content is as long as their size, or empty for bss sections.
+    pub fn check(&self) -> Result<(), ImageError> {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for section in &self.sections {
+            let (address, size) = (section.address, section.size);
+            let expected = match section.kind {
+                SectionKind::Bss => 0,
+                _ => size as usize,
+            };
+            if section.bytes.len() != expected {
+                return Err(ImageError::SectionContentMismatch {
+                    address,
+                    size,
+                    stored: section.bytes.len(),
+                });
+            }
+            match address.checked_add(size) {
+                Some(end) if end as usize <= MEMORY_SIZE => ranges.push((address, end)),
+                _ => return Err(ImageError::SectionOutOfMemory { address, size }),
+            }
+        }
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            if pair[1].0 < pair[0].1 {
+                return Err(ImageError::OverlappingSections {
+                    first: pair[0].0,
+                    second: pair[1].0,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The image file content.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = IMAGE_MAGIC.to_vec();
+        bytes.extend_from_slice(&IMAGE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.sections.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.entry.to_le_bytes());
+        bytes.extend_from_slice(&(self.symbols.len() as u32).to_le_bytes());
+        for section in &self.sections {
+            bytes.extend_from_slice(&[section.kind.to_byte(), 0, 0, 0]);
+            bytes.extend_from_slice(&section.address.to_le_bytes());
+            bytes.extend_from_slice(&section.size.to_le_bytes());
+            if section.kind != SectionKind::Bss {
+                bytes.extend_from_slice(&section.content());
+            }
+        }
+        for symbol in &self.symbols {
+            bytes.extend_from_slice(&symbol.address.to_le_bytes());
+            write_name(&mut bytes, &symbol.name);
+        }
+        bytes
+    }
+}
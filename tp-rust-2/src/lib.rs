@@ -1,3 +1,38 @@
+mod arguments;
+mod assemble;
+mod compile;
+mod debug;
+mod decompile;
+mod disassemble;
+mod expression;
+mod image;
+mod link;
+mod loop_detection;
 mod machine;
+mod memory;
+mod object;
+mod optimize;
+mod protection;
+mod suggest;
+mod translate;
+mod unit_test;
+mod verify;
+mod watchpoint;
 
+pub use arguments::{ARGC_REGISTER, ARGV_REGISTER, ENVP_REGISTER, STACK_REGISTER};
+pub use assemble::*;
+pub use compile::*;
+pub use debug::*;
+pub use decompile::decompile;
+pub use disassemble::{disassemble, disassemble_instruction};
+pub use image::*;
+pub use link::*;
 pub use machine::*;
+pub use memory::MemoryPolicy;
+pub use object::*;
+pub use optimize::optimize;
+pub use protection::*;
+pub use translate::*;
+pub use unit_test::*;
+pub use verify::*;
+pub use watchpoint::*;
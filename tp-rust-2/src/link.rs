@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::debug::{DebugInfo, SourceLine};
+use crate::image::{Image, Section, SectionKind, Symbol};
+use crate::machine::MEMORY_SIZE;
+use crate::object::Object;
+use crate::suggest::suggest;
+
+/// A problem found by [link]. Modules are numbered from 0 in the order
+/// they are given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// Several modules define the same global symbol.
+    DuplicateSymbol(String),
+    /// A relocation refers to a symbol defined neither in its module nor
+    /// as a global symbol. The source file and line of the immediate are
+    /// given when the module records them, and a close name is suggested,
+    /// if any.
+    UndefinedSymbol {
+        module: usize,
+        source: String,
+        line: Option<u32>,
+        name: String,
+        suggestion: Option<String>,
+    },
+    /// A relocation does not designate 2 bytes of its module.
+    InvalidRelocation { module: usize, offset: u32 },
+    /// The value of a relocation does not fit in 16 bits.
+    RelocationOutOfRange {
+        module: usize,
+        symbol: String,
+        value: i64,
+    },
+    /// The modules do not fit in the machine memory.
+    ProgramTooLarge(usize),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol(name) => {
+                write!(f, "symbol `{name}` is defined by several modules")
+            }
+            LinkError::UndefinedSymbol {
+                module,
+                source,
+                line,
+                name,
+                suggestion,
+            } => {
+                match (source.is_empty(), line) {
+                    (false, Some(line)) => write!(f, "{source}:{line}: ")?,
+                    (false, None) => write!(f, "{source}: ")?,
+                    (true, Some(line)) => write!(f, "module {module}, line {line}: ")?,
+                    (true, None) => write!(f, "module {module}: ")?,
+                }
+                write!(f, "undefined symbol `{name}`")?;
+                match suggestion {
+                    Some(suggestion) => write!(f, ", did you mean `{suggestion}`?"),
+                    None => Ok(()),
+                }
+            }
+            LinkError::InvalidRelocation { module, offset } => {
+                write!(f, "module {module}: invalid relocation at offset {offset}")
+            }
+            LinkError::RelocationOutOfRange {
+                module,
+                symbol,
+                value,
+            } => write!(
+                f,
+                "module {module}: value {value} of `{symbol}` does not fit in the 16 bits of `loadimm` (-32768 to 32767)"
+            ),
+            LinkError::ProgramTooLarge(size) => write!(
+                f,
+                "program is {size} bytes long but memory is {MEMORY_SIZE} bytes"
+            ),
+        }
+    }
+}
+
+/// The address of each module, followed by the size of the program.
+fn place(objects: &[Object]) -> (Vec<usize>, usize) {
+    let mut bases = Vec::new();
+    let mut size: usize = 0;
+    for object in objects {
+        size = size.next_multiple_of(object.alignment.max(1) as usize);
+        bases.push(size);
+        size += object.bytes.len();
+    }
+    (bases, size)
+}
+
+/// Combine modules into an image running the first one from its
+/// beginning. The modules are placed one after the other from address 0,
+/// padded with zeros to honour their alignment. Then each relocation is
+/// replaced by the address of its symbol plus its addend, or by its addend
+/// minus the address if it is negated. Symbols are looked up in the module
+/// of the relocation first, then among the global symbols of every module.
+///
+/// The image keeps the global symbols.
+pub fn link(objects: &[Object]) -> Result<Image, Vec<LinkError>> {
+    let mut errors = Vec::new();
+    let (bases, size) = place(objects);
+    if size > MEMORY_SIZE {
+        return Err(vec![LinkError::ProgramTooLarge(size)]);
+    }
+
+    let mut globals: HashMap<&str, usize> = HashMap::new();
+    let mut symbols = Vec::new();
+    for (object, base) in objects.iter().zip(&bases) {
+        for symbol in object.symbols.iter().filter(|s| s.global) {
+            let address = base + symbol.offset as usize;
+            if globals.insert(&symbol.name, address).is_some() {
+                errors.push(LinkError::DuplicateSymbol(symbol.name.clone()));
+            }
+            symbols.push(Symbol {
+                name: symbol.name.clone(),
+                address: address as u32,
+            });
+        }
+    }
+
+    let mut bytes: Vec<u8> = Vec::new();
+    for (module, (object, &base)) in objects.iter().zip(&bases).enumerate() {
+        let mut code = object.bytes.clone();
+        for relocation in &object.relocations {
+            let offset = relocation.offset as usize;
+            if offset + 2 > code.len() {
+                errors.push(LinkError::InvalidRelocation {
+                    module,
+                    offset: relocation.offset,
+                });
+                continue;
+            }
+            let local = object.symbols.iter().find(|s| s.name == relocation.symbol);
+            let address = match local {
+                Some(symbol) => base + symbol.offset as usize,
+                None => match globals.get(relocation.symbol.as_str()) {
+                    Some(&address) => address,
+                    None => {
+                        let candidates = object.symbols.iter().map(|s| s.name.as_str());
+                        let line = object
+                            .lines
+                            .iter()
+                            .take_while(|entry| entry.offset <= relocation.offset)
+                            .last();
+                        let error = LinkError::UndefinedSymbol {
+                            module,
+                            source: object.source.clone(),
+                            line: line.map(|entry| entry.line),
+                            name: relocation.symbol.clone(),
+                            suggestion: suggest(
+                                &relocation.symbol,
+                                candidates.chain(globals.keys().copied()),
+                            ),
+                        };
+                        if !errors.contains(&error) {
+                            errors.push(error);
+                        }
+                        continue;
+                    }
+                },
+            };
+            let value = match relocation.negated {
+                true => relocation.addend as i64 - address as i64,
+                false => address as i64 + relocation.addend as i64,
+            };
+            match i16::try_from(value) {
+                Ok(value) => code[offset..offset + 2].copy_from_slice(&value.to_le_bytes()),
+                Err(_) => errors.push(LinkError::RelocationOutOfRange {
+                    module,
+                    symbol: relocation.symbol.clone(),
+                    value,
+                }),
+            }
+        }
+        bytes.resize(base, 0);
+        bytes.extend(code);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Image {
+        entry: 0,
+        sections: vec![Section {
+            kind: SectionKind::Code,
+            address: 0,
+            size: bytes.len() as u32,
+            bytes,
+        }],
+        symbols,
+    })
+}
+
+/// The debug information of the image made by [link] from the same modules:
+/// the labels of every module, the lines recorded by the assembler and the
+/// relocations which are not negated.
+pub fn debug_info(objects: &[Object]) -> DebugInfo {
+    let (bases, _) = place(objects);
+    let mut info = DebugInfo::default();
+    for (object, base) in objects.iter().zip(bases) {
+        info.labels
+            .extend(object.symbols.iter().map(|symbol| Symbol {
+                name: symbol.name.clone(),
+                address: (base + symbol.offset as usize) as u32,
+            }));
+        info.lines
+            .extend(object.lines.iter().map(|entry| SourceLine {
+                address: (base + entry.offset as usize) as u32,
+                file: object.source.clone(),
+                line: entry.line,
+            }));
+        info.relocations.extend(
+            object
+                .relocations
+                .iter()
+                .filter(|relocation| !relocation.negated)
+                .map(|relocation| (base + relocation.offset as usize) as u32),
+        );
+    }
+    info.labels.sort_by_key(|l| l.address);
+    info.relocations.sort();
+    info
+}
@@ -0,0 +1,80 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroU64;
+
+/// A copy of the registers and memory of a machine.
+pub(crate) struct Snapshot {
+    regs: Vec<u32>,
+    memory: Vec<u8>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(regs: &[u32], memory: &[u8]) -> Snapshot {
+        Snapshot {
+            regs: regs.to_vec(),
+            memory: memory.to_vec(),
+        }
+    }
+
+    /// Tells whether the snapshot matches the given registers and memory.
+    pub(crate) fn matches(&self, regs: &[u32], memory: &[u8]) -> bool {
+        self.regs == regs && self.memory == memory
+    }
+}
+
+fn hash_state(regs: &[u32], memory: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    regs.hash(&mut hasher);
+    memory.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Detects that a machine comes back to a state it has already been in,
+/// looking at its state every `interval` steps. Since the machine is
+/// deterministic, it will then loop forever.
+///
+/// Brent's algorithm is used: a sampled state is kept and compared with
+/// the following samples, and replaced by a new one after a number of
+/// samples which doubles each time, so that a cycle of any length is found
+/// while keeping a single copy of the memory.
+pub(crate) struct LoopDetector {
+    interval: u64,
+    steps: u64,
+    saved: Option<(u64, Snapshot)>,
+    samples: u64,
+    power: u64,
+}
+
+impl LoopDetector {
+    pub(crate) fn new(interval: NonZeroU64) -> LoopDetector {
+        LoopDetector {
+            interval: interval.get(),
+            steps: 0,
+            saved: None,
+            samples: 0,
+            power: 1,
+        }
+    }
+
+    /// To be called after each step. Returns `true` if the given state
+    /// has already been seen.
+    pub(crate) fn repeated(&mut self, regs: &[u32], memory: &[u8]) -> bool {
+        self.steps += 1;
+        if !self.steps.is_multiple_of(self.interval) {
+            return false;
+        }
+        let hash = hash_state(regs, memory);
+        if let Some((saved_hash, snapshot)) = &self.saved {
+            if *saved_hash == hash && snapshot.matches(regs, memory) {
+                return true;
+            }
+        }
+        self.samples += 1;
+        if self.saved.is_none() || self.samples == self.power {
+            self.saved = Some((hash, Snapshot::new(regs, memory)));
+            self.samples = 0;
+            self.power *= 2;
+        }
+        false
+    }
+}
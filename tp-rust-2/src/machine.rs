@@ -1,28 +1,65 @@
 use std::io::{self, Write};
+use std::num::NonZeroU64;
+use std::ops::Range;
 
+use crate::arguments::{self, ARGC_REGISTER, ARGV_REGISTER, ENVP_REGISTER, STACK_REGISTER};
+use crate::image::{Image, ImageError, SectionKind};
+use crate::loop_detection::{LoopDetector, Snapshot};
+use crate::memory::{Memory, MemoryPolicy};
+use crate::protection::{Access, Permissions, Region};
+use crate::watchpoint::{WatchKind, Watchpoint, WatchpointHit};
 
-const MEMORY_SIZE: usize = 4096;
-const NREGS: usize = 16;
 
-const IP: usize = 0;
+pub(crate) const MEMORY_SIZE: usize = 4096;
+pub(crate) const NREGS: usize = 16;
+
+pub(crate) const IP: usize = 0;
 
 #[derive(Debug)]
 pub struct Machine {
-    memory : [u8 ; MEMORY_SIZE],
+    memory : Memory,
     regs : [u32 ; NREGS],
+    loop_detection : Option<NonZeroU64>,
+    watchpoint_hit : Option<WatchpointHit>,
+    exit_code : Option<u32>,
+    last_ip : usize,
+    /// Whether the memory and registers are printed when the machine is
+    /// created and when the program exits.
+    verbose : bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum MachineError {
     InvalidRegister(usize),
     InvalidInstruction(u8),
     InvalidMemoryAddress(usize),
+    MisalignedAccess(usize),
     InsufficientPointerSize,
     WriteError,
+    ProtectionFault { address: usize, access: Access },
+    InfiniteLoop { ip: usize, period: u64 },
+    ArgumentsTooLarge,
+}
+
+/// Why [run_until_on](Machine::run_until_on) or [run_on](Machine::run_on)
+/// returned successfully.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program executed an exit instruction, with the given exit code:
+    /// the content of the register of `exit rA`, or 0 for `exit`.
+    Exited(u32),
+    /// The program executed the `brk` instruction at the given address.
+    /// Running the machine again resumes the execution after it.
+    Breakpoint(usize),
+    /// The maximum number of steps has been executed.
+    StepLimit,
+    /// The last instruction executed triggered a watchpoint. Running the
+    /// machine again resumes the execution with the next instruction.
+    Watchpoint(WatchpointHit),
 }
 
-#[derive(Copy, Clone)]
-enum InstructionType {
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum InstructionType {
     MoveIf,
     Store,
     Load,
@@ -31,6 +68,8 @@ enum InstructionType {
     Out,
     Exit,
     OutNumber,
+    Brk,
+    ExitWith,
 }
 
 impl Machine {
@@ -40,14 +79,25 @@ impl Machine {
     /// # Panics
     /// This function panics when `memory` is larger than the machine memory.
     pub fn new(memory: &[u8]) -> Self {
+        let machine = Machine::new_quiet(memory);
+        println!("\nCreating a virtual machine...\nmemory : {:?}, regs : {:?}", machine.memory.bytes(), machine.regs);
+        Machine{verbose : true, ..machine}
+    }
+
+    /// Create a new machine like [new](Machine::new), without printing the
+    /// memory and the registers when it is created and when the program
+    /// exits.
+    ///
+    /// # Panics
+    /// This function panics when `memory` is larger than the machine memory.
+    pub fn new_quiet(memory: &[u8]) -> Self {
 
         // building the memory
-        let mut array_memory : [u8 ; MEMORY_SIZE] = [0 ; MEMORY_SIZE];
         let memory_size :usize = memory.len();
         if memory_size > MEMORY_SIZE {
             panic!("memory is larger than the machine memory");
         }
-        array_memory[..memory_size].copy_from_slice(memory);
+        let memory :Memory = Memory::new(memory);
 
         let regs : [u32 ; NREGS] = [0 ; NREGS];
         // initializing the registers to example values
@@ -58,20 +108,215 @@ impl Machine {
         regs[4] = 0;
         regs[5] = 65;*/
 
-        println!("\nCreating a virtual machine...\nmemory : {array_memory:?}, regs : {regs:?}");
-        Machine{memory : array_memory, regs}
+        Machine{memory, regs, loop_detection : None, watchpoint_hit : None, exit_code : None, last_ip : 0, verbose : false}
     }
 
-    /// Run until the program terminates or until an error happens.
-    /// If output instructions are run, they print on `fd`.
-    pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<(), MachineError> {
-        while !self.step_on(fd)? {}
+    /// Create a new machine like [new](Machine::new), with the bytes of
+    /// `memory` marked as read-only code (RX) and the rest of the memory
+    /// marked as read-write data (RW).
+    ///
+    /// # Panics
+    /// This function panics when `memory` is larger than the machine memory.
+    pub fn new_protected(memory: &[u8]) -> Self {
+        let mut machine = Machine::new(memory);
+        machine.protect(memory.len()..MEMORY_SIZE, Permissions::RW).unwrap();
+        machine.protect(0..memory.len(), Permissions::RX).unwrap();
+        machine
+    }
+
+    /// Place the arguments and the `NAME=VALUE` environment variables at the
+    /// top of memory as nul-terminated strings, and set the registers
+    /// before running the program:
+    ///   - [ARGC_REGISTER] to the number of arguments,
+    ///   - [ARGV_REGISTER] to the address of a list of words pointing to
+    ///     the arguments, ended by a zero word,
+    ///   - [ENVP_REGISTER] to the address of a similar list for the
+    ///     environment variables,
+    ///   - [STACK_REGISTER] to the lowest address used, so that a stack
+    ///     growing down from there preserves the arguments.
+    ///
+    /// Programs using their arguments must not reset the stack pointer to
+    /// the end of memory. An
+    /// [ArgumentsTooLarge](MachineError::ArgumentsTooLarge) error is
+    /// returned if the arguments do not fit in memory.
+    pub fn set_arguments(&mut self, args: &[&str], env: &[(&str, &str)]) -> Result<(), MachineError> {
+        let arguments = arguments::lay_out(args, env).ok_or(MachineError::ArgumentsTooLarge)?;
+        self.memory.load(arguments.start, &arguments.bytes);
+        self.regs[ARGC_REGISTER] = arguments.argc as u32;
+        self.regs[ARGV_REGISTER] = arguments.argv as u32;
+        self.regs[ENVP_REGISTER] = arguments.envp as u32;
+        self.regs[STACK_REGISTER] = arguments.start as u32;
         Ok(())
     }
 
-    /// Run until the program terminates or until an error happens.
+    /// Create a new machine like [new](Machine::new), with the sections of
+    /// `image` loaded at their address and the IP set to its entry point.
+    pub fn from_image(image: &Image) -> Result<Self, ImageError> {
+        image.check()?;
+        let mut machine = Machine::new(&[]);
+        for section in &image.sections {
+            machine.memory.load(section.address as usize, &section.content());
+        }
+        machine.regs[IP] = image.entry;
+        Ok(machine)
+    }
+
+    /// Create a new machine like [from_image](Machine::from_image), with
+    /// the code sections marked as read-only code (RX) and the rest of the
+    /// memory marked as read-write data (RW).
+    pub fn from_image_protected(image: &Image) -> Result<Self, ImageError> {
+        let mut machine = Machine::from_image(image)?;
+        machine.protect(0..MEMORY_SIZE, Permissions::RW).unwrap();
+        for section in image.sections.iter().filter(|s| s.kind == SectionKind::Code) {
+            let start = section.address as usize;
+            machine.protect(start..start + section.size as usize, Permissions::RX).unwrap();
+        }
+        Ok(machine)
+    }
+
+    /// Give `permissions` to the addresses in `range`. When regions overlap,
+    /// the most recently added one wins. Addresses which are not part of any
+    /// region can be read, written and executed.
+    pub fn protect(&mut self, range: Range<usize>, permissions: Permissions) -> Result<(), MachineError> {
+        self.memory.protect(range, permissions)
+    }
+
+    /// The memory regions added with [protect](Machine::protect), oldest first.
+    pub fn regions(&self) -> &[Region] {
+        self.memory.regions()
+    }
+
+    /// Permissions of the given address.
+    pub fn permissions(&self, address: usize) -> Permissions {
+        self.memory.permissions(address)
+    }
+
+    /// Select how out-of-bounds and unaligned accesses are handled. The
+    /// default policy is [Strict](MemoryPolicy::Strict).
+    pub fn set_memory_policy(&mut self, policy: MemoryPolicy) {
+        self.memory.set_policy(policy);
+    }
+
+    /// The current memory access policy.
+    pub fn memory_policy(&self) -> MemoryPolicy {
+        self.memory.policy()
+    }
+
+    /// Enable or disable predecoding. When enabled, the instructions found
+    /// in memory are decoded at once, as well as those of the programs
+    /// loaded later, and kept in a cache until their bytes are overwritten.
+    /// Overwritten instructions are decoded again when executed. The
+    /// execution results are the same in both modes.
+    pub fn set_predecoding(&mut self, enabled: bool) {
+        self.memory.set_predecoding(enabled);
+    }
+
+    /// Tells whether predecoding is enabled.
+    pub fn predecoding(&self) -> bool {
+        self.memory.predecoding()
+    }
+
+    /// Enable infinite loop detection in [run_on](Machine::run_on) by
+    /// looking at the registers and memory every `interval` steps, or
+    /// disable it with `None`. Smaller intervals detect loops sooner but
+    /// slow down the execution.
+    pub fn set_loop_detection(&mut self, interval: Option<NonZeroU64>) {
+        self.loop_detection = interval;
+    }
+
+    /// The interval of the infinite loop detection, if enabled.
+    pub fn loop_detection(&self) -> Option<NonZeroU64> {
+        self.loop_detection
+    }
+
+    /// Pause [run_on](Machine::run_on) when `load` or `store` instructions
+    /// access some of the addresses in `range`, depending on `kind`.
+    /// Returns the identifier of the watchpoint.
+    pub fn add_watchpoint(&mut self, range: Range<usize>, kind: WatchKind) -> Result<usize, MachineError> {
+        self.memory.add_watchpoint(range, kind)
+    }
+
+    /// Remove the watchpoint with the given identifier, and return it if
+    /// it existed.
+    pub fn remove_watchpoint(&mut self, id: usize) -> Option<Watchpoint> {
+        self.memory.remove_watchpoint(id)
+    }
+
+    /// The current watchpoints, oldest first.
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.memory.watchpoints()
+    }
+
+    /// The watchpoint triggered by the last instruction executed with
+    /// [step_on](Machine::step_on), if any. It is cleared by this call.
+    pub fn take_watchpoint_hit(&mut self) -> Option<WatchpointHit> {
+        self.watchpoint_hit.take()
+    }
+
+    /// Run until the program terminates, a breakpoint or a watchpoint is
+    /// triggered, `max_steps` instructions have been executed or an error
+    /// happens. If output instructions are run, they print on `fd`.
+    ///
+    /// If loop detection is enabled and the machine comes back to the exact
+    /// same registers and memory, it would run forever, so an
+    /// [InfiniteLoop](MachineError::InfiniteLoop) error is returned with the
+    /// IP of the repeated state and the number of steps in the loop.
+    pub fn run_until_on<T: Write>(&mut self, fd: &mut T, max_steps: u64) -> Result<StopReason, MachineError> {
+        self.run_with_limit(fd, Some(max_steps))
+    }
+
+    /// Similar to [run_until_on](Machine::run_until_on).
+    /// If output instructions are run, they print on standard output.
+    pub fn run_until(&mut self, max_steps: u64) -> Result<StopReason, MachineError> {
+        self.run_until_on(&mut io::stdout().lock(), max_steps)
+    }
+
+    /// Similar to [run_until_on](Machine::run_until_on), without limiting
+    /// the number of steps.
+    pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<StopReason, MachineError> {
+        self.run_with_limit(fd, None)
+    }
+
+    fn run_with_limit<T: Write>(&mut self, fd: &mut T, max_steps: Option<u64>) -> Result<StopReason, MachineError> {
+        let mut detector :Option<LoopDetector> = self.loop_detection.map(LoopDetector::new);
+        let mut steps :u64 = 0;
+        loop {
+            if max_steps == Some(steps) {
+                return Ok(StopReason::StepLimit);
+            }
+            if let Some(reason) = self.execute(fd)? {
+                return Ok(reason);
+            }
+            steps += 1;
+            if let Some(hit) = self.watchpoint_hit.take() {
+                return Ok(StopReason::Watchpoint(hit));
+            }
+            if let Some(detector) = detector.as_mut() {
+                if detector.repeated(&self.regs, self.memory.bytes()) {
+                    return Err(MachineError::InfiniteLoop { ip: self.regs[IP] as usize, period: self.loop_period()? });
+                }
+            }
+        }
+    }
+
+    /// Returns the number of steps needed to come back to the current
+    /// state, which must be part of a loop. The output of those steps is
+    /// discarded since it has already been produced.
+    fn loop_period(&mut self) -> Result<u64, MachineError> {
+        let snapshot :Snapshot = Snapshot::new(&self.regs, self.memory.bytes());
+        let mut period :u64 = 0;
+        loop {
+            self.step_on(&mut io::sink())?;
+            period += 1;
+            if snapshot.matches(&self.regs, self.memory.bytes()) {
+                return Ok(period);
+            }
+        }
+    }
+
+    /// Similar to [run_on](Machine::run_on).
     /// If output instructions are run, they print on standard output.
-    pub fn run(&mut self) -> Result<(), MachineError> {
+    pub fn run(&mut self) -> Result<StopReason, MachineError> {
         self.run_on(&mut io::stdout().lock())
     }
 
@@ -86,22 +331,44 @@ impl Machine {
     ///
     /// In case of success, `true` is returned if the program is
     /// terminated (upon encountering an exit instruction), or
-    /// `false` if the execution must continue.
+    /// `false` if the execution must continue. A `brk` instruction does
+    /// nothing here: use [run_until_on](Machine::run_until_on) to know why
+    /// the execution stopped.
     pub fn step_on<T: Write>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
-        
-        // getting the length of the instruction
-        let _instruction_type :InstructionType = self.instruction_type()?;
-        let _instruction_length :usize = Machine::instruction_length(_instruction_type);
+        Ok(matches!(self.execute(fd)?, Some(StopReason::Exited(_))))
+    }
+
+    /// Execute the next instruction like [step_on](Machine::step_on).
+    /// Returns the reason to stop if it was an exit or a `brk` instruction.
+    fn execute<T: Write>(&mut self, fd: &mut T) -> Result<Option<StopReason>, MachineError> {
+
+        // forgetting about the watchpoints triggered by the previous instruction
+        self.watchpoint_hit = None;
+        self.memory.take_triggered();
 
-        // incrementing IP and fetching the instruction
+        // decoding the instruction, unless it has already been decoded
         let pc :usize = self.get_reg(IP)? as usize;
-        let instruction :&[u8];
-        let new_pc :Option<usize> = pc.checked_add(_instruction_length);
-        match new_pc {
-            Some(n) if n <= MEMORY_SIZE => {self.set_reg(IP, n as u32)?; instruction = &self.memory[pc..new_pc.unwrap()]},
-            Some(n) =>  {self.set_reg(IP, MEMORY_SIZE as u32)?; return Err(MachineError::InvalidMemoryAddress(n));},
-            None => {return Err(MachineError::InsufficientPointerSize);},
-        }
+        self.last_ip = pc;
+        let (_instruction_type, instruction) :(InstructionType, [u8 ; 4]) = match self.memory.decoded(pc) {
+            Some(decoded) => decoded,
+            None => {
+                let _instruction_type :InstructionType = self.instruction_type()?;
+                let instruction :[u8 ; 4] = match self.memory.fetch(pc, _instruction_type.length()) {
+                    // an instruction running past the end of memory moves IP to the end and
+                    // reports the address following the instruction
+                    Err(MachineError::InvalidMemoryAddress(_)) => {
+                        self.set_reg(IP, MEMORY_SIZE as u32)?;
+                        return Err(MachineError::InvalidMemoryAddress(pc + _instruction_type.length()));
+                    },
+                    result => result?,
+                };
+                self.memory.remember(pc, _instruction_type, instruction);
+                (_instruction_type, instruction)
+            },
+        };
+
+        // incrementing IP
+        self.set_reg(IP, self.memory.advance(pc, _instruction_type.length()) as u32)?;
 
         match _instruction_type {
             InstructionType::MoveIf => { // MOVE IF : regA (1) = regB (2) if regC (3) != 0
@@ -110,27 +377,21 @@ impl Machine {
                     let reg_b = self.get_reg(instruction[2] as usize)?;
                     self.set_reg(instruction[1] as usize, reg_b)?; // regA = regB
                 }
-                Ok(false)
+                Ok(None)
                 },
             InstructionType::Store => { // STORE : *regA (1) = regB (2)
                 let address :usize = self.get_reg(instruction[1] as usize)? as usize;
-                if address + 3 > MEMORY_SIZE - 1 {
-                    return Err(MachineError::InvalidMemoryAddress(address + 3));
-                }
                 let reg_b = self.get_reg(instruction[2] as usize)?;
-                for i in 0..4 {
-                    self.memory[address + i] = reg_b.to_le_bytes()[i];
-                }
-                Ok(false)
+                self.memory.write(address, &reg_b.to_le_bytes())?;
+                self.record_watchpoint_hit(pc, reg_b);
+                Ok(None)
                 },
             InstructionType::Load => { // LOAD : regA (1) = *regB (2)
                 let address :usize = self.get_reg(instruction[2] as usize)? as usize;
-                let mut reg_a :u32 = 0;
-                for i in 0..4 {
-                    reg_a += (self.load_from_memory(address + i)? as u32) << (8 * i);
-                }
+                let reg_a :u32 = u32::from_le_bytes(self.memory.read(address, Access::Read)?);
                 self.set_reg(instruction[1] as usize, reg_a)?;
-                Ok(false)
+                self.record_watchpoint_hit(pc, reg_a);
+                Ok(None)
                 },
             InstructionType::LoadImm => { // LOADIMM : regA (1) = (H (3) << 8) | L (2) as i32
                 let imm :u16 = ((instruction[3] as u16) << 8) | (instruction[2] as u16);
@@ -140,12 +401,12 @@ impl Machine {
                     (imm as u32) | 0xFFFF0000
                 };
                 self.set_reg(instruction[1] as usize, reg_a)?;
-                Ok(false)
+                Ok(None)
                 },
             InstructionType::Sub => { // SUB : regA (1) = regB (2) - regC (3)
                 let substraction : u32 = self.get_reg(instruction[2] as usize)?.wrapping_sub(self.get_reg(instruction[3] as usize)?);
                 self.set_reg(instruction[1] as usize, substraction)?;
-                Ok(false)
+                Ok(None)
                 },
             InstructionType::Out => { // OUT : print low 8 bits of regA (1) on fd
                 let reg_a = self.get_reg(instruction[1] as usize)?;
@@ -153,11 +414,12 @@ impl Machine {
                     Ok(_) => (),
                     Err(_) => return Err(MachineError::WriteError),
                 }
-                Ok(false)
+                Ok(None)
                 },
-            InstructionType::Exit => {
-                println!("\nExiting the program...\nmemory : {:?}, regs : {:?}", self.memory, self.regs);
-                Ok(true)
+            InstructionType::Exit => Ok(Some(self.exit(0))),
+            InstructionType::ExitWith => { // EXIT : exit with the code in regA (1)
+                let reg_a = self.get_reg(instruction[1] as usize)?;
+                Ok(Some(self.exit(reg_a)))
                 },
             InstructionType::OutNumber => { // OUTNUMBER : print the signed number in regA (1) in decimal on fd
                 let reg_a = self.get_reg(instruction[1] as usize)?;
@@ -165,8 +427,9 @@ impl Machine {
                     Ok(_) => (),
                     Err(_) => return Err(MachineError::WriteError),
                 }
-                Ok(false)
+                Ok(None)
                 },
+            InstructionType::Brk => Ok(Some(StopReason::Breakpoint(pc))), // BRK : stop for a debugger
         }
     }
 
@@ -176,6 +439,18 @@ impl Machine {
         self.step_on(&mut io::stdout().lock())
     }
 
+    /// The exit code of the last exit instruction executed, or `None` if the
+    /// program has not exited.
+    pub fn exit_code(&self) -> Option<u32> {
+        self.exit_code
+    }
+
+    /// The address of the last instruction the machine started to execute,
+    /// which is the faulting instruction when a step returns an error.
+    pub fn last_ip(&self) -> usize {
+        self.last_ip
+    }
+
     /// Reference onto the machine current set of registers.
     pub fn regs(&self) -> &[u32] {
         &self.regs[..]
@@ -184,7 +459,7 @@ impl Machine {
     /// Sets a register to the given value.
     pub fn set_reg(&mut self, reg: usize, value: u32) -> Result<(), MachineError> {
         match reg {
-            n if n < 16 => Ok(self.regs[reg] = value),
+            n if n < 16 => {self.regs[reg] = value; Ok(())},
             _ =>  Err(MachineError::InvalidRegister(reg)),
         }
     }
@@ -199,21 +474,37 @@ impl Machine {
 
     /// Reference onto the machine current memory.
     pub fn memory(&self) -> &[u8] {
-        &self.memory
+        self.memory.bytes()
+    }
+
+    /// Keep the watchpoint triggered by the instruction at `ip`, if any,
+    /// which has read or written `value`.
+    fn record_watchpoint_hit(&mut self, ip :usize, value :u32) {
+        if let Some((id, address, access)) = self.memory.take_triggered() {
+            self.watchpoint_hit = Some(WatchpointHit { id, ip, address, access, value });
+        }
     }
 
-    /// Gets a u8 value from a given place in the memory
-    fn load_from_memory(&self, address :usize) -> Result<u8, MachineError> {
-        match address {
-            n if n < MEMORY_SIZE => Ok(self.memory[address]),
-            n => Err(MachineError::InvalidMemoryAddress(n)),
+    /// Terminate the program with the given exit code
+    fn exit(&mut self, code :u32) -> StopReason {
+        if self.verbose {
+            println!("\nExiting the program...\nmemory : {:?}, regs : {:?}", self.memory.bytes(), self.regs);
         }
+        self.exit_code = Some(code);
+        StopReason::Exited(code)
     }
 
     /// Returns the type of the instruction which starts at the address pointed by the IP register
     fn instruction_type(&self) -> Result<InstructionType, MachineError> {
-        let _instruction_type = self.load_from_memory(self.regs[IP] as usize)?;
-        match _instruction_type {
+        let [opcode] = self.memory.read(self.regs[IP] as usize, Access::Execute)?;
+        InstructionType::from_opcode(opcode)
+    }
+}
+
+impl InstructionType {
+    /// Returns the type of the instruction which opcode is given
+    pub(crate) fn from_opcode(opcode :u8) -> Result<InstructionType, MachineError> {
+        match opcode {
             1 => Ok(InstructionType::MoveIf),
             2 => Ok(InstructionType::Store),
             3 => Ok(InstructionType::Load),
@@ -222,13 +513,15 @@ impl Machine {
             6 => Ok(InstructionType::Out),
             7 => Ok(InstructionType::Exit),
             8 => Ok(InstructionType::OutNumber),
-            _ => Err(MachineError::InvalidInstruction(_instruction_type)),
+            9 => Ok(InstructionType::Brk),
+            10 => Ok(InstructionType::ExitWith),
+            _ => Err(MachineError::InvalidInstruction(opcode)),
         }
     }
 
-    /// Returns the length of the instruction which type is given
-    fn instruction_length(instruction_type :InstructionType) -> usize {
-        match instruction_type {
+    /// Returns the length of the instruction
+    pub(crate) fn length(self) -> usize {
+        match self {
             InstructionType::MoveIf => 4,
             InstructionType::Store => 3,
             InstructionType::Load => 3,
@@ -237,6 +530,8 @@ impl Machine {
             InstructionType::Out => 2,
             InstructionType::Exit => 1,
             InstructionType::OutNumber => 2,
+            InstructionType::Brk => 1,
+            InstructionType::ExitWith => 2,
         }
     }
 }
@@ -1,19 +1,351 @@
-use interpreter::{Machine, MachineError};
-use std::fs::File;
-use std::io::Read;
+use interpreter::{
+    assemble, assemble_with_tests, compile, debug_info, decompile, disassemble,
+    disassemble_instruction, link, optimize, run_test, translate, verify, DebugInfo, Image,
+    Machine, MachineError, Object, StopReason, WatchKind,
+};
+use std::fmt::Display;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::process::ExitCode;
 
-fn main() -> Result<(), MachineError> {
-    // Take a filename as argument on the command line
-    let filename = std::env::args().nth(1).unwrap();
+/// Read a file, exiting on error.
+fn read_file(filename: &str) -> Vec<u8> {
+    fs::read(filename).unwrap_or_else(|error| {
+        eprintln!("{filename}: {error}");
+        std::process::exit(1);
+    })
+}
+
+/// Read a source file, exiting on error or if it is not UTF-8.
+fn read_source(filename: &str) -> String {
+    String::from_utf8(read_file(filename)).unwrap_or_else(|error| {
+        eprintln!("{filename}: {error}");
+        std::process::exit(1);
+    })
+}
+
+fn report<T: Display>(filename: &str, diagnostics: Vec<T>) -> ! {
+    for diagnostic in diagnostics {
+        eprintln!("{filename}:{diagnostic}");
+    }
+    std::process::exit(1);
+}
+
+/// Describe the command line and exit with code 2, for invalid arguments.
+fn usage() -> ! {
+    eprintln!(
+        "usage: tp-rust-2 [OPTION]... FILE [ARGUMENT]...
+       tp-rust-2 verify|translate|disassemble|decompile FILE
+       tp-rust-2 compile|assemble [-O] [-o OUTPUT] FILE
+       tp-rust-2 link [-o OUTPUT] FILE...
+       tp-rust-2 test FILE...
+options: --watch RANGE, --watch-read RANGE, --watch-write RANGE,
+         --env NAME=VALUE, --trace"
+    );
+    std::process::exit(2);
+}
+
+/// The argument following an option, or the usage if there is none.
+fn value<'a>(args: &mut impl Iterator<Item = &'a String>) -> &'a str {
+    args.next().map(String::as_str).unwrap_or_else(|| usage())
+}
+
+/// Split the `-o FILE` option from the other arguments.
+fn output_option(args: &[String]) -> (Option<&str>, Vec<&str>) {
+    let mut output = None;
+    let mut others = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(value(&mut args)),
+            _ => others.push(arg.as_str()),
+        }
+    }
+    (output, others)
+}
+
+/// Read an image file or a raw program, exiting on error.
+fn load_image(filename: &str) -> Image {
+    Image::load(&read_file(filename)).unwrap_or_else(|error| {
+        eprintln!("{filename}: {error}");
+        std::process::exit(1);
+    })
+}
+
+/// Read the debug information written by `link` next to an image, if any.
+fn load_debug_info(filename: &str) -> Option<DebugInfo> {
+    let path = Path::new(filename).with_extension("sym");
+    let text = fs::read_to_string(&path).ok()?;
+    match DebugInfo::parse(&text) {
+        Ok(info) => Some(info),
+        Err(error) => {
+            eprintln!("{}:{error}", path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parse an address range given as `START..END` or as a single address,
+/// or show the usage if it is invalid.
+fn parse_range(range: &str) -> Range<usize> {
+    let address = |text: &str| text.parse::<usize>().unwrap_or_else(|_| usage());
+    match range.split_once("..") {
+        Some((start, end)) => address(start)..address(end),
+        None => {
+            let address = address(range);
+            address..address.saturating_add(1)
+        }
+    }
+}
+
+fn main() -> Result<ExitCode, MachineError> {
+    // Take a filename as argument on the command line, optionally
+    // preceded by a subcommand
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = args.first() else { usage() };
+    // The file given to a subcommand taking a single one
+    let filename = || args.get(1).unwrap_or_else(|| usage());
+
+    if command == "verify" {
+        // Check the program without running it
+        let filename = filename();
+        if let Err(diagnostics) = verify(&read_file(filename)) {
+            report(filename, diagnostics);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if command == "translate" {
+        // Print the Rust translation of the program
+        let filename = filename();
+        match translate(&read_file(filename)) {
+            Ok(source) => print!("{source}"),
+            Err(diagnostics) => report(filename, diagnostics),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if command == "compile" {
+        // Write the assembly source of a program, by default next to it
+        let (output, sources) = output_option(&args[1..]);
+        let filename = *sources.first().unwrap_or_else(|| usage());
+        let source = read_source(filename);
+        match compile(&source) {
+            Ok(assembly) => {
+                let default = Path::new(filename).with_extension("s");
+                let output = output.map(Path::new).unwrap_or(&default);
+                fs::write(output, assembly).unwrap();
+            }
+            Err(errors) => {
+                for error in errors {
+                    eprint!("{}", error.report(filename, &source));
+                }
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if command == "assemble" {
+        // Write the object file of a module, by default next to the source,
+        // optimized with -O
+        let (output, mut sources) = output_option(&args[1..]);
+        let optimized = sources.contains(&"-O");
+        sources.retain(|&arg| arg != "-O");
+        let filename = *sources.first().unwrap_or_else(|| usage());
+        let source = read_source(filename);
+        match assemble(&source) {
+            Ok(object) => {
+                let mut object = match optimized {
+                    true => optimize(&object),
+                    false => object,
+                };
+                let name = Path::new(filename).file_name().unwrap();
+                object.source = name.to_string_lossy().into_owned();
+                let default = Path::new(filename).with_extension("o");
+                let output = output.map(Path::new).unwrap_or(&default);
+                fs::write(output, object.to_bytes()).unwrap();
+            }
+            Err(errors) => {
+                for error in errors {
+                    eprint!("{}", error.report(filename, &source));
+                }
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if command == "link" {
+        // Write the image made of the object files, by default to a.out,
+        // and its debug information next to it
+        let (output, filenames) = output_option(&args[1..]);
+        let output = output.unwrap_or("a.out");
+        let objects: Vec<Object> = filenames
+            .iter()
+            .map(|filename| {
+                Object::parse(&read_file(filename)).unwrap_or_else(|error| {
+                    eprintln!("{filename}: {error}");
+                    std::process::exit(1);
+                })
+            })
+            .collect();
+        match link(&objects) {
+            Ok(image) => {
+                fs::write(output, image.to_bytes()).unwrap();
+                let sym = Path::new(output).with_extension("sym");
+                fs::write(sym, debug_info(&objects).to_text()).unwrap();
+            }
+            Err(errors) => report("link", errors.iter().map(|e| format!(" {e}")).collect()),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if command == "disassemble" {
+        // List the program, with the labels of its debug information
+        let filename = filename();
+        match disassemble(&read_file(filename), load_debug_info(filename).as_ref()) {
+            Ok(listing) => print!("{listing}"),
+            Err(diagnostics) => report(filename, diagnostics),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if command == "decompile" {
+        // Pseudo-code of the program, named after its debug information
+        let filename = filename();
+        match decompile(&read_file(filename), load_debug_info(filename).as_ref()) {
+            Ok(source) => print!("{source}"),
+            Err(diagnostics) => report(filename, diagnostics),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if command == "test" {
+        // Run the tests written in assembly sources and report them
+        let (mut passed, mut failed) = (0, 0);
+        for filename in &args[1..] {
+            let source = read_source(filename);
+            let (mut object, tests) = match assemble_with_tests(&source) {
+                Ok(assembled) => assembled,
+                Err(errors) => {
+                    for error in errors {
+                        eprint!("{}", error.report(filename, &source));
+                    }
+                    failed += 1;
+                    continue;
+                }
+            };
+            let name = Path::new(filename).file_name().unwrap();
+            object.source = name.to_string_lossy().into_owned();
+            for test in tests {
+                let result = run_test(&object, &test);
+                let status = if result.is_ok() { "ok" } else { "FAILED" };
+                println!("test {filename}:{} {} ... {status}", test.line, test.name);
+                match result {
+                    Ok(()) => passed += 1,
+                    Err(failures) => {
+                        for failure in failures {
+                            println!("    {failure}");
+                        }
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        println!("{passed} passed, {failed} failed");
+        return Ok(if failed == 0 {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        });
+    }
+
+    // Options: --watch, --watch-read and --watch-write followed by an
+    // address range, --env followed by NAME=VALUE, and --trace to print
+    // each instruction before running it. They come before the filename,
+    // which is followed by the program arguments.
+    let mut watchpoints = Vec::new();
+    let mut env = Vec::new();
+    let mut trace = false;
+    let mut args = args.iter();
+    let filename = loop {
+        let Some(arg) = args.next() else { usage() };
+        let kind = match arg.as_str() {
+            "--watch" => WatchKind::Access,
+            "--watch-read" => WatchKind::Read,
+            "--watch-write" => WatchKind::Write,
+            "--env" => {
+                env.push(value(&mut args).split_once('=').unwrap_or_else(|| usage()));
+                continue;
+            }
+            "--trace" => {
+                trace = true;
+                continue;
+            }
+            _ => break arg,
+        };
+        watchpoints.push((parse_range(value(&mut args)), kind));
+    };
+    let program_args: Vec<&str> = std::iter::once(filename)
+        .chain(args)
+        .map(String::as_str)
+        .collect();
 
-    // Read content to buffer
-    let mut fs = File::open(&filename).unwrap();
-    let mut buffer = Vec::new();
-    fs.read_to_end(&mut buffer).unwrap();
+    // Read content to buffer, either an image file or a raw program, and
+    // the debug information locating addresses in the source
+    let image = load_image(filename);
+    let debug_info = load_debug_info(filename).unwrap_or_default();
 
     // Create a machine with this memory content
-    let mut machine = Machine::new(&buffer);
+    let mut machine = Machine::from_image(&image).unwrap();
+    // Without arguments nor environment, the machine keeps its reset state:
+    // argc is 0 and there is no argv, not even the program name
+    if program_args.len() > 1 || !env.is_empty() {
+        machine.set_arguments(&program_args, &env)?;
+    }
+    for (range, kind) in watchpoints {
+        machine.add_watchpoint(range, kind)?;
+    }
 
-    // Run the machine until the end
-    machine.run()
+    // Run the machine until the end, reporting the breakpoints and
+    // watchpoints triggered, and exit with the code of the program. Only
+    // the low 8 bits are kept, like on Unix, but a code which is not 0
+    // never gives 0: 256 gives 1 for instance. When tracing, the machine
+    // runs one step at a time.
+    loop {
+        if trace {
+            let ip = machine.regs()[0] as usize;
+            let memory = machine.memory().get(ip..).unwrap_or_default();
+            let instruction = disassemble_instruction(memory, ip, Some(&debug_info))
+                .unwrap_or_else(|| "????".to_string());
+            eprintln!("{}: {instruction}", debug_info.locate(ip));
+        }
+        let result = if trace {
+            machine.run_until(1)
+        } else {
+            machine.run()
+        };
+        match result {
+            Ok(StopReason::Watchpoint(hit)) => eprintln!("{hit}"),
+            Ok(StopReason::Breakpoint(address)) => {
+                eprintln!("breakpoint at {}", debug_info.locate(address))
+            }
+            Ok(StopReason::Exited(code)) => {
+                return Ok(match (code, code as u8) {
+                    (1.., 0) => ExitCode::FAILURE,
+                    (_, low) => ExitCode::from(low),
+                })
+            }
+            Ok(StopReason::StepLimit) => (),
+            Err(error) => {
+                eprintln!(
+                    "error at {}: {error:?}",
+                    debug_info.locate(machine.last_ip())
+                );
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
 }
@@ -0,0 +1,305 @@
+use std::cell::Cell;
+use std::ops::Range;
+
+use crate::machine::{InstructionType, MachineError, MEMORY_SIZE};
+use crate::protection::{Access, Permissions, Region};
+use crate::watchpoint::{WatchKind, Watchpoint};
+
+/// How out-of-bounds and unaligned memory accesses are handled. The same
+/// rule applies to instruction fetches, `load` and `store`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemoryPolicy {
+    /// Every byte accessed must be inside the memory, otherwise the access
+    /// fails with [InvalidMemoryAddress](MachineError::InvalidMemoryAddress)
+    /// of the first byte outside of it. An instruction which starts in
+    /// memory but runs past its end rather reports the address following
+    /// the instruction, and moves IP to the end of memory.
+    #[default]
+    Strict,
+    /// Addresses are taken modulo the memory size, so an access starting
+    /// near the end of memory continues at its beginning.
+    Wrap,
+    /// Like [Strict](MemoryPolicy::Strict), and additionally the words
+    /// accessed by `load` and `store` must start at a multiple of 4.
+    Aligned,
+}
+
+/// An instruction type along with the bytes of the instruction.
+pub(crate) type Decoded = (InstructionType, [u8; 4]);
+
+/// The machine memory along with the rules to access it.
+#[derive(Debug)]
+pub(crate) struct Memory {
+    bytes: [u8; MEMORY_SIZE],
+    regions: Vec<Region>,
+    policy: MemoryPolicy,
+    /// When predecoding is enabled, the instruction found at each address.
+    /// The whole memory is decoded when predecoding is enabled, the rules
+    /// to fetch instructions change or a program is loaded, and an address
+    /// whose bytes are overwritten is decoded again when it is fetched.
+    decoded: Option<Vec<Option<Decoded>>>,
+    watchpoints: Vec<Watchpoint>,
+    next_watchpoint: usize,
+    /// Watchpoint identifier, address and kind of the last access which
+    /// triggered a watchpoint, until it is taken.
+    triggered: Cell<Option<(usize, usize, Access)>>,
+}
+
+impl Memory {
+    /// Build a memory whose beginning is `content`, which must fit.
+    pub(crate) fn new(content: &[u8]) -> Memory {
+        let mut bytes = [0; MEMORY_SIZE];
+        bytes[..content.len()].copy_from_slice(content);
+        Memory {
+            bytes,
+            regions: Vec::new(),
+            policy: MemoryPolicy::default(),
+            decoded: None,
+            watchpoints: Vec::new(),
+            next_watchpoint: 0,
+            triggered: Cell::new(None),
+        }
+    }
+
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(crate) fn policy(&self) -> MemoryPolicy {
+        self.policy
+    }
+
+    pub(crate) fn set_policy(&mut self, policy: MemoryPolicy) {
+        self.policy = policy;
+        // Which instructions can be fetched depends on the policy
+        self.predecode(0..MEMORY_SIZE);
+    }
+
+    pub(crate) fn predecoding(&self) -> bool {
+        self.decoded.is_some()
+    }
+
+    pub(crate) fn set_predecoding(&mut self, enabled: bool) {
+        self.decoded = enabled.then(|| vec![None; MEMORY_SIZE]);
+        self.predecode(0..MEMORY_SIZE);
+    }
+
+    /// Decode the instructions starting in `range` if predecoding is
+    /// enabled. Those which cannot be fetched are left to the machine,
+    /// which reports why.
+    fn predecode(&mut self, range: Range<usize>) {
+        let Some(mut decoded) = self.decoded.take() else {
+            return;
+        };
+        for address in range {
+            decoded[address] = self.fetch(address, 1).ok().and_then(|[opcode, ..]| {
+                let instruction_type = InstructionType::from_opcode(opcode).ok()?;
+                let instruction = self.fetch(address, instruction_type.length()).ok()?;
+                Some((instruction_type, instruction))
+            });
+        }
+        self.decoded = Some(decoded);
+    }
+
+    /// The instruction previously decoded at `address`, if any.
+    pub(crate) fn decoded(&self, address: usize) -> Option<Decoded> {
+        *self.decoded.as_ref()?.get(address)?
+    }
+
+    /// Keep the instruction decoded at `address` if predecoding is enabled.
+    pub(crate) fn remember(
+        &mut self,
+        address: usize,
+        instruction_type: InstructionType,
+        instruction: [u8; 4],
+    ) {
+        if let Some(entry) = self.decoded.as_mut().and_then(|d| d.get_mut(address)) {
+            *entry = Some((instruction_type, instruction));
+        }
+    }
+
+    pub(crate) fn protect(
+        &mut self,
+        range: Range<usize>,
+        permissions: Permissions,
+    ) -> Result<(), MachineError> {
+        if range.end > MEMORY_SIZE || range.start > range.end {
+            return Err(MachineError::InvalidMemoryAddress(range.end));
+        }
+        self.regions.push(Region {
+            start: range.start,
+            end: range.end,
+            permissions,
+        });
+        self.predecode(0..MEMORY_SIZE);
+        Ok(())
+    }
+
+    pub(crate) fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub(crate) fn permissions(&self, address: usize) -> Permissions {
+        match self
+            .regions
+            .iter()
+            .rev()
+            .find(|region| region.contains(address))
+        {
+            Some(region) => region.permissions,
+            None => Permissions::RWX,
+        }
+    }
+
+    pub(crate) fn add_watchpoint(
+        &mut self,
+        range: Range<usize>,
+        kind: WatchKind,
+    ) -> Result<usize, MachineError> {
+        if range.end > MEMORY_SIZE || range.start > range.end {
+            return Err(MachineError::InvalidMemoryAddress(range.end));
+        }
+        let id = self.next_watchpoint;
+        self.next_watchpoint += 1;
+        self.watchpoints.push(Watchpoint {
+            id,
+            start: range.start,
+            end: range.end,
+            kind,
+        });
+        Ok(id)
+    }
+
+    pub(crate) fn remove_watchpoint(&mut self, id: usize) -> Option<Watchpoint> {
+        let index = self.watchpoints.iter().position(|w| w.id == id)?;
+        Some(self.watchpoints.remove(index))
+    }
+
+    pub(crate) fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// The watchpoint identifier, address and kind of the last access which
+    /// triggered a watchpoint since the previous call, if any.
+    pub(crate) fn take_triggered(&mut self) -> Option<(usize, usize, Access)> {
+        self.triggered.take()
+    }
+
+    /// Record the access of `length` bytes starting at `start` if it
+    /// triggers a watchpoint.
+    fn watch(&self, start: usize, length: usize, access: Access) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+        let bytes = (start..start + length).map(|a| a % MEMORY_SIZE);
+        let watchpoint = self
+            .watchpoints
+            .iter()
+            .find(|w| w.kind.triggered_by(access) && bytes.clone().any(|a| w.overlaps(a..a + 1)));
+        if let Some(watchpoint) = watchpoint {
+            self.triggered.set(Some((watchpoint.id, start, access)));
+        }
+    }
+
+    /// Address following the `length` bytes starting at `address`, as
+    /// seen by the instruction pointer.
+    pub(crate) fn advance(&self, address: usize, length: usize) -> usize {
+        match self.policy {
+            MemoryPolicy::Wrap => (address % MEMORY_SIZE + length) % MEMORY_SIZE,
+            MemoryPolicy::Strict | MemoryPolicy::Aligned => address + length,
+        }
+    }
+
+    /// Check that the `length` bytes starting at `address` can be accessed
+    /// and return the position of the first one in memory.
+    fn locate(&self, address: usize, length: usize, access: Access) -> Result<usize, MachineError> {
+        let start = match self.policy {
+            MemoryPolicy::Wrap => address % MEMORY_SIZE,
+            MemoryPolicy::Strict | MemoryPolicy::Aligned => {
+                if self.policy == MemoryPolicy::Aligned
+                    && access != Access::Execute
+                    && !address.is_multiple_of(length)
+                {
+                    return Err(MachineError::MisalignedAccess(address));
+                }
+                let end = address
+                    .checked_add(length)
+                    .ok_or(MachineError::InsufficientPointerSize)?;
+                if end > MEMORY_SIZE {
+                    return Err(MachineError::InvalidMemoryAddress(address.max(MEMORY_SIZE)));
+                }
+                address
+            }
+        };
+        if !self.regions.is_empty() {
+            if let Some(faulty) = (start..start + length)
+                .map(|a| a % MEMORY_SIZE)
+                .find(|&a| !self.permissions(a).allows(access))
+            {
+                return Err(MachineError::ProtectionFault {
+                    address: faulty,
+                    access,
+                });
+            }
+        }
+        Ok(start)
+    }
+
+    /// Read `N` bytes starting at `address`.
+    pub(crate) fn read<const N: usize>(
+        &self,
+        address: usize,
+        access: Access,
+    ) -> Result<[u8; N], MachineError> {
+        let start = self.locate(address, N, access)?;
+        self.watch(start, N, access);
+        Ok(std::array::from_fn(|i| {
+            self.bytes[(start + i) % MEMORY_SIZE]
+        }))
+    }
+
+    /// Read the `length` bytes (at most 4) of an instruction starting at
+    /// `address`. Unused bytes are set to 0.
+    pub(crate) fn fetch(&self, address: usize, length: usize) -> Result<[u8; 4], MachineError> {
+        let start = self.locate(address, length, Access::Execute)?;
+        Ok(std::array::from_fn(|i| {
+            if i < length {
+                self.bytes[(start + i) % MEMORY_SIZE]
+            } else {
+                0
+            }
+        }))
+    }
+
+    /// Write `bytes` starting at `address`.
+    pub(crate) fn write(&mut self, address: usize, bytes: &[u8]) -> Result<(), MachineError> {
+        let start = self.locate(address, bytes.len(), Access::Write)?;
+        self.watch(start, bytes.len(), Access::Write);
+        for (i, &b) in bytes.iter().enumerate() {
+            self.bytes[(start + i) % MEMORY_SIZE] = b;
+        }
+        self.forget(start, bytes.len());
+        Ok(())
+    }
+
+    /// Copy `bytes` at `address` on behalf of a loader, ignoring the
+    /// policy, the protection and the watchpoints. The bytes must fit.
+    pub(crate) fn load(&mut self, address: usize, bytes: &[u8]) {
+        self.bytes[address..address + bytes.len()].copy_from_slice(bytes);
+        // Instructions starting up to 3 bytes before may include them
+        let start = address.saturating_sub(3);
+        self.predecode(start..address + bytes.len());
+    }
+
+    /// Forget the decoded instructions which may include some of the
+    /// `length` bytes starting at `start`.
+    fn forget(&mut self, start: usize, length: usize) {
+        if let Some(decoded) = self.decoded.as_mut() {
+            // Instructions are at most 4 bytes long, so those starting up
+            // to 3 bytes before the written ones may include them
+            for i in 0..length + 3 {
+                decoded[(start + MEMORY_SIZE - 3 + i) % MEMORY_SIZE] = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::image::{write_name, ImageError, Reader};
+
+/// First bytes of an object file.
+pub const OBJECT_MAGIC: &[u8; 4] = b"TPVO";
+/// Version of the object format written by [Object::to_bytes].
+pub const OBJECT_VERSION: u16 = 1;
+
+/// An assembled module, whose code does not have an address yet. It is
+/// turned into an [Image] by [link](crate::link).
+///
+/// The file starts with a header, all numbers being little-endian:
+///   - [OBJECT_MAGIC], then the version on 2 bytes and 2 zero bytes,
+///   - the size of the code, the number of symbols, the number of
+///     relocations, the number of line entries and the alignment, on 4
+///     bytes each,
+///   - the length of the source file name on 2 bytes and its UTF-8 name.
+///
+/// The code follows. Each symbol follows with its offset on 4 bytes, 1 if
+/// it is global or 0 otherwise on 1 byte, then its name like the source
+/// file name. Each relocation follows with its offset and its addend on 4
+/// bytes each, 1 if it is negated or 0 otherwise on 1 byte, then the name
+/// of its symbol. Each line entry follows with
+/// its offset and its line number on 4 bytes each.
+///
+/// [Image]: crate::Image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    /// The code and data of the module, to be loaded at consecutive
+    /// addresses.
+    pub bytes: Vec<u8>,
+    pub symbols: Vec<ObjectSymbol>,
+    pub relocations: Vec<Relocation>,
+    /// Name of the source file, for debug information.
+    pub source: String,
+    /// Source line of the bytes starting at each offset, by increasing
+    /// offset.
+    pub lines: Vec<LineEntry>,
+    /// The module must be placed at a multiple of this address, as
+    /// required by its `.align` directives. 0 and 1 allow any address.
+    pub alignment: u32,
+}
+
+/// A label defined in a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectSymbol {
+    pub name: String,
+    /// Position of the label from the beginning of the module.
+    pub offset: u32,
+    /// Whether other modules can refer to the label.
+    pub global: bool,
+}
+
+/// A 16-bit immediate which must be set to the address of a symbol plus
+/// an addend once the module is placed in memory, or to the addend minus
+/// the address if the relocation is negated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// Position of the immediate from the beginning of the module.
+    pub offset: u32,
+    /// A symbol of the same module, or a global symbol of another one.
+    pub symbol: String,
+    pub addend: i32,
+    pub negated: bool,
+}
+
+/// The bytes starting at `offset` come from line `line` of the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEntry {
+    pub offset: u32,
+    pub line: u32,
+}
+
+impl Object {
+    /// Read an object file.
+    pub fn parse(bytes: &[u8]) -> Result<Object, ImageError> {
+        let mut reader = Reader { bytes };
+        if !bytes.starts_with(OBJECT_MAGIC) {
+            return Err(ImageError::InvalidMagic);
+        }
+        reader.take(4)?;
+        let version = reader.u16()?;
+        if version != OBJECT_VERSION {
+            return Err(ImageError::UnsupportedVersion(version));
+        }
+        reader.take(2)?;
+        let size = reader.u32()?;
+        let symbol_count = reader.u32()?;
+        let relocation_count = reader.u32()?;
+        let line_count = reader.u32()?;
+        let alignment = reader.u32()?;
+        let source = reader.name()?;
+
+        let bytes = reader.take(size as usize)?.to_vec();
+        let mut symbols = Vec::new();
+        for _ in 0..symbol_count {
+            let offset = reader.u32()?;
+            let global = reader.u8()? != 0;
+            let name = reader.name()?;
+            symbols.push(ObjectSymbol {
+                name,
+                offset,
+                global,
+            });
+        }
+        let mut relocations = Vec::new();
+        for _ in 0..relocation_count {
+            let offset = reader.u32()?;
+            let addend = reader.u32()? as i32;
+            let negated = reader.u8()? != 0;
+            let symbol = reader.name()?;
+            relocations.push(Relocation {
+                offset,
+                symbol,
+                addend,
+                negated,
+            });
+        }
+        let mut lines = Vec::new();
+        for _ in 0..line_count {
+            let offset = reader.u32()?;
+            let line = reader.u32()?;
+            lines.push(LineEntry { offset, line });
+        }
+        Ok(Object {
+            bytes,
+            symbols,
+            relocations,
+            source,
+            lines,
+            alignment,
+        })
+    }
+
+    /// The object file content.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = OBJECT_MAGIC.to_vec();
+        bytes.extend_from_slice(&OBJECT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&(self.bytes.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.symbols.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.relocations.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.lines.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.alignment.to_le_bytes());
+        write_name(&mut bytes, &self.source);
+        bytes.extend_from_slice(&self.bytes);
+        for symbol in &self.symbols {
+            bytes.extend_from_slice(&symbol.offset.to_le_bytes());
+            bytes.push(symbol.global as u8);
+            write_name(&mut bytes, &symbol.name);
+        }
+        for relocation in &self.relocations {
+            bytes.extend_from_slice(&relocation.offset.to_le_bytes());
+            bytes.extend_from_slice(&relocation.addend.to_le_bytes());
+            bytes.push(relocation.negated as u8);
+            write_name(&mut bytes, &relocation.symbol);
+        }
+        for entry in &self.lines {
+            bytes.extend_from_slice(&entry.offset.to_le_bytes());
+            bytes.extend_from_slice(&entry.line.to_le_bytes());
+        }
+        bytes
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::machine::{InstructionType, IP, MEMORY_SIZE, NREGS};
+use crate::object::{LineEntry, Object, Relocation};
+use crate::verify::{explore, register_operands};
+
+/// Registers, one bit each.
+type Registers = u32;
+
+const ALL: Registers = (1 << NREGS) - 1;
+
+/// A reachable instruction of the module being optimized.
+struct Instruction {
+    offset: usize,
+    length: usize,
+    instruction_type: InstructionType,
+    /// Its register operands, 0 when absent.
+    operands: [usize; 3],
+    /// The immediate of `loadimm`, when it is not relocated.
+    immediate: Option<u32>,
+    /// The relocations of its bytes.
+    relocations: Vec<Relocation>,
+    removed: bool,
+}
+
+impl Instruction {
+    /// The register written, other than by a `move` whose condition may
+    /// be false.
+    fn written(&self) -> Option<usize> {
+        match self.instruction_type {
+            InstructionType::Load | InstructionType::LoadImm | InstructionType::Sub => {
+                Some(self.operands[0])
+            }
+            _ => None,
+        }
+    }
+
+    /// The registers whose value is used.
+    fn read(&self) -> Registers {
+        let [a, b, c] = self.operands.map(|r| 1 << r);
+        match self.instruction_type {
+            // The destination keeps its value when the condition is false
+            InstructionType::MoveIf => a | b | c,
+            InstructionType::LoadImm => 0,
+            InstructionType::Sub => b | c,
+            InstructionType::Out | InstructionType::OutNumber | InstructionType::ExitWith => a,
+            // The registers can be seen once the machine has stopped, as
+            // it does when a load or a store faults
+            InstructionType::Load
+            | InstructionType::Store
+            | InstructionType::Exit
+            | InstructionType::Brk => ALL,
+        }
+    }
+
+    /// Whether the instruction uses the value of the IP, which depends on
+    /// the place of the code. Conditions only check that it is not 0.
+    fn reads_ip(&self) -> bool {
+        let [a, b, c] = self.operands;
+        match self.instruction_type {
+            InstructionType::MoveIf => b == IP,
+            InstructionType::Store => a == IP || b == IP,
+            InstructionType::Load => b == IP,
+            InstructionType::Sub => b == IP || c == IP,
+            InstructionType::Out | InstructionType::OutNumber | InstructionType::ExitWith => {
+                a == IP
+            }
+            InstructionType::LoadImm | InstructionType::Exit | InstructionType::Brk => false,
+        }
+    }
+
+    /// Whether control may not go on with the next instruction.
+    fn ends_block(&self) -> bool {
+        self.written() == Some(IP)
+            || (self.instruction_type == InstructionType::MoveIf && self.operands[0] == IP)
+            || matches!(
+                self.instruction_type,
+                InstructionType::Exit | InstructionType::ExitWith | InstructionType::Brk
+            )
+    }
+
+    /// The jump to a label made by `loadimm r0 <- #label`, if it is one.
+    fn jump(&self) -> Option<&Relocation> {
+        match &self.relocations[..] {
+            [relocation]
+                if self.instruction_type == InstructionType::LoadImm
+                    && self.operands[0] == IP
+                    && relocation.offset as usize == self.offset + 2
+                    && !relocation.negated =>
+            {
+                Some(relocation)
+            }
+            _ => None,
+        }
+    }
+}
+
+struct Optimizer<'a> {
+    object: &'a Object,
+    instructions: Vec<Instruction>,
+    /// Index of the instruction starting at each offset.
+    index: BTreeMap<usize, usize>,
+    /// Offsets that control may reach other than from the previous
+    /// instruction.
+    boundaries: BTreeSet<usize>,
+    /// Whether instructions can be removed, moving the code after them.
+    compact: bool,
+}
+
+impl Optimizer<'_> {
+    /// The offset of a label of the module.
+    fn symbol(&self, name: &str) -> Option<usize> {
+        let symbol = self.object.symbols.iter().find(|s| s.name == name)?;
+        Some(symbol.offset as usize)
+    }
+
+    /// The instruction executed when control reaches `offset`, skipping
+    /// the removed instructions.
+    fn resolve(&self, offset: usize) -> Option<usize> {
+        let mut i = *self.index.get(&offset)?;
+        while self.instructions[i].removed {
+            let end = self.instructions[i].offset + self.instructions[i].length;
+            i = *self.index.get(&end)?;
+        }
+        Some(i)
+    }
+
+    /// Make the jumps to jumps go to the final target, and remove the
+    /// jumps to the next instruction.
+    fn thread_jumps(&mut self) -> bool {
+        let mut changed = false;
+        for i in 0..self.instructions.len() {
+            let instruction = &self.instructions[i];
+            let Some(jump) = instruction.jump().filter(|_| !instruction.removed) else {
+                continue;
+            };
+            let Some(target) = self
+                .symbol(&jump.symbol)
+                .and_then(|offset| offset.checked_add_signed(jump.addend as isize))
+                .and_then(|offset| self.resolve(offset))
+            else {
+                continue;
+            };
+            let next = self.resolve(instruction.offset + instruction.length);
+            if self.compact && next == Some(target) {
+                self.instructions[i].removed = true;
+                changed = true;
+            } else if let Some(further) = self.instructions[target].jump() {
+                let relocation = Relocation {
+                    offset: jump.offset,
+                    ..further.clone()
+                };
+                if *jump != relocation {
+                    self.instructions[i].relocations = vec![relocation];
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// The straight-line sequences of instructions still present, by index.
+    fn blocks(&self) -> Vec<Vec<usize>> {
+        let mut blocks: Vec<Vec<usize>> = Vec::new();
+        let mut starts = true;
+        let mut end = None;
+        for (i, instruction) in self.instructions.iter().enumerate() {
+            starts |=
+                self.boundaries.contains(&instruction.offset) || end != Some(instruction.offset);
+            end = Some(instruction.offset + instruction.length);
+            if instruction.removed {
+                continue;
+            }
+            if starts {
+                blocks.push(Vec::new());
+            }
+            blocks.last_mut().unwrap().push(i);
+            starts = instruction.ends_block();
+        }
+        blocks
+    }
+
+    /// Remove the `loadimm` of a value which the register already holds,
+    /// such as the `loadimm r3 <- #4` of consecutive pushes.
+    fn remove_reloads(&mut self) -> bool {
+        let mut changed = false;
+        for block in self.blocks() {
+            let mut known: [Option<u32>; NREGS] = [None; NREGS];
+            for i in block {
+                let instruction = &self.instructions[i];
+                let [a, b, c] = instruction.operands;
+                match instruction.instruction_type {
+                    InstructionType::LoadImm => match instruction.immediate {
+                        Some(value) if known[a] == Some(value) && a != IP => {
+                            self.instructions[i].removed = true;
+                            changed = true;
+                        }
+                        value => known[a] = value,
+                    },
+                    InstructionType::Sub => {
+                        known[a] = match (known[b], known[c]) {
+                            (Some(x), Some(y)) => Some(x.wrapping_sub(y)),
+                            _ => None,
+                        }
+                    }
+                    InstructionType::MoveIf => match known[c] {
+                        Some(0) => (),
+                        Some(_) => known[a] = known[b],
+                        None if known[a] != known[b] => known[a] = None,
+                        None => (),
+                    },
+                    InstructionType::Load => known[a] = None,
+                    _ => (),
+                }
+            }
+        }
+        changed
+    }
+
+    /// Remove the instructions which only write a register overwritten
+    /// before being read.
+    fn remove_dead_writes(&mut self) -> bool {
+        let mut changed = false;
+        for block in self.blocks() {
+            // Anything may be read after the block
+            let mut live = ALL;
+            for &i in block.iter().rev() {
+                let instruction = &self.instructions[i];
+                let written = instruction.written();
+                let removable = matches!(
+                    instruction.instruction_type,
+                    InstructionType::LoadImm | InstructionType::Sub
+                );
+                match written {
+                    Some(r) if r != IP && removable && live & (1 << r) == 0 => {
+                        self.instructions[i].removed = true;
+                        changed = true;
+                        continue;
+                    }
+                    Some(r) => live &= !(1 << r),
+                    None => (),
+                }
+                live |= instruction.read();
+            }
+        }
+        changed
+    }
+
+    /// The module without the removed instructions.
+    fn rebuild(self) -> Object {
+        let object = self.object;
+        // Offsets of the removed instructions, and the bytes removed before
+        // the end of each one
+        let mut removed: Vec<(usize, usize)> = Vec::new();
+        let mut total = 0;
+        for instruction in self.instructions.iter().filter(|i| i.removed) {
+            total += instruction.length;
+            removed.push((instruction.offset, total));
+        }
+        let shift = |offset: u32| {
+            let before = removed.partition_point(|&(start, _)| start < offset as usize);
+            let removed = before.checked_sub(1).map_or(0, |i| removed[i].1);
+            offset - removed as u32
+        };
+
+        let mut bytes = Vec::new();
+        let mut start = 0;
+        for instruction in self.instructions.iter().filter(|i| i.removed) {
+            bytes.extend_from_slice(&object.bytes[start..instruction.offset]);
+            start = instruction.offset + instruction.length;
+        }
+        bytes.extend_from_slice(&object.bytes[start..]);
+
+        let code: BTreeSet<usize> = self.instructions.iter().map(|i| i.offset).collect();
+        let mut relocations: Vec<Relocation> = object
+            .relocations
+            .iter()
+            .filter(|r| {
+                let before = code.range(..=r.offset as usize).next_back();
+                before.is_none_or(|&o| {
+                    let instruction = &self.instructions[self.index[&o]];
+                    o + instruction.length <= r.offset as usize
+                })
+            })
+            .cloned()
+            .collect();
+        relocations.extend(
+            self.instructions
+                .iter()
+                .filter(|i| !i.removed)
+                .flat_map(|i| i.relocations.iter().cloned()),
+        );
+        for relocation in &mut relocations {
+            relocation.offset = shift(relocation.offset);
+        }
+        relocations.sort_by_key(|r| r.offset);
+
+        let mut symbols = object.symbols.clone();
+        for symbol in &mut symbols {
+            symbol.offset = shift(symbol.offset);
+        }
+        // The line of a removed instruction goes to the next one, unless it
+        // has its own
+        let mut lines: Vec<LineEntry> = Vec::new();
+        for entry in &object.lines {
+            let offset = shift(entry.offset);
+            if lines.last().is_some_and(|last| last.offset == offset) {
+                lines.pop();
+            }
+            lines.push(LineEntry { offset, ..*entry });
+        }
+
+        Object {
+            bytes,
+            symbols,
+            relocations,
+            lines,
+            ..object.clone()
+        }
+    }
+}
+
+/// Optimize an assembled module, without changing what it prints, how it
+/// stops and the registers it leaves:
+///   - the jumps to a jump go directly to the final target, and the jumps
+///     to the next instruction are removed,
+///   - the `loadimm` of a value which the register already holds is
+///     removed, such as the second `loadimm r3 <- #4` of two consecutive
+///     pushes,
+///   - the `loadimm` and `sub` whose result is overwritten before being
+///     read are removed, unless a `load` or a `store` between them could
+///     stop the machine.
+///
+/// The machine having no addition, the `loadimm` of an opposite followed
+/// by a `sub` which make up `add` are kept.
+///
+/// Only the code reachable from the beginning of the module is changed,
+/// as found by [verify](crate::verify) with the labels of the module at
+/// their offset, and the code is moved to fill the space of the removed
+/// instructions. Labels and relocations follow it, so addresses must be
+/// given with labels rather than numbers, and the constants computed from
+/// labels must not depend on the size of the code. Nothing is removed
+/// from a module with an alignment, or reading the IP other than as a
+/// condition, since moving its code would change its behaviour.
+pub fn optimize(object: &Object) -> Object {
+    if object.bytes.len() > MEMORY_SIZE {
+        return object.clone();
+    }
+    // The module placed at address 0, with the symbols of the other
+    // modules out of memory
+    let mut memory = [0u8; MEMORY_SIZE];
+    memory[..object.bytes.len()].copy_from_slice(&object.bytes);
+    for relocation in &object.relocations {
+        let offset = relocation.offset as usize;
+        let address = object.symbols.iter().find(|s| s.name == relocation.symbol);
+        let value = address.and_then(|symbol| {
+            let address = symbol.offset as i64;
+            let value = match relocation.negated {
+                true => relocation.addend as i64 - address,
+                false => address + relocation.addend as i64,
+            };
+            i16::try_from(value).ok()
+        });
+        if offset + 2 <= object.bytes.len() {
+            let value = value.unwrap_or(i16::MAX);
+            memory[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    let exploration = explore(&memory, 0);
+    let mut instructions: Vec<Instruction> = Vec::new();
+    for (&offset, &length) in &exploration.lengths {
+        let Ok(instruction_type) = InstructionType::from_opcode(memory[offset]) else {
+            continue;
+        };
+        let bytes = &memory[offset..(offset + length).min(MEMORY_SIZE)];
+        let operand_positions = register_operands(instruction_type);
+        if offset + length > object.bytes.len()
+            || length != instruction_type.length()
+            || operand_positions
+                .iter()
+                .any(|&i| bytes[i] as usize >= NREGS)
+        {
+            continue;
+        }
+        // Instructions overlapping each other cannot be moved apart
+        if let Some(last) = instructions.last() {
+            if last.offset + last.length > offset {
+                return object.clone();
+            }
+        }
+        let mut operands = [0; 3];
+        for (operand, &i) in operands.iter_mut().zip(operand_positions) {
+            *operand = bytes[i] as usize;
+        }
+        let relocations: Vec<Relocation> = object
+            .relocations
+            .iter()
+            .filter(|r| (offset..offset + length).contains(&(r.offset as usize)))
+            .cloned()
+            .collect();
+        let immediate = match instruction_type {
+            InstructionType::LoadImm if relocations.is_empty() => {
+                Some(i16::from_le_bytes([bytes[2], bytes[3]]) as u32)
+            }
+            _ => None,
+        };
+        instructions.push(Instruction {
+            offset,
+            length,
+            instruction_type,
+            operands,
+            immediate,
+            relocations,
+            removed: false,
+        });
+    }
+
+    let index = instructions
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| (instruction.offset, i))
+        .collect();
+    let mut boundaries = exploration.targets;
+    boundaries.extend(object.symbols.iter().map(|s| s.offset as usize));
+    let compact = object.alignment <= 1 && !instructions.iter().any(Instruction::reads_ip);
+    let mut optimizer = Optimizer {
+        object,
+        instructions,
+        index,
+        boundaries,
+        compact,
+    };
+    loop {
+        let mut changed = optimizer.thread_jumps();
+        if optimizer.compact {
+            changed |= optimizer.remove_reloads();
+            changed |= optimizer.remove_dead_writes();
+        }
+        if !changed {
+            break;
+        }
+    }
+    optimizer.rebuild()
+}
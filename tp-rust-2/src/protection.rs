@@ -0,0 +1,71 @@
+/// Kind of memory access performed by the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Reading data with `load`.
+    Read,
+    /// Writing data with `store`.
+    Write,
+    /// Fetching an instruction.
+    Execute,
+}
+
+/// Accesses allowed on a memory region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions {
+        read: false,
+        write: false,
+        execute: false,
+    };
+    pub const R: Permissions = Permissions {
+        read: true,
+        write: false,
+        execute: false,
+    };
+    pub const RW: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: false,
+    };
+    pub const RX: Permissions = Permissions {
+        read: true,
+        write: false,
+        execute: true,
+    };
+    pub const RWX: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: true,
+    };
+
+    /// Tells whether the given access is allowed.
+    pub fn allows(self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+/// A range of memory addresses, from `start` included to `end` excluded,
+/// sharing the same permissions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub permissions: Permissions,
+}
+
+impl Region {
+    /// Tells whether `address` is part of the region.
+    pub fn contains(&self, address: usize) -> bool {
+        self.start <= address && address < self.end
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::disassemble::describe;
+use crate::machine::{InstructionType, IP, MEMORY_SIZE};
+use crate::verify::{explore, load_program, Diagnostic, Program};
+
+/// Header of the generated code, documenting how to use it.
+const PRELUDE: &str = "\
+// Generated by `tp-rust-2 translate`.
+//
+// `run` executes the program with the same semantics as the interpreter
+// using the default memory policy and no memory protection. Every
+// instruction found by the verifier is translated. When the program jumps
+// to another address, stores into its own code or reaches a `brk`
+// instruction, `run` stops before that instruction and returns
+// `Stop::Fallback`: the execution can then resume in the interpreter from
+// `regs` and `memory`.
+
+/// How the translated program stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The program executed `exit` or `exit rA`, with the given exit code.
+    Exit(u32),
+    /// The instruction at `regs[0]` must be run by the interpreter.
+    Fallback,
+}
+";
+
+/// Tells whether the instruction may change the IP other than by
+/// advancing to the next instruction.
+fn ends_block(instruction_type: InstructionType, instruction: &[u8]) -> bool {
+    match instruction_type {
+        InstructionType::MoveIf
+        | InstructionType::Load
+        | InstructionType::LoadImm
+        | InstructionType::Sub => instruction[1] as usize == IP,
+        InstructionType::Exit | InstructionType::ExitWith | InstructionType::Brk => true,
+        InstructionType::Store | InstructionType::Out | InstructionType::OutNumber => false,
+    }
+}
+
+/// Translate a program, the content of an image file or a raw program,
+/// into Rust source code defining:
+///   - `PROGRAM`, the memory content from address 0 to the end of the last
+///     section,
+///   - `ENTRY`, the address where the program starts,
+///   - `memory()`, the initial memory content,
+///   - `Stop`, telling why the execution stopped,
+///   - `run(regs, memory, out)`, running the program from `regs[0]`, which
+///     should be `ENTRY` at first.
+///
+/// The generated code only depends on the standard library. It is a state
+/// machine over the basic blocks of the program, found the same way as in
+/// [verify](crate::verify), which must accept the program.
+pub fn translate(program: &[u8]) -> Result<String, Vec<Diagnostic>> {
+    let Program { memory, end, entry } = load_program(program)?;
+    let exploration = explore(&memory, entry);
+    if !exploration.diagnostics.is_empty() {
+        let mut diagnostics = exploration.diagnostics;
+        diagnostics.sort_by_key(|d| d.address);
+        return Err(diagnostics);
+    }
+    let lengths = exploration.lengths;
+    let decode = |address: usize| {
+        let instruction_type = InstructionType::from_opcode(memory[address]).unwrap();
+        let instruction = &memory[address..address + lengths[&address]];
+        (instruction_type, instruction)
+    };
+
+    // Blocks start at the entry point, at jump targets and after
+    // instructions which may jump
+    let mut leaders: BTreeSet<usize> = exploration.targets;
+    leaders.insert(entry);
+    for &address in lengths.keys() {
+        let (instruction_type, instruction) = decode(address);
+        if ends_block(instruction_type, instruction) {
+            leaders.insert(address + instruction.len());
+        }
+    }
+    leaders.retain(|address| lengths.contains_key(address));
+
+    // Bytes belonging to the code, as inclusive ranges
+    let mut code: Vec<(usize, usize)> = Vec::new();
+    for (&address, &length) in &lengths {
+        match code.last_mut() {
+            Some((_, end)) if address <= *end + 1 => *end = (*end).max(address + length - 1),
+            _ => code.push((address, address + length - 1)),
+        }
+    }
+
+    let mut source = String::from(PRELUDE);
+    writeln!(source).unwrap();
+    writeln!(source, "/// The program bytes, loaded at address 0.").unwrap();
+    writeln!(source, "pub const PROGRAM: &[u8] = &{:?};", &memory[..end]).unwrap();
+    writeln!(source).unwrap();
+    writeln!(source, "/// The initial value of `regs[0]`.").unwrap();
+    writeln!(source, "pub const ENTRY: u32 = {entry};").unwrap();
+    writeln!(source).unwrap();
+    writeln!(source, "/// The memory content before running the program.").unwrap();
+    writeln!(source, "pub fn memory() -> [u8; {MEMORY_SIZE}] {{").unwrap();
+    writeln!(source, "    let mut memory = [0; {MEMORY_SIZE}];").unwrap();
+    writeln!(
+        source,
+        "    memory[..PROGRAM.len()].copy_from_slice(PROGRAM);"
+    )
+    .unwrap();
+    writeln!(source, "    memory").unwrap();
+    writeln!(source, "}}").unwrap();
+    writeln!(source).unwrap();
+    writeln!(
+        source,
+        "/// Whether the byte at `address` is part of the translated code."
+    )
+    .unwrap();
+    writeln!(source, "fn is_code(address: usize) -> bool {{").unwrap();
+    let ranges: Vec<String> = code.iter().map(|(s, e)| format!("{s}..={e}")).collect();
+    writeln!(source, "    matches!(address, {})", ranges.join(" | ")).unwrap();
+    writeln!(source, "}}").unwrap();
+    writeln!(source).unwrap();
+    writeln!(
+        source,
+        "/// Run the program from `regs[0]` until it exits or needs the interpreter."
+    )
+    .unwrap();
+    writeln!(source, "#[allow(unused_variables, clippy::all)]").unwrap();
+    writeln!(source, "pub fn run<W: std::io::Write>(").unwrap();
+    writeln!(source, "    regs: &mut [u32; 16],").unwrap();
+    writeln!(source, "    memory: &mut [u8; {MEMORY_SIZE}],").unwrap();
+    writeln!(source, "    out: &mut W,").unwrap();
+    writeln!(source, ") -> std::io::Result<Stop> {{").unwrap();
+    writeln!(source, "    loop {{").unwrap();
+    writeln!(source, "        match regs[0] {{").unwrap();
+    for &leader in &leaders {
+        writeln!(source, "            {leader} => {{").unwrap();
+        let mut address = leader;
+        loop {
+            let (instruction_type, instruction) = decode(address);
+            translate_instruction(&mut source, address, instruction_type, instruction);
+            address += instruction.len();
+            if ends_block(instruction_type, instruction) || leaders.contains(&address) {
+                break;
+            }
+        }
+        writeln!(source, "            }}").unwrap();
+    }
+    writeln!(source, "            _ => return Ok(Stop::Fallback),").unwrap();
+    writeln!(source, "        }}").unwrap();
+    writeln!(source, "    }}").unwrap();
+    writeln!(source, "}}").unwrap();
+    Ok(source)
+}
+
+/// Append the code executing one instruction.
+fn translate_instruction(
+    source: &mut String,
+    address: usize,
+    instruction_type: InstructionType,
+    instruction: &[u8],
+) {
+    let indent = "                ";
+    let (a, b, c) = (
+        instruction.get(1).copied().unwrap_or(0),
+        instruction.get(2).copied().unwrap_or(0),
+        instruction.get(3).copied().unwrap_or(0),
+    );
+    writeln!(
+        source,
+        "{indent}// {address:04}: {}",
+        describe(instruction_type, instruction, None)
+    )
+    .unwrap();
+    writeln!(source, "{indent}regs[0] = {};", address + instruction.len()).unwrap();
+    let last_word = MEMORY_SIZE - 4;
+    let fallback = [
+        format!("    regs[0] = {address};"),
+        "    return Ok(Stop::Fallback);".to_string(),
+    ];
+    let body: Vec<String> = match instruction_type {
+        InstructionType::MoveIf => vec![format!("if regs[{c}] != 0 {{ regs[{a}] = regs[{b}]; }}")],
+        InstructionType::Store => [
+            format!("let address = regs[{a}] as usize;"),
+            format!("if address > {last_word} || (address..address + 4).any(is_code) {{"),
+        ]
+        .into_iter()
+        .chain(fallback)
+        .chain([
+            "}".to_string(),
+            format!("memory[address..address + 4].copy_from_slice(&regs[{b}].to_le_bytes());"),
+        ])
+        .collect(),
+        InstructionType::Load => [
+            format!("let address = regs[{b}] as usize;"),
+            format!("if address > {last_word} {{"),
+        ]
+        .into_iter()
+        .chain(fallback)
+        .chain([
+            "}".to_string(),
+            format!(
+                "regs[{a}] = u32::from_le_bytes(memory[address..address + 4].try_into().unwrap());"
+            ),
+        ])
+        .collect(),
+        InstructionType::LoadImm => {
+            let imm = i16::from_le_bytes([b, c]) as i32 as u32;
+            vec![format!("regs[{a}] = {imm:#x};")]
+        }
+        InstructionType::Sub => vec![format!("regs[{a}] = regs[{b}].wrapping_sub(regs[{c}]);")],
+        InstructionType::Out => vec![format!(
+            "write!(out, \"{{}}\", (regs[{a}] & 0xFF) as u8 as char)?;"
+        )],
+        InstructionType::Exit => vec!["return Ok(Stop::Exit(0));".to_string()],
+        InstructionType::ExitWith => vec![format!("return Ok(Stop::Exit(regs[{a}]));")],
+        InstructionType::OutNumber => vec![format!("write!(out, \"{{}}\", regs[{a}] as i32)?;")],
+        // Breakpoints are reported by the interpreter
+        InstructionType::Brk => vec![
+            format!("regs[0] = {address};"),
+            "return Ok(Stop::Fallback);".to_string(),
+        ],
+    };
+    if body.len() == 1 {
+        writeln!(source, "{indent}{}", body[0]).unwrap();
+    } else {
+        // Scope the local variables of the instruction
+        writeln!(source, "{indent}{{").unwrap();
+        for line in body {
+            writeln!(source, "{indent}    {line}").unwrap();
+        }
+        writeln!(source, "{indent}}}").unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+
+use crate::arguments::STACK_REGISTER;
+use crate::assemble::assemble;
+use crate::link::{debug_info, link, LinkError};
+use crate::machine::{Machine, MachineError, IP, MEMORY_SIZE};
+use crate::object::Object;
+
+/// Number of instructions after which a test which is still running fails.
+pub const MAX_TEST_STEPS: u64 = 1_000_000;
+
+/// A test written in an assembly source, found by
+/// [assemble_with_tests](crate::assemble_with_tests). Addresses are those
+/// of the module placed at address 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitTest {
+    pub name: String,
+    /// Line of the `.test` directive.
+    pub line: usize,
+    /// The label of the routine to call, or `None` to run the module from
+    /// its beginning.
+    pub routine: Option<String>,
+    /// Registers set before running, in order.
+    pub registers: Vec<(usize, u32)>,
+    /// Bytes written in memory before running, by address, in order.
+    pub memory: Vec<(usize, Vec<u8>)>,
+    pub expected_registers: Vec<(usize, u32)>,
+    pub expected_memory: Vec<(usize, Vec<u8>)>,
+    /// Everything the test must print, if it is checked.
+    pub expected_output: Option<Vec<u8>>,
+}
+
+/// Why [run_test] failed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TestFailure {
+    /// The module cannot be linked on its own.
+    Link(Vec<LinkError>),
+    /// The machine stopped with an error at the given location, as
+    /// described by [DebugInfo::locate](crate::DebugInfo::locate).
+    Error {
+        error: MachineError,
+        location: String,
+    },
+    /// The program was still running after [MAX_TEST_STEPS] instructions.
+    StepLimit,
+    Register {
+        register: usize,
+        expected: u32,
+        found: u32,
+    },
+    Memory {
+        address: usize,
+        expected: Vec<u8>,
+        found: Vec<u8>,
+    },
+    Output {
+        expected: Vec<u8>,
+        found: Vec<u8>,
+    },
+}
+
+impl fmt::Display for TestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestFailure::Link(errors) => {
+                write!(f, "cannot link the module:")?;
+                errors.iter().try_for_each(|error| write!(f, " {error}."))
+            }
+            TestFailure::Error { error, location } => write!(f, "error at {location}: {error:?}"),
+            TestFailure::StepLimit => {
+                write!(f, "still running after {MAX_TEST_STEPS} instructions")
+            }
+            TestFailure::Register {
+                register,
+                expected,
+                found,
+            } => write!(
+                f,
+                "r{register} is {} but {} was expected",
+                *found as i32, *expected as i32
+            ),
+            TestFailure::Memory {
+                address,
+                expected,
+                found,
+            } => write!(
+                f,
+                "memory at {address} is {found:?} but {expected:?} was expected"
+            ),
+            TestFailure::Output { expected, found } => write!(
+                f,
+                "the output is {:?} but {:?} was expected",
+                String::from_utf8_lossy(found),
+                String::from_utf8_lossy(expected)
+            ),
+        }
+    }
+}
+
+/// Run a test of `object`, the module it was written in.
+///
+/// The module is placed at address 0, with the stack pointer at the end of
+/// memory. When a routine is called, a `call` to it followed by `exit` is
+/// placed after the module, at label `ROUTINE_caller`, and run instead of
+/// the module. Then the registers and memory given by the test are set,
+/// and the program runs until it exits, `brk` instructions being ignored.
+/// Like any `call`, the caller overwrites `r3` before the routine starts.
+///
+/// Every expectation which does not hold is returned.
+pub fn run_test(object: &Object, test: &UnitTest) -> Result<(), Vec<TestFailure>> {
+    let mut objects = vec![object.clone()];
+    if let Some(routine) = &test.routine {
+        for symbol in objects[0].symbols.iter_mut().filter(|s| s.name == *routine) {
+            symbol.global = true;
+        }
+        let caller = format!("{routine}_caller:\ncall {routine}\nexit");
+        objects.push(assemble(&caller).unwrap());
+    }
+    let image = link(&objects).map_err(|errors| vec![TestFailure::Link(errors)])?;
+    let mut memory = image.sections[0].content().to_vec();
+    memory.resize(MEMORY_SIZE, 0);
+    for (address, bytes) in &test.memory {
+        memory[*address..*address + bytes.len()].copy_from_slice(bytes);
+    }
+
+    let mut machine = Machine::new_quiet(&memory);
+    let entry = match test.routine {
+        Some(_) => object.bytes.len(),
+        None => 0,
+    };
+    machine.set_reg(IP, entry as u32).unwrap();
+    machine.set_reg(STACK_REGISTER, MEMORY_SIZE as u32).unwrap();
+    for &(register, value) in &test.registers {
+        machine.set_reg(register, value).unwrap();
+    }
+    let mut output = Vec::new();
+    let mut steps = 0;
+    loop {
+        match machine.step_on(&mut output) {
+            Ok(true) => break,
+            Ok(false) => (),
+            Err(error) => {
+                let ip = machine.last_ip();
+                // The caller has no source file
+                let location = match &test.routine {
+                    Some(routine) if ip >= entry => format!("{routine}_caller+{}", ip - entry),
+                    _ => debug_info(&objects).locate(ip),
+                };
+                return Err(vec![TestFailure::Error { error, location }]);
+            }
+        }
+        steps += 1;
+        if steps == MAX_TEST_STEPS {
+            return Err(vec![TestFailure::StepLimit]);
+        }
+    }
+
+    let mut failures = Vec::new();
+    for &(register, expected) in &test.expected_registers {
+        let found = machine.regs()[register];
+        if found != expected {
+            failures.push(TestFailure::Register {
+                register,
+                expected,
+                found,
+            });
+        }
+    }
+    for (address, expected) in &test.expected_memory {
+        let found = &machine.memory()[*address..*address + expected.len()];
+        if found != expected {
+            failures.push(TestFailure::Memory {
+                address: *address,
+                expected: expected.clone(),
+                found: found.to_vec(),
+            });
+        }
+    }
+    match &test.expected_output {
+        Some(expected) if *expected != output => failures.push(TestFailure::Output {
+            expected: expected.clone(),
+            found: output,
+        }),
+        _ => (),
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+
+use crate::image::{Image, ImageError};
+use crate::machine::{InstructionType, IP, MEMORY_SIZE, NREGS};
+
+/// A problem found by [verify] in the code reachable from the entry point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Address of the offending instruction.
+    pub address: usize,
+    pub kind: DiagnosticKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The program does not fit in the machine memory.
+    ProgramTooLarge(usize),
+    /// The image file cannot be loaded.
+    InvalidImage(ImageError),
+    /// The byte at this address is not a valid opcode.
+    InvalidInstruction(u8),
+    /// An operand designates a register which does not exist.
+    InvalidRegister(usize),
+    /// The instruction does not fit before the end of memory.
+    TruncatedInstruction { length: usize },
+    /// Control is transferred to an address outside of memory.
+    InvalidJumpTarget(usize),
+    /// A store to a constant address overwrites reachable code.
+    StoreIntoCode(usize),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}: ", self.address)?;
+        match &self.kind {
+            DiagnosticKind::ProgramTooLarge(size) => write!(
+                f,
+                "program is {size} bytes long but memory is {MEMORY_SIZE} bytes"
+            ),
+            DiagnosticKind::InvalidImage(error) => write!(f, "{error}"),
+            DiagnosticKind::InvalidInstruction(opcode) => write!(f, "invalid opcode {opcode}"),
+            DiagnosticKind::InvalidRegister(reg) => write!(f, "invalid register r{reg}"),
+            DiagnosticKind::TruncatedInstruction { length } => {
+                write!(f, "{length}-byte instruction goes past the end of memory")
+            }
+            DiagnosticKind::InvalidJumpTarget(target) => {
+                write!(f, "control reaches address {target} outside of memory")
+            }
+            DiagnosticKind::StoreIntoCode(target) => {
+                write!(f, "store to address {target} overwrites code")
+            }
+        }
+    }
+}
+
+/// What is known about the machine when reaching a given address.
+/// Registers hold `Some(value)` when their value is the same on every
+/// path, and `stored` lists the constants which have been stored in
+/// memory on every path (used to recognize return addresses).
+#[derive(Clone, PartialEq, Eq)]
+struct State {
+    regs: [Option<u32>; NREGS],
+    stored: Vec<u32>,
+}
+
+impl State {
+    /// Keep only what is known in both states. Returns `true` if `self`
+    /// has changed.
+    fn merge(&mut self, other: &State) -> bool {
+        let before = self.clone();
+        for (mine, theirs) in self.regs.iter_mut().zip(other.regs.iter()) {
+            if mine != theirs {
+                *mine = None;
+            }
+        }
+        self.stored.retain(|v| other.stored.contains(v));
+        *self != before
+    }
+}
+
+/// Returns the positions of the register operands of an instruction.
+pub(crate) fn register_operands(instruction_type: InstructionType) -> &'static [usize] {
+    match instruction_type {
+        InstructionType::MoveIf | InstructionType::Sub => &[1, 2, 3],
+        InstructionType::Store | InstructionType::Load => &[1, 2],
+        InstructionType::LoadImm
+        | InstructionType::Out
+        | InstructionType::OutNumber
+        | InstructionType::ExitWith => &[1],
+        InstructionType::Exit | InstructionType::Brk => &[],
+    }
+}
+
+/// Code found by [explore].
+pub(crate) struct Exploration {
+    /// Length of the reachable instructions, by address.
+    pub(crate) lengths: BTreeMap<usize, usize>,
+    /// Addresses that control is transferred to by a jump or a return
+    /// from a call, as opposed to flowing from the previous instruction.
+    pub(crate) targets: BTreeSet<usize>,
+    /// Address called by each jump considered to be a call, by address of
+    /// the jump.
+    pub(crate) calls: BTreeMap<usize, usize>,
+    /// Problems found, in no particular order.
+    pub(crate) diagnostics: Vec<Diagnostic>,
+}
+
+/// A program loaded in an empty memory.
+pub(crate) struct Program {
+    pub(crate) memory: [u8; MEMORY_SIZE],
+    /// Address following the last section.
+    pub(crate) end: usize,
+    pub(crate) entry: usize,
+}
+
+/// Load `program`, the content of an image file or a raw program, as
+/// [Image::load] reads it.
+pub(crate) fn load_program(program: &[u8]) -> Result<Program, Vec<Diagnostic>> {
+    let image = Image::load(program).map_err(|error| {
+        let kind = match error {
+            ImageError::ProgramTooLarge(size) => DiagnosticKind::ProgramTooLarge(size),
+            error => DiagnosticKind::InvalidImage(error),
+        };
+        vec![Diagnostic { address: 0, kind }]
+    })?;
+    let mut memory = [0u8; MEMORY_SIZE];
+    let mut end = 0;
+    for section in &image.sections {
+        let start = section.address as usize;
+        let content = section.content();
+        memory[start..start + content.len()].copy_from_slice(&content);
+        end = end.max(start + content.len());
+    }
+    Ok(Program {
+        memory,
+        end,
+        entry: image.entry as usize,
+    })
+}
+
+/// Check a program before running it, given as the content of an image
+/// file or as a raw program. Starting at its entry point, it follows
+/// every path whose target can be computed from constants loaded with
+/// `loadimm`. A jump following the store of its own return address is
+/// considered to be a call and execution is assumed to resume after it.
+/// Jumps to addresses which are only known at run time (such as returns)
+/// are not followed.
+///
+/// All the problems found are returned, sorted by address.
+pub fn verify(program: &[u8]) -> Result<(), Vec<Diagnostic>> {
+    let program = load_program(program)?;
+    let mut diagnostics = explore(&program.memory, program.entry).diagnostics;
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        diagnostics.sort_by_key(|d| d.address);
+        Err(diagnostics)
+    }
+}
+
+/// Find the code reachable from `entry` as described in [verify].
+pub(crate) fn explore(memory: &[u8; MEMORY_SIZE], entry: usize) -> Exploration {
+    let mut diagnostics = Vec::new();
+    // Reachable instructions along with the state before their execution
+    let mut states: BTreeMap<usize, State> = BTreeMap::new();
+    let mut lengths: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut targets = BTreeSet::new();
+    let mut calls = BTreeMap::new();
+    // (address of the store, target address)
+    let mut constant_stores = Vec::new();
+    let mut worklist = VecDeque::new();
+
+    if entry < MEMORY_SIZE {
+        let state = State {
+            regs: [None; NREGS],
+            stored: Vec::new(),
+        };
+        states.insert(entry, state);
+        worklist.push_back(entry);
+    } else {
+        diagnostics.push(Diagnostic {
+            address: entry,
+            kind: DiagnosticKind::InvalidJumpTarget(entry),
+        });
+    }
+
+    while let Some(address) = worklist.pop_front() {
+        let first_visit = !lengths.contains_key(&address);
+        let mut state = states[&address].clone();
+
+        let instruction_type = match InstructionType::from_opcode(memory[address]) {
+            Ok(t) => t,
+            Err(_) => {
+                if first_visit {
+                    diagnostics.push(Diagnostic {
+                        address,
+                        kind: DiagnosticKind::InvalidInstruction(memory[address]),
+                    });
+                    lengths.insert(address, 1);
+                }
+                continue;
+            }
+        };
+        let length = instruction_type.length();
+        lengths.insert(address, length);
+        if address + length > MEMORY_SIZE {
+            if first_visit {
+                diagnostics.push(Diagnostic {
+                    address,
+                    kind: DiagnosticKind::TruncatedInstruction { length },
+                });
+            }
+            continue;
+        }
+        let instruction = &memory[address..address + length];
+        let bad_registers: Vec<usize> = register_operands(instruction_type)
+            .iter()
+            .map(|&i| instruction[i] as usize)
+            .filter(|&r| r >= NREGS)
+            .collect();
+        if !bad_registers.is_empty() {
+            if first_visit {
+                diagnostics.extend(bad_registers.into_iter().map(|reg| Diagnostic {
+                    address,
+                    kind: DiagnosticKind::InvalidRegister(reg),
+                }));
+            }
+            continue;
+        }
+
+        let next = address + length;
+        let operand = |i: usize| instruction.get(i).copied().unwrap_or(0) as usize;
+        let (a, b, c) = (operand(1), operand(2), operand(3));
+        // The IP has already been advanced when the instruction executes
+        state.regs[IP] = Some(next as u32);
+
+        let mut successors: Vec<Option<u32>> = Vec::new();
+        let mut falls_through = true;
+        let mut jump = |state: &State, target: Option<u32>| {
+            successors.push(target);
+            // A call: the return address has been saved beforehand
+            if state.stored.contains(&(next as u32)) {
+                successors.push(Some(next as u32));
+                if let Some(target) = target {
+                    calls.insert(address, target as usize);
+                }
+            }
+            targets.extend(successors.iter().flatten().map(|&t| t as usize));
+        };
+        match instruction_type {
+            InstructionType::MoveIf => match state.regs[c] {
+                Some(0) => (),
+                Some(_) if a == IP => {
+                    jump(&state, state.regs[b]);
+                    falls_through = false;
+                }
+                Some(_) => state.regs[a] = state.regs[b],
+                None if a == IP => jump(&state, state.regs[b]),
+                None => {
+                    if state.regs[a] != state.regs[b] {
+                        state.regs[a] = None;
+                    }
+                }
+            },
+            InstructionType::Store => {
+                if let Some(target) = state.regs[a] {
+                    constant_stores.push((address, target as usize));
+                }
+                if let Some(value) = state.regs[b] {
+                    if !state.stored.contains(&value) {
+                        state.stored.push(value);
+                    }
+                }
+            }
+            InstructionType::Load => {
+                state.regs[a] = None;
+            }
+            InstructionType::LoadImm => {
+                let imm = u16::from_le_bytes([instruction[2], instruction[3]]);
+                state.regs[a] = Some(imm as i16 as i32 as u32);
+            }
+            InstructionType::Sub => {
+                state.regs[a] = match (state.regs[b], state.regs[c]) {
+                    (Some(x), Some(y)) => Some(x.wrapping_sub(y)),
+                    _ => None,
+                };
+            }
+            InstructionType::Out | InstructionType::OutNumber | InstructionType::Brk => (),
+            InstructionType::Exit | InstructionType::ExitWith => falls_through = false,
+        }
+        if a == IP
+            && matches!(
+                instruction_type,
+                InstructionType::Load | InstructionType::LoadImm | InstructionType::Sub
+            )
+        {
+            jump(&state, state.regs[IP]);
+            falls_through = false;
+        }
+        if falls_through {
+            successors.push(Some(next as u32));
+        }
+
+        // Unknown targets (`None`) cannot be followed
+        for target in successors.into_iter().flatten() {
+            let target = target as usize;
+            if target >= MEMORY_SIZE {
+                let diagnostic = Diagnostic {
+                    address,
+                    kind: DiagnosticKind::InvalidJumpTarget(target),
+                };
+                if !diagnostics.contains(&diagnostic) {
+                    diagnostics.push(diagnostic);
+                }
+                continue;
+            }
+            let changed = match states.get_mut(&target) {
+                Some(known) => known.merge(&state),
+                None => {
+                    states.insert(target, state.clone());
+                    true
+                }
+            };
+            if changed || !lengths.contains_key(&target) {
+                worklist.push_back(target);
+            }
+        }
+    }
+
+    for (address, target) in constant_stores {
+        // A store writes 4 bytes, check whether they overlap an instruction
+        let overlaps = lengths
+            .range(target.saturating_sub(3)..target.saturating_add(4))
+            .any(|(&start, &length)| start + length > target);
+        let diagnostic = Diagnostic {
+            address,
+            kind: DiagnosticKind::StoreIntoCode(target),
+        };
+        if overlaps && !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    Exploration {
+        lengths,
+        targets,
+        calls,
+        diagnostics,
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::protection::Access;
+
+/// Which data accesses trigger a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// `load` instructions.
+    Read,
+    /// `store` instructions.
+    Write,
+    /// Both `load` and `store` instructions.
+    Access,
+}
+
+impl WatchKind {
+    /// Tells whether the given access triggers this kind of watchpoint.
+    pub fn triggered_by(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::Read, Access::Read)
+                | (WatchKind::Write, Access::Write)
+                | (WatchKind::Access, Access::Read | Access::Write)
+        )
+    }
+}
+
+/// A range of memory addresses, from `start` included to `end` excluded,
+/// whose accesses pause the execution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub start: usize,
+    pub end: usize,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    /// Tells whether the watchpoint covers some of the bytes in `range`.
+    pub fn overlaps(&self, range: Range<usize>) -> bool {
+        self.start < range.end && range.start < self.end
+    }
+}
+
+/// Description of the access which triggered a watchpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchpointHit {
+    /// Identifier of the watchpoint, as returned by
+    /// [add_watchpoint](crate::Machine::add_watchpoint).
+    pub id: usize,
+    /// Address of the instruction doing the access.
+    pub ip: usize,
+    /// Address of the first byte accessed.
+    pub address: usize,
+    pub access: Access,
+    /// The word read or written.
+    pub value: u32,
+}
+
+impl fmt::Display for WatchpointHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operation = match self.access {
+            Access::Read => "load of",
+            Access::Write => "store of",
+            Access::Execute => "execution of",
+        };
+        write!(
+            f,
+            "watchpoint {}: {operation} {} at address {} by instruction at {}",
+            self.id, self.value as i32, self.address, self.ip
+        )
+    }
+}
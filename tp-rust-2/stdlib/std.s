@@ -0,0 +1,468 @@
+; Standard library, included with `.include "std.s"` or assembled and
+; linked as a module of its own.
+;
+; Calling convention: routines are called with `call` and return with
+; `ret`, r2 being the stack pointer. Arguments are passed in r10, r11 and
+; r12, and the result is returned in r11. Routines may modify r3, r8, r9
+; and r10 to r12, and preserve the other registers. They may use the
+; memory just below the stack.
+;
+; Routines: print, print_hex, mult, divmod, memcpy, memset, strlen and
+; alloc.
+
+.global print, print_hex, mult, divmod, memcpy, memset, strlen, alloc
+
+; print: write the r11 bytes starting at address r10. r10 ends after them.
+print:
+    loadimm r3 <- #print_next
+    move r0 <- r3 if r11 != 0
+    ret
+print_next:
+    load r8 <- [r10]
+    out r8
+    add r10 <- r10 + #1
+    add r11 <- r11 + #-1
+    jmp print
+
+; print_hex: write r10 as 8 lowercase hexadecimal digits. Each byte is
+; isolated by storing r10 on the stack followed by a zero word, then split
+; into two digits with divmod.
+print_hex:
+    push r13
+    push r14
+    mov r14 <- r10
+    add r2 <- r2 + #-8
+    ; Number of bytes left, the next one being at r2 + r13 - 1
+    loadimm r13 <- #4
+print_hex_byte:
+    store [r2] <- r14
+    add r9 <- r2 + r13
+    loadimm r8 <- #0
+    store [r9] <- r8
+    add r9 <- r9 + #-1
+    load r11 <- [r9]
+    loadimm r12 <- #16
+    call divmod
+    add r8 <- r11 + #std_hex_digits
+    load r8 <- [r8]
+    out r8
+    add r8 <- r12 + #std_hex_digits
+    load r8 <- [r8]
+    out r8
+    add r13 <- r13 + #-1
+    loadimm r3 <- #print_hex_byte
+    move r0 <- r3 if r13 != 0
+    add r2 <- r2 + #8
+    pop r14
+    pop r13
+    ret
+
+; mult: r11 = r11 * r12, wrapping around like sub. The powers of 2 up to
+; the absolute value of r12 are pushed with r11 times each of them, then
+; the largest ones adding up to it are taken, so it runs in time
+; proportional to the number of bits of r12.
+mult:
+    ; Make r12 positive by negating both factors
+    mov r8 <- r12
+    call std_negative
+    loadimm r3 <- #0
+    sub r8 <- r3 - r12
+    move r12 <- r8 if r9 != 0
+    sub r8 <- r3 - r11
+    move r11 <- r8 if r9 != 0
+    mov r10 <- r11
+    loadimm r11 <- #0
+    ; -2147483648 is still negative: r10 is added once and r12 becomes
+    ; 2147483647
+    mov r8 <- r12
+    call std_negative
+    move r11 <- r10 if r9 != 0
+    add r8 <- r12 + #-1
+    move r12 <- r8 if r9 != 0
+    push r13
+    ; The multiples of r10 are pushed above a 0, each followed by its power
+    loadimm r13 <- #0
+    push r13
+    loadimm r13 <- #1
+mult_double:
+    sub r8 <- r12 - r13
+    call std_negative
+    loadimm r3 <- #mult_loop
+    move r0 <- r3 if r9 != 0
+    push r10
+    push r13
+    add r10 <- r10 + r10
+    add r13 <- r13 + r13
+    jmp mult_double
+mult_loop:
+    pop r13
+    loadimm r3 <- #mult_next
+    move r0 <- r3 if r13 != 0
+    pop r13
+    ret
+mult_next:
+    pop r10
+    sub r8 <- r12 - r13
+    call std_negative
+    loadimm r3 <- #mult_loop
+    move r0 <- r3 if r9 != 0
+    mov r12 <- r8
+    add r11 <- r11 + r10
+    jmp mult_loop
+
+; divmod: r11 = r11 / r12 rounded towards 0 and r12 = r11 % r12, which has
+; the sign of r11, by long division of the absolute values: the multiples
+; of the divisor by powers of 2 up to the dividend are pushed, then
+; subtracted from the largest one when they fit, so it runs in time
+; proportional to the number of bits of the quotient. -2147483648 /
+; -1 wraps around to -2147483648. Division by 0 stops on a breakpoint,
+; then gives 0 and r11.
+divmod:
+    loadimm r3 <- #divmod_start
+    move r0 <- r3 if r12 != 0
+    brk
+    mov r12 <- r11
+    loadimm r11 <- #0
+    ret
+divmod_start:
+    push r13
+    push r14
+    ; r13 is 1 when the remainder is negative, r14 when the quotient is
+    mov r8 <- r11
+    call std_negative
+    mov r13 <- r9
+    mov r14 <- r9
+    loadimm r3 <- #0
+    sub r8 <- r3 - r11
+    move r11 <- r8 if r9 != 0
+    mov r8 <- r12
+    call std_negative
+    loadimm r3 <- #1
+    sub r8 <- r3 - r14
+    move r14 <- r8 if r9 != 0
+    loadimm r3 <- #0
+    sub r8 <- r3 - r12
+    move r12 <- r8 if r9 != 0
+    loadimm r10 <- #0
+    ; -2147483648 is still negative: the divisor is subtracted from it once
+    mov r8 <- r11
+    call std_negative
+    sub r8 <- r11 - r12
+    move r11 <- r8 if r9 != 0
+    move r10 <- r9 if r9 != 0
+    ; r13 and r14 are kept on the stack, below the multiples
+    push r13
+    push r14
+    ; A divisor of -2147483648 is larger than what is left of the dividend
+    mov r8 <- r12
+    call std_negative
+    loadimm r3 <- #divmod_end
+    move r0 <- r3 if r9 != 0
+    ; The multiples are pushed above a 0, each followed by its power
+    loadimm r14 <- #0
+    push r14
+    mov r13 <- r12
+    loadimm r14 <- #1
+divmod_double:
+    sub r8 <- r11 - r13
+    call std_negative
+    loadimm r3 <- #divmod_loop
+    move r0 <- r3 if r9 != 0
+    push r13
+    push r14
+    add r13 <- r13 + r13
+    add r14 <- r14 + r14
+    jmp divmod_double
+divmod_loop:
+    pop r14
+    loadimm r3 <- #divmod_next
+    move r0 <- r3 if r14 != 0
+divmod_end:
+    pop r14
+    pop r13
+    mov r12 <- r11
+    loadimm r3 <- #0
+    sub r8 <- r3 - r12
+    move r12 <- r8 if r13 != 0
+    mov r11 <- r10
+    loadimm r3 <- #0
+    sub r8 <- r3 - r11
+    move r11 <- r8 if r14 != 0
+    pop r14
+    pop r13
+    ret
+divmod_next:
+    pop r13
+    sub r8 <- r11 - r13
+    call std_negative
+    loadimm r3 <- #divmod_loop
+    move r0 <- r3 if r9 != 0
+    mov r11 <- r8
+    add r10 <- r10 + r14
+    jmp divmod_loop
+
+; memcpy: copy the r12 bytes starting at address r11 to address r10. The
+; regions must not overlap. Each byte is copied with the 3 bytes after it,
+; and the word after the destination is restored at the end, so the 3
+; bytes after each region must be in memory. r10 and r11 end after the
+; regions.
+memcpy:
+    add r8 <- r10 + r12
+    load r9 <- [r8]
+memcpy_loop:
+    loadimm r3 <- #memcpy_next
+    move r0 <- r3 if r12 != 0
+    store [r10] <- r9
+    ret
+memcpy_next:
+    load r8 <- [r11]
+    store [r10] <- r8
+    add r10 <- r10 + #1
+    add r11 <- r11 + #1
+    add r12 <- r12 + #-1
+    jmp memcpy_loop
+
+; memset: set the r12 bytes starting at address r10 to the byte r11, which
+; must be less than 256. Like for memcpy, the 3 bytes after them must be in
+; memory. r10 ends after them.
+memset:
+    add r8 <- r10 + r12
+    load r9 <- [r8]
+memset_loop:
+    loadimm r3 <- #memset_next
+    move r0 <- r3 if r12 != 0
+    store [r10] <- r9
+    ret
+memset_next:
+    store [r10] <- r11
+    add r10 <- r10 + #1
+    add r12 <- r12 + #-1
+    jmp memset_loop
+
+; strlen: r11 = the number of bytes before the first zero byte starting at
+; address r10. r10 ends at the zero byte.
+strlen:
+    loadimm r11 <- #0
+strlen_loop:
+    load r8 <- [r10]
+    call std_low_byte
+    loadimm r3 <- #strlen_next
+    move r0 <- r3 if r8 != 0
+    ret
+strlen_next:
+    add r10 <- r10 + #1
+    add r11 <- r11 + #1
+    jmp strlen_loop
+
+; alloc: r11 = the address of a new block of r10 bytes, rounded up to a
+; multiple of 4, or 0 if the block would reach the stack. Blocks are taken
+; one after the other, from the end of the library which must come last in
+; the program, and never freed.
+alloc:
+    add r11 <- r10 + #3
+    loadimm r12 <- #4
+    call divmod
+    add r11 <- r11 + r11
+    add r10 <- r11 + r11
+    ; The first block starts at std_heap
+    loadimm r9 <- #std_heap_top
+    load r11 <- [r9]
+    loadimm r3 <- #alloc_check
+    move r0 <- r3 if r11 != 0
+    loadimm r11 <- #std_heap
+alloc_check:
+    ; The block must end at or below the stack pointer
+    add r10 <- r11 + r10
+    sub r8 <- r10 - r2
+    add r8 <- r8 + #-1
+    call std_negative
+    loadimm r3 <- #alloc_done
+    move r0 <- r3 if r9 != 0
+    loadimm r11 <- #0
+    ret
+alloc_done:
+    loadimm r9 <- #std_heap_top
+    store [r9] <- r10
+    ret
+
+; std_negative: r9 = 1 if r8 is negative, 0 otherwise. r8 is stored below
+; the stack, followed by a zero word, so that its top byte can be loaded
+; alone. It indexes a table whose words are not zero from 128.
+std_negative:
+    loadimm r9 <- #4
+    sub r3 <- r2 - r9
+    loadimm r9 <- #0
+    store [r3] <- r9
+    loadimm r9 <- #4
+    sub r3 <- r3 - r9
+    store [r3] <- r8
+    loadimm r9 <- #-3
+    sub r3 <- r3 - r9
+    load r9 <- [r3]
+    loadimm r3 <- #0
+    sub r9 <- r3 - r9
+    loadimm r3 <- #std_sign_table
+    sub r3 <- r3 - r9
+    load r9 <- [r3]
+    loadimm r3 <- #1
+    move r9 <- r3 if r9 != 0
+    ret
+
+; std_low_byte: r8 = the low byte of r8, stored below the stack and
+; followed by a zero word from its second byte.
+std_low_byte:
+    loadimm r9 <- #8
+    sub r3 <- r2 - r9
+    store [r3] <- r8
+    loadimm r9 <- #-1
+    sub r3 <- r3 - r9
+    loadimm r9 <- #0
+    store [r3] <- r9
+    loadimm r9 <- #1
+    sub r3 <- r3 - r9
+    load r8 <- [r3]
+    ret
+
+std_hex_digits:
+    .ascii "0123456789abcdef"
+; Words read at the top byte of a value, not zero from 128
+std_sign_table:
+    .space 131
+    .space 128, 1
+std_heap_top:
+    .word 0
+    .align 4
+std_heap:
+
+.test print_bytes
+    .call print
+    .set [3000], "Hello\n"
+    .set r10, 3000
+    .set r11, 6
+    .expect r10, 3006
+    .output "Hello\n"
+.endtest
+
+.test print_hex_digits
+    .call print_hex
+    .set r10, 0x12af
+    .set r13, 7
+    .set r14, 8
+    .expect r13, 7
+    .expect r14, 8
+    .expect sp, 4096
+    .output "000012af"
+.endtest
+
+.test print_hex_negative
+    .call print_hex
+    .set r10, -2
+    .output "fffffffe"
+.endtest
+
+.test mult_positive
+    .call mult
+    .set r11, 12
+    .set r12, 5
+    .expect r11, 60
+.endtest
+
+.test mult_signs
+    .call mult
+    .set r11, 7
+    .set r12, -3
+    .expect r11, -21
+.endtest
+
+.test mult_zero
+    .call mult
+    .set r11, -7
+    .set r12, 0
+    .expect r11, 0
+.endtest
+
+.test divmod_positive
+    .call divmod
+    .set r11, 47
+    .set r12, 5
+    .set r13, 1
+    .set r14, 2
+    .expect r11, 9
+    .expect r12, 2
+    .expect r13, 1
+    .expect r14, 2
+    .expect sp, 4096
+.endtest
+
+.test divmod_signs
+    .call divmod
+    .set r11, -47
+    .set r12, 5
+    .expect r11, -9
+    .expect r12, -2
+.endtest
+
+.test divmod_negative_divisor
+    .call divmod
+    .set r11, 47
+    .set r12, -5
+    .expect r11, -9
+    .expect r12, 2
+.endtest
+
+.test divmod_by_zero
+    .call divmod
+    .set r11, 47
+    .set r12, 0
+    .expect r11, 0
+    .expect r12, 47
+.endtest
+
+.test memcpy_bytes
+    .call memcpy
+    .set [3000], "abcdefg"
+    .set [3100], "0123456789"
+    .set r10, 3101
+    .set r11, 3000
+    .set r12, 5
+    .expect [3100], "0abcde6789"
+    .expect r10, 3106
+.endtest
+
+.test memset_bytes
+    .call memset
+    .set [3100], "0123456789"
+    .set r10, 3102
+    .set r11, 'x'
+    .set r12, 3
+    .expect [3100], "01xxx56789"
+.endtest
+
+.test strlen_string
+    .call strlen
+    .set [3000], "hello", 0, "world"
+    .set r10, 3000
+    .expect r11, 5
+    .expect r10, 3005
+.endtest
+
+.test strlen_empty
+    .call strlen
+    .set [3000], 0
+    .set r10, 3000
+    .expect r11, 0
+.endtest
+
+.test alloc_blocks
+    .call alloc
+    .set r10, 5
+    .expect r11, std_heap
+    .expect [std_heap_top], std_heap + 8
+.endtest
+
+.test alloc_too_large
+    .call alloc
+    .set r10, 4000
+    .expect r11, 0
+    .expect [std_heap_top], 0
+.endtest
@@ -0,0 +1,151 @@
+use interpreter::{
+    Machine, MachineError, MemoryPolicy, StopReason, ARGC_REGISTER, ARGV_REGISTER, ENVP_REGISTER,
+    STACK_REGISTER,
+};
+
+fn word(machine: &Machine, address: u32) -> u32 {
+    let address = address as usize;
+    u32::from_le_bytes(machine.memory()[address..address + 4].try_into().unwrap())
+}
+
+/// The nul-terminated strings pointed to by the list at `address`.
+fn strings(machine: &Machine, mut address: u32) -> Vec<String> {
+    let mut strings = Vec::new();
+    loop {
+        let pointer = word(machine, address) as usize;
+        if pointer == 0 {
+            return strings;
+        }
+        let length = machine.memory()[pointer..]
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap();
+        strings
+            .push(String::from_utf8(machine.memory()[pointer..pointer + length].to_vec()).unwrap());
+        address += 4;
+    }
+}
+
+#[test]
+fn layout() {
+    let mut machine = Machine::new(&[7]);
+    machine
+        .set_arguments(&["prog.bin", "12", ""], &[("HOME", "/root")])
+        .unwrap();
+    let regs = machine.regs();
+    assert_eq!(3, regs[ARGC_REGISTER]);
+    assert_eq!(
+        vec!["prog.bin", "12", ""],
+        strings(&machine, regs[ARGV_REGISTER])
+    );
+    assert_eq!(vec!["HOME=/root"], strings(&machine, regs[ENVP_REGISTER]));
+    assert_eq!(0, regs[STACK_REGISTER] % 4);
+    assert_eq!(regs[STACK_REGISTER], regs[ENVP_REGISTER]);
+    assert!(regs[ARGV_REGISTER].is_multiple_of(4));
+    for i in 0..3 {
+        assert!(word(&machine, regs[ARGV_REGISTER] + 4 * i).is_multiple_of(4));
+    }
+    // Every byte of the strings can be read as part of a word
+    assert_eq!(0, word(&machine, 4092));
+}
+
+#[test]
+fn no_arguments() {
+    let mut machine = Machine::new(&[7]);
+    machine.set_arguments(&[], &[]).unwrap();
+    let regs = machine.regs();
+    assert_eq!(0, regs[ARGC_REGISTER]);
+    assert_eq!(0, word(&machine, regs[ARGV_REGISTER]));
+    assert_eq!(0, word(&machine, regs[ENVP_REGISTER]));
+    assert_eq!(4084, regs[STACK_REGISTER]);
+}
+
+#[test]
+fn program_reads_its_arguments() {
+    // 0: loadimm r4 <- #-4
+    // 4: sub r5 <- r14 - r4
+    // 8: load r6 <- [r5]
+    // 11: load r7 <- [r6]
+    // 14: out r7
+    // 16: exit r13
+    let program = [
+        4, 4, 0xfc, 0xff, 5, 5, 14, 4, 3, 6, 5, 3, 7, 6, 6, 7, 10, 13,
+    ];
+    for policy in [MemoryPolicy::Strict, MemoryPolicy::Aligned] {
+        let mut machine = Machine::new_protected(&program);
+        machine.set_memory_policy(policy);
+        machine.set_arguments(&["prog", "x", "y"], &[]).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(StopReason::Exited(3), machine.run_on(&mut out).unwrap());
+        assert_eq!(b"x", &out[..]);
+    }
+}
+
+#[test]
+fn stack_preserves_arguments() {
+    // 0: loadimm r3 <- #4
+    // 4: sub r2 <- r2 - r3
+    // 8: store [r2] <- r3
+    // 11: exit
+    let mut machine = Machine::new(&[4, 3, 4, 0, 5, 2, 2, 3, 2, 2, 3, 7]);
+    machine.set_arguments(&["a"], &[("B", "c")]).unwrap();
+    let before = machine.memory()[machine.regs()[STACK_REGISTER] as usize..].to_vec();
+    machine.run_on(&mut Vec::new()).unwrap();
+    let stack = machine.regs()[STACK_REGISTER] as usize;
+    assert_eq!(4, word(&machine, stack as u32));
+    assert_eq!(&before[..], &machine.memory()[stack + 4..]);
+}
+
+#[test]
+fn too_large() {
+    let mut machine = Machine::new(&[7]);
+    let arg = "a".repeat(4096);
+    assert_eq!(
+        Err(MachineError::ArgumentsTooLarge),
+        machine.set_arguments(&[&arg], &[])
+    );
+    assert_eq!(0, machine.regs()[ARGC_REGISTER]);
+}
+
+#[test]
+fn predecoded_code_is_replaced() {
+    // The only argument is placed at 4088, over a decoded out_number r1
+    let mut memory = [0; 4096];
+    memory[4088..4090].copy_from_slice(&[8, 1]);
+    let mut machine = Machine::new(&memory);
+    machine.set_predecoding(true);
+    machine.set_reg(0, 4088).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    machine.set_arguments(&["\u{7}"], &[]).unwrap();
+    assert_eq!(4088, word(&machine, machine.regs()[ARGV_REGISTER]));
+    machine.set_reg(0, 4088).unwrap();
+    let mut out = Vec::new();
+    assert_eq!(StopReason::Exited(0), machine.run_on(&mut out).unwrap());
+    assert!(out.is_empty());
+}
+
+#[test]
+fn command_line() {
+    // 0: exit r13, the number of arguments
+    let name = format!("tp-rust-2-arguments-{}.bin", std::process::id());
+    let program = std::env::temp_dir().join(name);
+    std::fs::write(&program, [10, 13]).unwrap();
+    let status = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+            .args(args)
+            .output()
+            .unwrap()
+            .status
+            .code()
+    };
+    let program = program.to_str().unwrap();
+    // Without arguments nor environment, the registers are left at 0
+    assert_eq!(Some(0), status(&[program]));
+    assert_eq!(Some(1), status(&["--env", "HOME=/root", program]));
+    assert_eq!(Some(3), status(&[program, "a", "b"]));
+    // 256 arguments make a code whose low 8 bits are 0, which still fails
+    let mut args = vec!["a"; 256];
+    args[0] = program;
+    assert_eq!(Some(1), status(&args));
+    std::fs::remove_file(program).unwrap();
+}
@@ -0,0 +1,293 @@
+use interpreter::{assemble, link, AssemblyError, AssemblyErrorKind, Relocation};
+
+/// Assemble and link a module on its own, as a raw program.
+fn program(source: &str) -> Vec<u8> {
+    let image = link(&[assemble(source).unwrap()]).unwrap();
+    image.sections[0].bytes.clone()
+}
+
+/// Turn a `.dis` listing into assembly source by removing the address
+/// column.
+fn strip_addresses(listing: &str) -> String {
+    listing
+        .lines()
+        .map(|line| match line.strip_prefix("  ") {
+            Some(rest) => &rest[rest.find(' ').unwrap()..],
+            None => line,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn errors(source: &str) -> Vec<(usize, AssemblyErrorKind)> {
+    assemble(source)
+        .unwrap_err()
+        .into_iter()
+        .map(|AssemblyError { line, kind, .. }| (line, kind))
+        .collect()
+}
+
+#[test]
+fn instructions() {
+    let source = "
+        move r0 <- r5 if r4 != 0
+        store [r2] <- r3
+        load r11 <- [r3]
+        loadimm r2 <- #4096
+        loadimm r3 <- #-4
+        sub r2 <- r2 - r3
+        out r3
+        exit
+        out_number r15
+        brk
+        exit r1
+    ";
+    assert_eq!(
+        vec![
+            1, 0, 5, 4, 2, 2, 3, 3, 11, 3, 4, 2, 0x00, 0x10, 4, 3, 0xfc, 0xff, 5, 2, 2, 3, 6, 3, 7,
+            8, 15, 9, 10, 1
+        ],
+        program(source)
+    );
+}
+
+#[test]
+fn labels_and_data() {
+    let source = "
+        ; a comment
+    start: loadimm r1 <- #data  ; another comment
+        loadimm r0 <- #start
+    data:
+        b'a\\n\\x00\"'
+        b\"'\"
+        [1, 2, 0xff]
+    ";
+    assert_eq!(
+        vec![4, 1, 8, 0, 4, 0, 0, 0, b'a', b'\n', 0, b'"', b'\'', 1, 2, 255],
+        program(source)
+    );
+    let object = assemble(source).unwrap();
+    assert_eq!(
+        vec![
+            Relocation {
+                offset: 2,
+                symbol: "data".to_string(),
+                addend: 0,
+                negated: false
+            },
+            Relocation {
+                offset: 6,
+                symbol: "start".to_string(),
+                addend: 0,
+                negated: false
+            }
+        ],
+        object.relocations
+    );
+}
+
+#[test]
+fn data_directives() {
+    let source = r#"
+        loadimm r1 <- #'A'
+        .byte 1, -1, '\n'
+        .align 4
+        .word 0x12345678, -2
+        .ascii "ab", "c\""
+        .asciz "e"
+        .space 3, 7
+        .org 32
+        .space 0
+        .byte 9
+    "#;
+    let mut expected = vec![4, 1, 65, 0, 1, 255, 10, 0];
+    expected.extend([0x78, 0x56, 0x34, 0x12, 254, 255, 255, 255]);
+    expected.extend([
+        b'a', b'b', b'c', b'"', b'e', 0, 7, 7, 7, 0, 0, 0, 0, 0, 0, 0, 9,
+    ]);
+    assert_eq!(expected, program(source));
+    assert_eq!(4, assemble(source).unwrap().alignment);
+
+    // The alignment of a module is kept when linking it after another one
+    let first = assemble("exit").unwrap();
+    let second = assemble("x: .align 8\n.byte 1").unwrap();
+    let image = link(&[first, second]).unwrap();
+    assert_eq!(vec![7, 0, 0, 0, 0, 0, 0, 0, 1], image.sections[0].bytes);
+
+    let source = "
+        .byte 256
+        .word 0x100000000
+        loadimm r1 <- #'ab'
+        .ascii 1
+        .space -1
+        .align 0
+        .org 8192
+        brk
+        .org 0
+    ";
+    assert_eq!(
+        vec![
+            (2, AssemblyErrorKind::ByteOutOfRange(256)),
+            (3, AssemblyErrorKind::WordOutOfRange(0x100000000)),
+            (4, AssemblyErrorKind::InvalidCharacter),
+            (5, AssemblyErrorKind::Expected("a string")),
+            (6, AssemblyErrorKind::InvalidSize(-1)),
+            (7, AssemblyErrorKind::InvalidSize(0)),
+            (8, AssemblyErrorKind::InvalidSize(8192)),
+            (10, AssemblyErrorKind::OrgBackwards(0)),
+        ],
+        errors(source)
+    );
+}
+
+#[test]
+fn listings() {
+    for (listing, binary) in [
+        (
+            include_str!("function.dis"),
+            &include_bytes!("function.bin")[..],
+        ),
+        (
+            include_str!("../examples/hello_world.dis"),
+            include_bytes!("../examples/hello_world.bin"),
+        ),
+    ] {
+        assert_eq!(binary, &program(&strip_addresses(listing))[..]);
+    }
+}
+
+#[test]
+fn report_every_error() {
+    let source = "
+        movz r1 <- r2 if r3 != 0
+        loadimm r16 <- #1
+        loadimm r1 <- #32768
+        sub r1 <- r2 & r3
+    a:
+    a:
+        .global b
+        [256]
+        b'unterminated
+        .section
+        out r1 r2
+        loadimm r1 <- #-32768
+    ";
+    assert_eq!(
+        vec![
+            (2, AssemblyErrorKind::UnknownMnemonic("movz".to_string())),
+            (3, AssemblyErrorKind::InvalidRegister("r16".to_string())),
+            (4, AssemblyErrorKind::ImmediateOutOfRange(32768)),
+            (5, AssemblyErrorKind::UnexpectedCharacter('&')),
+            (7, AssemblyErrorKind::DuplicateLabel("a".to_string())),
+            (8, AssemblyErrorKind::UndefinedGlobal("b".to_string())),
+            (9, AssemblyErrorKind::ByteOutOfRange(256)),
+            (10, AssemblyErrorKind::UnterminatedString),
+            (
+                11,
+                AssemblyErrorKind::UnknownDirective("section".to_string())
+            ),
+            (12, AssemblyErrorKind::Expected("the end of the line")),
+        ],
+        errors(source)
+    );
+    assert_eq!(
+        "4:24: immediate 32768 does not fit in the 16 bits of `loadimm` (-32768 to 32767)",
+        assemble(source).unwrap_err()[2].to_string()
+    );
+}
+
+#[test]
+fn expressions_and_constants() {
+    let source = "
+        .equ SIZE, 3
+        .equ BYTES, SIZE * 4
+    start:
+        loadimm sp <- #(SIZE*4)
+        loadimm r1 <- #table+4
+        loadimm r1 <- #-table
+        loadimm r1 <- #table - start + 1
+        move ip <- r1 if r1 != 0
+    table:
+        .space BYTES, SIZE - 1
+        [BYTES / 2, 255]
+    ";
+    let table = 20i16;
+    let mut expected = vec![4, 2, 12, 0, 4, 1];
+    expected.extend((table + 4).to_le_bytes());
+    expected.extend([4, 1]);
+    expected.extend((-table).to_le_bytes());
+    expected.extend([4, 1, 21, 0, 1, 0, 1, 1]);
+    expected.extend([2; 12]);
+    expected.extend([6, 255]);
+    assert_eq!(expected, program(source));
+}
+
+#[test]
+fn expression_errors() {
+    let source = "
+        .equ SIZE, 1
+        .equ SIZE, 2
+        loadimm r1 <- #32767 + 1
+        loadimm r1 <- #label * 2
+        loadimm r1 <- #1 / (SIZE - 1)
+        [later]
+    later:
+        loadimm r1 <- #-32769
+        loadimm r1 <- #(label - 1) - (later - 40000)
+    ";
+    assert_eq!(
+        vec![
+            (3, AssemblyErrorKind::DuplicateLabel("SIZE".to_string())),
+            (4, AssemblyErrorKind::ImmediateOutOfRange(32768)),
+            (5, AssemblyErrorKind::NotRelocatable),
+            (6, AssemblyErrorKind::DivisionByZero),
+            (7, AssemblyErrorKind::NotConstant),
+            (9, AssemblyErrorKind::ImmediateOutOfRange(-32769)),
+            (10, AssemblyErrorKind::NotRelocatable),
+        ],
+        errors(source)
+    );
+}
+
+#[test]
+fn diagnostics() {
+    let source = "start:
+    lodimm r1 <- #1
+\tloadimm r1 <- #40000
+    .globl start
+    .global strat
+    push r3
+    add r1 <- r1 + #start * 2
+    .byte 1, 300
+    out r1 r2";
+    let errors = assemble(source).unwrap_err();
+    let summary: Vec<(usize, usize, Option<&str>)> = errors
+        .iter()
+        .map(|e| (e.line, e.column, e.suggestion.as_deref()))
+        .collect();
+    assert_eq!(
+        vec![
+            (2, 5, Some("loadimm")),
+            (3, 17, None),
+            (4, 5, Some(".global")),
+            (5, 13, Some("start")),
+            (6, 10, None),
+            (7, 5, None),
+            (8, 14, None),
+            (9, 12, None),
+        ],
+        summary
+    );
+    assert_eq!(
+        "2:5: unknown instruction `lodimm`, did you mean `loadimm`?",
+        errors[0].to_string()
+    );
+    assert_eq!(
+        "test.s:3:17: immediate 40000 does not fit in the 16 bits of `loadimm` (-32768 to 32767)
+  |
+3 | \tloadimm r1 <- #40000
+  | \t               ^
+",
+        errors[1].report("test.s", source)
+    );
+}
@@ -1,3 +1,6 @@
+// The original tests predate this lint
+#![allow(clippy::manual_repeat_n)]
+
 use interpreter::Machine;
 
 fn create_machine(code: &[u8]) -> (Machine, Vec<u8>) {
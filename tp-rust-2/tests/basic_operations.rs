@@ -1,3 +1,6 @@
+// The original tests predate these lints
+#![allow(clippy::zero_prefixed_literal, clippy::needless_range_loop)]
+
 use interpreter::Machine;
 use std::io::{self, Write};
 
@@ -248,6 +251,16 @@ fn test_out_number() {
     assert_eq!("-1234".as_bytes(), &out[..]);
 }
 
+#[test]
+fn test_brk() {
+    // 0: brk
+    // 1: exit
+    // 2:
+    let mut machine = Machine::new(&[9, 7]);
+    expect(&mut machine, false, 1);
+    expect(&mut machine, true, 2);
+}
+
 #[test]
 fn test_run_on() {
     // 0: out_number r0
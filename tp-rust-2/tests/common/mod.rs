@@ -0,0 +1,21 @@
+use std::collections::BTreeMap;
+
+/// The source of a `.dis` listing without its address column, and the
+/// address announced by each line which has one, by line number.
+pub fn strip_listing(listing: &str) -> (String, BTreeMap<u32, u32>) {
+    let mut addresses = BTreeMap::new();
+    let source: Vec<&str> = listing
+        .lines()
+        .enumerate()
+        .map(|(i, line)| match line.trim_start().split_once(' ') {
+            Some((address, rest)) if line.starts_with(' ') && address.len() == 4 => {
+                if let Ok(address) = address.parse::<u32>() {
+                    addresses.insert(i as u32 + 1, address);
+                }
+                rest.trim()
+            }
+            _ => line,
+        })
+        .collect();
+    (source.join("\n"), addresses)
+}
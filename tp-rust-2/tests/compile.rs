@@ -0,0 +1,253 @@
+use interpreter::{assemble, compile, link, CompileError, CompileErrorKind, Machine, StopReason};
+
+/// Compile, assemble and link a program, then run it and return what it
+/// printed and how it stopped.
+fn run(source: &str) -> (String, StopReason) {
+    let assembly = compile(source).unwrap();
+    let image = link(&[assemble(&assembly).unwrap()]).unwrap();
+    let mut machine = Machine::from_image(&image).unwrap();
+    let mut output = Vec::new();
+    let reason = machine.run_on(&mut output).unwrap();
+    (String::from_utf8(output).unwrap(), reason)
+}
+
+fn output(program: &[u8]) -> String {
+    let mut machine = Machine::new(program);
+    let mut output = Vec::new();
+    machine.run_on(&mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+fn errors(source: &str) -> Vec<(usize, usize, CompileErrorKind, Option<String>)> {
+    compile(source)
+        .unwrap_err()
+        .into_iter()
+        .map(
+            |CompileError {
+                 line,
+                 column,
+                 kind,
+                 suggestion,
+             }| (line, column, kind, suggestion),
+        )
+        .collect()
+}
+
+#[test]
+fn examples() {
+    // The compiled examples print like the hand-written programs
+    let (fact, reason) = run(include_str!("../examples/factorial.tiny"));
+    assert_eq!(fact, output(include_bytes!("../examples/factorial.bin")));
+    assert_eq!(reason, StopReason::Exited(0));
+    let (fibo, _) = run(include_str!("../examples/fibonacci.tiny"));
+    assert_eq!(fibo, output(include_bytes!("../examples/fibonacci.bin")));
+}
+
+#[test]
+fn expressions() {
+    let (printed, reason) = run(r#"
+        fn main() {
+            print 1 + 2 * 3, " ", (1 + 2) * 3, " ", 10 - 4 - 3, "\n";
+            print -7 * 6, " ", 7 * -6, " ", -7 * -6, " ", 0 * 5, "\n";
+            print 17 / 5, " ", 17 % 5, " ", -17 / 5, " ", -17 % 5, " ", 17 / -5, "\n";
+            print 3 < 4, 4 < 3, 3 <= 3, 4 <= 3, 4 > 3, 3 >= 4, 3 == 3, 3 != 3, "\n";
+            print -5 < 2, 2 < -5, 100000 > -100000, 1 && 2, 0 || 0, !0, !7, "\n";
+            print 2000000000, " ", -40000, "\n";
+            return 3;
+        }
+    "#);
+    assert_eq!(
+        printed,
+        "7 9 3\n-42 -42 42 0\n3 2 -3 -2 -3\n10101010\n1011010\n2000000000 -40000\n"
+    );
+    assert_eq!(reason, StopReason::Exited(3));
+}
+
+#[test]
+fn control_flow() {
+    let (printed, _) = run(r#"
+        // Short-circuits skip the call
+        fn loud(x) { print "!"; return x; }
+        fn sign(x) {
+            if x < 0 { return -1; } else if x == 0 { return 0; } else { return 1; }
+        }
+        fn gcd(a, b) {
+            if b == 0 { return a; }
+            return gcd(b, a % b);
+        }
+        fn main() {
+            print sign(-3), sign(0), sign(8), " ", gcd(84, 36), "\n";
+            var n = 0 && loud(1);
+            n = 1 || loud(1);
+            n = 1 && loud(0);
+            var i = 0;
+            while i < 3 { var j = i * i; print " ", j; i = i + 1; }
+        }
+    "#);
+    assert_eq!(printed, "-101 12\n! 0 1 4");
+}
+
+#[test]
+fn division_by_zero() {
+    // It stops on a breakpoint, then exits with -1
+    let assembly = compile("fn main() { print 7 / 0; }").unwrap();
+    let image = link(&[assemble(&assembly).unwrap()]).unwrap();
+    let mut machine = Machine::from_image(&image).unwrap();
+    let mut output = Vec::new();
+    assert!(matches!(
+        machine.run_on(&mut output).unwrap(),
+        StopReason::Breakpoint(_)
+    ));
+    assert_eq!(
+        machine.run_on(&mut output).unwrap(),
+        StopReason::Exited(u32::MAX)
+    );
+    assert!(output.is_empty());
+}
+
+#[test]
+fn large_comparisons() {
+    // Operands whose difference does not fit in 32 bits
+    let (output, reason) = run(r#"
+        fn main() {
+            var a = 0 - 2000000000;
+            var b = 2000000000;
+            var min = -2147483647 - 1;
+            print a < b, a <= b, a > b, a >= b, "\n";
+            print b < a, b <= a, b > a, b >= a, "\n";
+            print min < 1, 2147483647 > min, min < min, min <= min, "\n";
+        }
+    "#);
+    assert_eq!(output, "1100\n0011\n1101\n");
+    assert_eq!(reason, StopReason::Exited(0));
+}
+
+#[test]
+fn long_division() {
+    // The extreme values, and quotients too large to be found by repeated
+    // subtraction in the number of steps allowed
+    let assembly = compile(
+        r#"
+        fn show(a, b) { print a / b, " ", a % b, "\n"; }
+        fn main() {
+            var min = -2147483647 - 1;
+            show(min, -1);
+            show(min, 1);
+            show(min, -7);
+            show(min, min);
+            show(5, min);
+            show(-5, min);
+            show(min, 2147483647);
+            show(2147483647, 3);
+            show(-2000000000, 7);
+        }
+    "#,
+    )
+    .unwrap();
+    let image = link(&[assemble(&assembly).unwrap()]).unwrap();
+    let mut machine = Machine::from_image(&image).unwrap();
+    let mut output = Vec::new();
+    assert_eq!(
+        machine.run_until_on(&mut output, 100_000).unwrap(),
+        StopReason::Exited(0)
+    );
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "-2147483648 0\n-2147483648 0\n306783378 -2\n1 0\n0 5\n0 -5\n-1 -1\n\
+         715827882 1\n-285714285 -5\n"
+    );
+}
+
+#[test]
+fn stack_overflow() {
+    // It stops on a breakpoint before the stack reaches the code, then
+    // exits with -1
+    let assembly = compile("fn down(n) { return down(n + 1); }\nfn main() { down(0); }").unwrap();
+    let image = link(&[assemble(&assembly).unwrap()]).unwrap();
+    let mut machine = Machine::from_image(&image).unwrap();
+    let mut output = Vec::new();
+    assert!(matches!(
+        machine.run_on(&mut output).unwrap(),
+        StopReason::Breakpoint(_)
+    ));
+    let end = image.sections[0].size;
+    assert!(machine.regs()[2] > end + 200, "{}", machine.regs()[2]);
+    assert_eq!(
+        machine.run_on(&mut output).unwrap(),
+        StopReason::Exited(u32::MAX)
+    );
+}
+
+#[test]
+fn compile_errors() {
+    use CompileErrorKind::*;
+    // Only the first syntax error is reported
+    assert_eq!(
+        errors("fn main() {\n    var x = 1 +;\n    y = ;\n}"),
+        vec![(2, 16, Expected("an expression"), None)]
+    );
+    assert_eq!(
+        errors("fn main() { print \"a\\q\"; }"),
+        vec![(1, 19, InvalidEscape, None)]
+    );
+    assert_eq!(
+        errors("fn main() { return 3000000000; }"),
+        vec![(1, 20, InvalidNumber("3000000000".to_string()), None)]
+    );
+    assert_eq!(
+        errors("fn main() { return 1 }"),
+        vec![(1, 22, Expected("`;`"), None)]
+    );
+    // Every name error is reported
+    let source = "
+fn square(value) { return valeu * value; }
+fn square(x) { return x; }
+fn main() {
+    var total = sqaure(2);
+    var total = square(1, 2);
+    print totl;
+}";
+    assert_eq!(
+        errors(source),
+        vec![
+            (
+                2,
+                27,
+                UndefinedVariable("valeu".to_string()),
+                Some("value".to_string())
+            ),
+            (3, 4, DuplicateFunction("square".to_string()), None),
+            (
+                5,
+                17,
+                UndefinedFunction("sqaure".to_string()),
+                Some("square".to_string())
+            ),
+            (6, 9, DuplicateVariable("total".to_string()), None),
+            (
+                6,
+                17,
+                Arguments {
+                    name: "square".to_string(),
+                    expected: 1,
+                    found: 2
+                },
+                None
+            ),
+            (
+                7,
+                11,
+                UndefinedVariable("totl".to_string()),
+                Some("total".to_string())
+            ),
+        ]
+    );
+    assert_eq!(errors("fn f() {}"), vec![(1, 10, MissingMain, None)]);
+
+    let source = "fn main() {\n\tprint sum;\n}";
+    let error = &compile(source).unwrap_err()[0];
+    assert_eq!(
+        error.report("sum.tiny", source),
+        "sum.tiny:2:8: variable `sum` is not defined\n  |\n2 | \tprint sum;\n  | \t      ^\n"
+    );
+}
@@ -0,0 +1,114 @@
+use interpreter::{
+    assemble, debug_info, decompile, disassemble, disassemble_instruction, link, DebugInfo,
+    DebugInfoError, Image, Machine, MachineError, Object,
+};
+
+/// `count.s` linked with `print.s`, as `tp-rust-2 link` would.
+fn count() -> (Vec<Object>, Image) {
+    let mut objects = Vec::new();
+    for (name, source) in [
+        ("count.s", include_str!("../examples/count.s")),
+        ("print.s", include_str!("../examples/print.s")),
+    ] {
+        let mut object = assemble(source).unwrap();
+        object.source = name.to_string();
+        objects.push(object);
+    }
+    let image = link(&objects).unwrap();
+    (objects, image)
+}
+
+#[test]
+fn locate_fault() {
+    let (objects, mut image) = count();
+    let info = debug_info(&objects);
+    // `loadimm r3 <- #-4` becomes `loadimm r20 <- #-4`
+    image.sections[0].bytes[54] = 20;
+    let mut machine = Machine::from_image(&image).unwrap();
+    assert_eq!(
+        Err(MachineError::InvalidRegister(20)),
+        machine.run_on(&mut Vec::new())
+    );
+    assert_eq!(53, machine.last_ip());
+    assert_eq!(
+        "return_from_print_1+0 (count.s:17)",
+        info.locate(machine.last_ip())
+    );
+    let print = image.symbols[0].address as usize;
+    assert_eq!("print+4 (print.s:9)", info.locate(print + 4));
+    assert_eq!("0002", DebugInfo::default().locate(2));
+}
+
+#[test]
+fn sidecar_round_trip() {
+    let (objects, _) = count();
+    let info = debug_info(&objects);
+    assert_eq!(info, DebugInfo::parse(&info.to_text()).unwrap());
+    assert_eq!(
+        Err(DebugInfoError { line: 2 }),
+        DebugInfo::parse("label 0 start\nline 4 count.s\n")
+    );
+}
+
+#[test]
+fn disassemble_round_trip() {
+    let (objects, image) = count();
+    let listing = disassemble(&image.sections[0].bytes, Some(&debug_info(&objects))).unwrap();
+    assert!(listing.contains("return_from_print_1:\n  0053   loadimm r3 <- #-4\n"));
+    assert!(listing.contains("  0049   loadimm r0 <- #print\n"));
+    assert!(listing.contains("  ???? b'I will count from 1 to 10 (included)\\n'\n"));
+    // Without the address column, the listing is the source of the program
+    let source: Vec<&str> = listing
+        .lines()
+        .map(|line| match line.strip_prefix("  ") {
+            Some(rest) => &rest[rest.find(' ').unwrap()..],
+            None => line,
+        })
+        .collect();
+    let object = assemble(&source.join("\n")).unwrap();
+    assert_eq!(
+        image.sections[0].bytes,
+        link(&[object]).unwrap().sections[0].bytes
+    );
+}
+
+#[test]
+fn immediate_labels() {
+    // Only relocated immediates and jump targets are shown as labels, not
+    // numbers equal to the address of a label
+    let objects = [assemble(
+        "
+start:
+        loadimm r2 <- #4096
+        call values
+        out r1
+        out r4
+        exit
+values:
+        loadimm r1 <- #0
+        loadimm r4 <- #message
+        ret
+message:
+        .ascii \"hi\"
+",
+    )
+    .unwrap()];
+    let image = link(&objects).unwrap();
+    let info = debug_info(&objects);
+    let values = 28;
+    let listing = disassemble(&image.sections[0].bytes, Some(&info)).unwrap();
+    assert!(
+        listing.contains("values:\n  0028   loadimm r1 <- #0\n  0032   loadimm r4 <- #message\n")
+    );
+    let bytes = &image.sections[0].bytes;
+    assert_eq!(
+        Some("loadimm r1 <- #0".to_string()),
+        disassemble_instruction(&bytes[values..], values, Some(&info))
+    );
+    assert_eq!(
+        Some("loadimm r4 <- #message".to_string()),
+        disassemble_instruction(&bytes[values + 4..], values + 4, Some(&info))
+    );
+    let source = decompile(bytes, Some(&info)).unwrap();
+    assert!(source.contains("    r1 = 0;\n    r4 = message;\n"));
+}
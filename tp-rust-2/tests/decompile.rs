@@ -0,0 +1,159 @@
+use interpreter::{
+    assemble, compile, debug_info, decompile, disassemble, link, Image, Section, SectionKind,
+};
+
+#[test]
+fn loops_and_calls() {
+    // fact.dis: a loop calling mult, which loops in turn
+    let source = decompile(include_bytes!("fact.bin"), None).unwrap();
+    assert_eq!(
+        source,
+        "\
+fn start(r1, r10) {  // 0000
+    sp = 4096;
+    function_0087(r1, r10);
+    exit(0);
+}
+
+fn function_0024(r1, r11, r12) -> r11 {  // 0024
+    r13 = r1 - r11;
+    r14 = r12;
+    while r14 != 1 {
+        r11 = r11 - r13;
+        r14 = r14 - 1;
+    }
+    return;
+}
+
+fn function_0087(r1, r10) {  // 0087
+    r11 = 1;
+    while r10 != 1 {
+        r12 = r10;
+        function_0024(r1, r11, r12);
+        r10 = r10 - 1;
+    }
+    return;
+}
+"
+    );
+}
+
+#[test]
+fn recursion_and_stack_slots() {
+    // rfact.dis keeps n on the stack while computing fact(n - 1)
+    let source = decompile(include_bytes!("rfact.bin"), None).unwrap();
+    assert!(source.contains(
+        "\
+fn function_0087(r1, r10) -> r11 {  // 0087
+    if r10 != 1 {
+        local1 = r10;
+        r10 = r10 - 1;
+        function_0087(r1, r10);
+        r12 = local1;
+        function_0024(r1, r11, r12);
+    } else {
+        r11 = 1;
+    }
+    return;
+}
+"
+    ));
+    // Memory accessed at a constant address
+    let source = decompile(include_bytes!("afact.bin"), None).unwrap();
+    assert!(source.contains("    mem[186] = 1;\n    while r10 != 1 {\n        r11 = mem[186];\n"));
+}
+
+#[test]
+fn labels() {
+    let object = assemble(
+        "
+        loadimm r2 <- #4096
+        call count
+        exit
+count:
+        loadimm r1 <- #0
+loop:
+        loadimm r3 <- #5
+        sub r3 <- r1 - r3
+        loadimm r4 <- #body
+        move r0 <- r4 if r3 != 0
+        jmp done
+body:
+        out_number r1
+        add r1 <- r1 + #1
+        jmp loop
+done:
+        ret
+",
+    )
+    .unwrap();
+    let objects = [object];
+    let image = link(&objects).unwrap();
+    let program = image.sections[0].content();
+    let source = decompile(&program, Some(&debug_info(&objects))).unwrap();
+    assert_eq!(
+        source,
+        "\
+fn start() {  // 0000
+    sp = 4096;
+    count();
+    exit(0);
+}
+
+fn count() {  // 0024
+    r1 = 0;
+    while r1 != 5 {
+        print(r1);
+        r1 = r1 + 1;
+    }
+    return;
+}
+"
+    );
+}
+
+#[test]
+fn compiled_program() {
+    // Functions of the compiled factorial, with their frames on the stack
+    let objects =
+        [assemble(&compile(include_str!("../examples/factorial.tiny")).unwrap()).unwrap()];
+    let image = link(&objects).unwrap();
+    let program = image.sections[0].content();
+    let source = decompile(&program, Some(&debug_info(&objects))).unwrap();
+    assert!(source.contains("fn fact() -> r4 {"));
+    assert!(source.contains("        fact();\n"));
+    assert!(source.contains("fn main() -> r4 {"));
+    assert!(source.contains(
+        "\
+fn __print(r10, r11) {  // 0874
+    while r11 != 0 {
+        putchar(mem[r10]);
+        r10 = r10 + 1;
+        r11 = r11 - 1;
+    }
+    return;
+}
+"
+    ));
+}
+
+#[test]
+fn image_entry_point() {
+    // 0: data
+    // 1: out_number r1
+    // 3: exit
+    let image = Image {
+        entry: 1,
+        sections: vec![Section {
+            kind: SectionKind::Code,
+            address: 0,
+            size: 4,
+            bytes: vec![0xff, 8, 1, 7],
+        }],
+        symbols: Vec::new(),
+    };
+    let source = decompile(&image.to_bytes(), None).unwrap();
+    assert!(source.starts_with("fn start(r1) {  // 0001\n"), "{source}");
+    let listing = disassemble(&image.to_bytes(), None).unwrap();
+    assert!(listing.contains("  0001   out_number r1\n"), "{listing}");
+}
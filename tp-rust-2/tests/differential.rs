@@ -0,0 +1,328 @@
+//! Differential testing of [Machine] against [Reference], a model of the
+//! instruction set written from its description rather than from the
+//! machine code, on random programs and registers.
+
+use interpreter::{Machine, MachineError};
+
+const MEMORY_SIZE: usize = 4096;
+const NREGS: usize = 16;
+
+/// Number of random programs run by [random_programs].
+const CASES: u64 = 2000;
+
+/// Instructions executed at most by each program.
+const MAX_STEPS: usize = 500;
+
+/// The machine as described by the instruction set, with a strict memory
+/// and no protection. Each step:
+///   - reads the instruction at IP, failing if its opcode is unknown or if
+///     it does not fit in memory,
+///   - advances IP past it,
+///   - executes it, reading its register operands as they are needed, so
+///     that `move rA <- rB if rC` does not look at `rA` and `rB` when
+///     `rC` is 0.
+///
+/// A memory access failing is reported with the first address of the
+/// access which is outside of memory. The exception is an instruction
+/// which starts in memory but runs past its end: like the original
+/// machine, whose tests rely on it, IP moves to the end of memory and the
+/// address following the instruction is reported.
+struct Reference {
+    memory: Vec<u8>,
+    regs: [u32; NREGS],
+    output: Vec<u8>,
+    exit_code: Option<u32>,
+}
+
+impl Reference {
+    fn new(program: &[u8]) -> Reference {
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[..program.len()].copy_from_slice(program);
+        Reference {
+            memory,
+            regs: [0; NREGS],
+            output: Vec::new(),
+            exit_code: None,
+        }
+    }
+
+    fn reg(&self, reg: u8) -> Result<u32, MachineError> {
+        self.regs
+            .get(reg as usize)
+            .copied()
+            .ok_or(MachineError::InvalidRegister(reg as usize))
+    }
+
+    fn set_reg(&mut self, reg: u8, value: u32) -> Result<(), MachineError> {
+        let slot = self
+            .regs
+            .get_mut(reg as usize)
+            .ok_or(MachineError::InvalidRegister(reg as usize))?;
+        *slot = value;
+        Ok(())
+    }
+
+    /// The `length` bytes starting at `address`.
+    fn bytes(&self, address: usize, length: usize) -> Result<&[u8], MachineError> {
+        match (address..address + length).find(|&a| a >= MEMORY_SIZE) {
+            Some(outside) => Err(MachineError::InvalidMemoryAddress(outside)),
+            None => Ok(&self.memory[address..address + length]),
+        }
+    }
+
+    /// Execute one instruction, returns whether the program has exited.
+    fn step(&mut self) -> Result<bool, MachineError> {
+        let ip = self.regs[0] as usize;
+        let opcode = self.bytes(ip, 1)?[0];
+        let length = match opcode {
+            7 | 9 => 1,
+            6 | 8 | 10 => 2,
+            2 | 3 => 3,
+            1 | 4 | 5 => 4,
+            _ => return Err(MachineError::InvalidInstruction(opcode)),
+        };
+        if ip + length > MEMORY_SIZE {
+            self.regs[0] = MEMORY_SIZE as u32;
+            return Err(MachineError::InvalidMemoryAddress(ip + length));
+        }
+        let mut operands = [0u8; 4];
+        operands[..length].copy_from_slice(self.bytes(ip, length)?);
+        let [_, a, b, c] = operands;
+        self.regs[0] = (ip + length) as u32;
+        match opcode {
+            // move rA <- rB if rC
+            1 => {
+                if self.reg(c)? != 0 {
+                    let value = self.reg(b)?;
+                    self.set_reg(a, value)?;
+                }
+            }
+            // store [rA] <- rB
+            2 => {
+                let address = self.reg(a)? as usize;
+                let value = self.reg(b)?;
+                self.bytes(address, 4)?;
+                self.memory[address..address + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            // load rA <- [rB]
+            3 => {
+                let address = self.reg(b)? as usize;
+                let word = self.bytes(address, 4)?.try_into().unwrap();
+                self.set_reg(a, u32::from_le_bytes(word))?;
+            }
+            // loadimm rA <- #imm, with imm a signed 16-bit number
+            4 => self.set_reg(a, i16::from_le_bytes([b, c]) as u32)?,
+            // sub rA <- rB - rC
+            5 => {
+                let value = self.reg(b)?.wrapping_sub(self.reg(c)?);
+                self.set_reg(a, value)?;
+            }
+            // out rA, the character whose code is the low byte of rA
+            6 => {
+                let character = char::from(self.reg(a)? as u8);
+                self.output
+                    .extend_from_slice(character.to_string().as_bytes());
+            }
+            // exit
+            7 => self.exit_code = Some(0),
+            // out_number rA, as a signed decimal number
+            8 => {
+                let number = self.reg(a)? as i32;
+                self.output.extend_from_slice(number.to_string().as_bytes());
+            }
+            // brk
+            9 => (),
+            // exit rA
+            10 => self.exit_code = Some(self.reg(a)?),
+            _ => unreachable!(),
+        }
+        Ok(self.exit_code.is_some())
+    }
+}
+
+/// Final state of a run: how it stopped (`Ok(false)` if it was still
+/// running), registers, memory, output and exit code.
+type Outcome = (
+    Result<bool, MachineError>,
+    Vec<u32>,
+    Vec<u8>,
+    Vec<u8>,
+    Option<u32>,
+);
+
+/// Run `step` until the program exits, fails or has run [MAX_STEPS]
+/// instructions.
+fn run_steps(mut step: impl FnMut() -> Result<bool, MachineError>) -> Result<bool, MachineError> {
+    for _ in 0..MAX_STEPS {
+        if step()? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn run_reference(program: &[u8], regs: &[u32; NREGS]) -> Outcome {
+    let mut reference = Reference::new(program);
+    reference.regs = *regs;
+    let result = run_steps(|| reference.step());
+    (
+        result,
+        reference.regs.to_vec(),
+        reference.memory,
+        reference.output,
+        reference.exit_code,
+    )
+}
+
+fn run_machine(program: &[u8], regs: &[u32; NREGS], predecoding: bool) -> Outcome {
+    let mut machine = Machine::new(program);
+    machine.set_predecoding(predecoding);
+    for (reg, &value) in regs.iter().enumerate() {
+        machine.set_reg(reg, value).unwrap();
+    }
+    let mut output = Vec::new();
+    let result = run_steps(|| machine.step_on(&mut output));
+    (
+        result,
+        machine.regs().to_vec(),
+        machine.memory().to_vec(),
+        output,
+        machine.exit_code(),
+    )
+}
+
+/// A xorshift generator, so that failures can be reproduced from a seed.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn choose<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len() as u64) as usize]
+    }
+}
+
+/// A random value which is likely to be an interesting register value or
+/// immediate: an address in the program, an address near the end of
+/// memory, a small number or anything.
+fn value(random: &mut Random, program_length: u64) -> u32 {
+    match random.below(5) {
+        0 => random.below(program_length) as u32,
+        1 => (MEMORY_SIZE as u64 - 8 + random.below(16)) as u32,
+        2 => random.below(8) as u32,
+        3 => (random.below(9) as i32 - 4) as u32,
+        _ => random.next() as u32,
+    }
+}
+
+/// A register operand, rarely one which does not exist.
+fn register(random: &mut Random) -> u8 {
+    if random.below(50) == 0 {
+        random.below(256) as u8
+    } else {
+        // The IP and the registers used as pointers come up more often
+        let any = random.below(NREGS as u64) as u8;
+        random.choose(&[0, 2, 3, any])
+    }
+}
+
+/// A random program of mostly valid instructions, along with the initial
+/// registers.
+fn generate(random: &mut Random) -> (Vec<u8>, [u32; NREGS]) {
+    let mut program = Vec::new();
+    let length = 16 + random.below(64);
+    while (program.len() as u64) < length {
+        let opcode = if random.below(40) == 0 {
+            random.below(256) as u8
+        } else {
+            // Exits are rarer than the other instructions
+            random.choose(&[1, 1, 2, 2, 3, 3, 4, 4, 4, 5, 5, 6, 7, 8, 9, 10])
+        };
+        program.push(opcode);
+        match opcode {
+            1 | 5 => {
+                let operands = [register(random), register(random), register(random)];
+                program.extend(operands);
+            }
+            2 | 3 => {
+                let operands = [register(random), register(random)];
+                program.extend(operands);
+            }
+            4 => {
+                program.push(register(random));
+                let imm = value(random, length) as u16;
+                program.extend(imm.to_le_bytes());
+            }
+            6 | 8 | 10 => program.push(register(random)),
+            _ => (),
+        }
+    }
+    let mut regs = [0; NREGS];
+    for reg in regs.iter_mut().skip(1) {
+        *reg = value(random, length);
+    }
+    // Starting near the end of memory is left to the instructions
+    regs[0] = random.below(4) as u32;
+    (program, regs)
+}
+
+#[test]
+fn random_programs() {
+    for seed in 1..=CASES {
+        let mut random = Random(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let (program, regs) = generate(&mut random);
+        let expected = run_reference(&program, &regs);
+        for predecoding in [false, true] {
+            assert_eq!(
+                run_machine(&program, &regs, predecoding),
+                expected,
+                "seed {seed}, predecoding {predecoding}, registers {regs:?}, program {program:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn store_at_end_of_memory() {
+    // loadimm r1 <- #4092, store [r1] <- r1, loadimm r1 <- #4093,
+    // store [r1] <- r1
+    let program = [4, 1, 0xfc, 0x0f, 2, 1, 1, 4, 1, 0xfd, 0x0f, 2, 1, 1];
+    let expected = run_reference(&program, &[0; NREGS]);
+    assert_eq!(
+        expected.0,
+        Err(MachineError::InvalidMemoryAddress(MEMORY_SIZE))
+    );
+    assert_eq!(expected.2[MEMORY_SIZE - 4..], [0xfc, 0x0f, 0, 0]);
+    assert_eq!(run_machine(&program, &[0; NREGS], false), expected);
+}
+
+#[test]
+fn fetch_at_end_of_memory() {
+    // store [r1] <- r2 puts a loadimm at 4094, then loadimm r0 <- #4094
+    let program = [2, 1, 2, 4, 0, 0xfe, 0x0f];
+    let mut regs = [0; NREGS];
+    regs[1] = 4092;
+    regs[2] = 0x0004_0000;
+    let expected = run_reference(&program, &regs);
+    assert_eq!(expected.0, Err(MachineError::InvalidMemoryAddress(4098)));
+    assert_eq!(expected.1[0], MEMORY_SIZE as u32);
+    for predecoding in [false, true] {
+        assert_eq!(run_machine(&program, &regs, predecoding), expected);
+    }
+
+    // loadimm r0 <- #5000
+    let program = [4, 0, 0x88, 0x13];
+    let expected = run_reference(&program, &[0; NREGS]);
+    assert_eq!(expected.0, Err(MachineError::InvalidMemoryAddress(5000)));
+    assert_eq!(expected.1[0], 5000);
+    assert_eq!(run_machine(&program, &[0; NREGS], false), expected);
+}
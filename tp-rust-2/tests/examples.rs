@@ -0,0 +1,105 @@
+//! Run the example programs and compare what they print and their final
+//! registers with the expectations stored next to them, as `NAME.stdout`
+//! and `NAME.regs`. Run with `cargo test --test examples -- --bless` to
+//! write the expectations from the current behaviour instead, then review
+//! the changes.
+//!
+//! The machine prints its memory on the standard output when it starts
+//! and exits, the results are printed on the standard error.
+
+use interpreter::Machine;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+const EXAMPLES: [&str; 5] = [
+    "hello_world",
+    "99bottles",
+    "count",
+    "fibonacci",
+    "factorial",
+];
+
+/// Programs still running after this many instructions are stopped.
+const MAX_STEPS: usize = 10_000_000;
+
+/// What a program prints and its final registers, one `rN = value` line
+/// each, or the reason it failed.
+fn run(program: &[u8]) -> Result<(Vec<u8>, String), String> {
+    let mut machine = Machine::new(program);
+    let mut output = Vec::new();
+    let mut steps = 0;
+    while !machine.step_on(&mut output).map_err(|e| format!("{e:?}"))? {
+        steps += 1;
+        if steps == MAX_STEPS {
+            return Err(format!("still running after {MAX_STEPS} instructions"));
+        }
+    }
+    let registers = machine
+        .regs()
+        .iter()
+        .enumerate()
+        .map(|(r, value)| format!("r{r} = {value}\n"))
+        .collect();
+    Ok((output, registers))
+}
+
+/// Compare the content of a file with the expected one, or write it when
+/// blessing. Returns whether they match.
+fn check(path: &Path, actual: &[u8], bless: bool) -> bool {
+    if bless {
+        fs::write(path, actual).unwrap();
+        return true;
+    }
+    match fs::read(path) {
+        Ok(expected) if expected == actual => true,
+        Ok(expected) => {
+            eprintln!("{} differs", path.display());
+            eprintln!("--- expected\n{}", String::from_utf8_lossy(&expected));
+            eprintln!("--- actual\n{}", String::from_utf8_lossy(actual));
+            false
+        }
+        Err(_) => {
+            eprintln!(
+                "{} is missing, run with --bless to write it",
+                path.display()
+            );
+            false
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let bless = std::env::args().any(|arg| arg == "--bless");
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut failures = 0;
+    for name in EXAMPLES {
+        let program = fs::read(directory.join(format!("{name}.bin"))).unwrap();
+        let passed = match run(&program) {
+            Ok((output, registers)) => {
+                let stdout = check(&directory.join(format!("{name}.stdout")), &output, bless);
+                let regs = check(
+                    &directory.join(format!("{name}.regs")),
+                    registers.as_bytes(),
+                    bless,
+                );
+                stdout && regs
+            }
+            Err(error) => {
+                eprintln!("{name}: {error}");
+                false
+            }
+        };
+        eprintln!(
+            "example {name} ... {}",
+            if passed { "ok" } else { "FAILED" }
+        );
+        failures += !passed as usize;
+    }
+    if failures == 0 {
+        ExitCode::SUCCESS
+    } else {
+        eprintln!("{failures} example(s) failed");
+        ExitCode::FAILURE
+    }
+}
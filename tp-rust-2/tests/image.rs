@@ -0,0 +1,189 @@
+use interpreter::{
+    Access, Image, ImageError, Machine, MachineError, Section, SectionKind, StopReason, Symbol,
+};
+
+/// Code at 100 printing the word at 2000 and clearing the word at 3000.
+fn sample() -> Image {
+    // 100: loadimm r1 <- #2000
+    // 104: load r2 <- [r1]
+    // 107: out_number r2
+    // 109: loadimm r1 <- #3000
+    // 113: load r2 <- [r1]
+    // 116: out_number r2
+    // 118: exit
+    Image {
+        entry: 100,
+        sections: vec![
+            Section {
+                kind: SectionKind::Code,
+                address: 100,
+                size: 19,
+                bytes: vec![
+                    4, 1, 0xd0, 0x07, 3, 2, 1, 8, 2, 4, 1, 0xb8, 0x0b, 3, 2, 1, 8, 2, 7,
+                ],
+            },
+            Section {
+                kind: SectionKind::Data,
+                address: 2000,
+                size: 4,
+                bytes: vec![42, 0, 0, 0],
+            },
+            Section {
+                kind: SectionKind::Bss,
+                address: 3000,
+                size: 100,
+                bytes: vec![],
+            },
+        ],
+        symbols: vec![
+            Symbol {
+                name: "main".to_string(),
+                address: 100,
+            },
+            Symbol {
+                name: "answer".to_string(),
+                address: 2000,
+            },
+        ],
+    }
+}
+
+#[test]
+fn raw_programs() {
+    let program = include_bytes!("afact.bin");
+    let image = Image::load(program).unwrap();
+    assert_eq!(Image::from_raw(program).unwrap(), image);
+    assert_eq!(0, image.entry);
+    let machine = Machine::from_image(&image).unwrap();
+    assert_eq!(Machine::new(program).memory(), machine.memory());
+    assert_eq!(0, machine.regs()[0]);
+    assert_eq!(
+        Err(ImageError::ProgramTooLarge(4097)),
+        Image::load(&[0; 4097])
+    );
+}
+
+#[test]
+fn header() {
+    let image = Image {
+        entry: 0x01020304,
+        sections: vec![Section {
+            kind: SectionKind::Bss,
+            address: 16,
+            size: 8,
+            bytes: vec![],
+        }],
+        symbols: vec![Symbol {
+            name: "a".to_string(),
+            address: 16,
+        }],
+    };
+    assert_eq!(
+        vec![
+            b'T', b'P', b'V', b'M', 1, 0, 1, 0, 4, 3, 2, 1, 1, 0, 0, 0, // header
+            2, 0, 0, 0, 16, 0, 0, 0, 8, 0, 0, 0, // section
+            16, 0, 0, 0, 1, 0, b'a', // symbol
+        ],
+        image.to_bytes()
+    );
+}
+
+#[test]
+fn round_trip() {
+    let image = sample();
+    assert_eq!(image, Image::load(&image.to_bytes()).unwrap());
+}
+
+#[test]
+fn run_image() {
+    let mut image = sample();
+    let mut machine = Machine::from_image(&image).unwrap();
+    assert_eq!(100, machine.regs()[0]);
+    assert_eq!(42, machine.memory()[2000]);
+    let mut out = Vec::new();
+    assert_eq!(StopReason::Exited(0), machine.run_on(&mut out).unwrap());
+    assert_eq!(b"420", &out[..]);
+
+    // 100: loadimm r1 <- #100
+    // 104: store [r1] <- r2
+    image.sections[0].bytes[2..4].copy_from_slice(&100u16.to_le_bytes());
+    image.sections[0].bytes[4..7].copy_from_slice(&[2, 1, 2]);
+    let mut machine = Machine::from_image_protected(&image).unwrap();
+    assert_eq!(
+        Err(MachineError::ProtectionFault {
+            address: 100,
+            access: Access::Write
+        }),
+        machine.run_on(&mut out)
+    );
+}
+
+#[test]
+fn invalid_images() {
+    let bytes = sample().to_bytes();
+    for length in 4..bytes.len() {
+        assert_eq!(
+            Err(ImageError::Truncated),
+            Image::parse(&bytes[..length]),
+            "{length}"
+        );
+    }
+    assert_eq!(Err(ImageError::InvalidMagic), Image::parse(b"TPV"));
+
+    let mut version = bytes.clone();
+    version[4] = 2;
+    assert_eq!(
+        Err(ImageError::UnsupportedVersion(2)),
+        Image::parse(&version)
+    );
+
+    let mut kind = bytes.clone();
+    kind[16] = 3;
+    assert_eq!(Err(ImageError::InvalidSectionKind(3)), Image::parse(&kind));
+
+    let mut image = sample();
+    image.sections[2].size = 1097;
+    assert_eq!(
+        Err(ImageError::SectionOutOfMemory {
+            address: 3000,
+            size: 1097
+        }),
+        Image::parse(&image.to_bytes())
+    );
+    assert!(Machine::from_image(&image).is_err());
+
+    let mut image = sample();
+    image.sections[1].address = 115;
+    assert_eq!(
+        Err(ImageError::OverlappingSections {
+            first: 100,
+            second: 115
+        }),
+        Image::parse(&image.to_bytes())
+    );
+
+    let mut image = sample();
+    image.sections[1].bytes.pop();
+    let short = ImageError::SectionContentMismatch {
+        address: 2000,
+        size: 4,
+        stored: 3,
+    };
+    assert_eq!(Err(short), image.check());
+    assert!(Machine::from_image(&image).is_err());
+    let mut image = sample();
+    image.sections[2].bytes.push(0);
+    assert_eq!(
+        Err(ImageError::SectionContentMismatch {
+            address: 3000,
+            size: 100,
+            stored: 1
+        }),
+        image.check()
+    );
+
+    let mut name = bytes.clone();
+    let last = name.len() - 1;
+    name[last] = 0xff;
+    assert_eq!(Err(ImageError::InvalidSymbolName), Image::parse(&name));
+}
@@ -0,0 +1,199 @@
+use interpreter::{assemble, link, LinkError, Machine, Object, ObjectSymbol, Relocation, Symbol};
+
+fn output(machine: &mut Machine) -> Vec<u8> {
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    out
+}
+
+#[test]
+fn examples_share_print() {
+    let print = assemble(include_str!("../examples/print.s")).unwrap();
+    for (source, binary) in [
+        (
+            include_str!("../examples/count.s"),
+            &include_bytes!("../examples/count.bin")[..],
+        ),
+        (
+            include_str!("../examples/hello_world.s"),
+            include_bytes!("../examples/hello_world.bin"),
+        ),
+        (
+            include_str!("../examples/99bottles.s"),
+            include_bytes!("../examples/99bottles.bin"),
+        ),
+    ] {
+        let main = assemble(source).unwrap();
+        let image = link(&[main.clone(), print.clone()]).unwrap();
+        assert_eq!(
+            vec![Symbol {
+                name: "print".to_string(),
+                address: main.bytes.len() as u32
+            }],
+            image.symbols
+        );
+        assert_eq!(
+            output(&mut Machine::new(binary)),
+            output(&mut Machine::from_image(&image).unwrap())
+        );
+    }
+}
+
+#[test]
+fn object_round_trip() {
+    let object = assemble(include_str!("../examples/count.s")).unwrap();
+    assert_eq!(object, Object::parse(&object.to_bytes()).unwrap());
+}
+
+#[test]
+fn local_labels() {
+    // Both modules jump to their own `loop` label
+    let first = assemble(
+        "
+        loadimm r0 <- #loop
+    loop:
+        loadimm r0 <- #second
+    ",
+    )
+    .unwrap();
+    let second = assemble(
+        "
+        .global second
+    second:
+        loadimm r0 <- #loop
+    loop:
+        exit
+    ",
+    )
+    .unwrap();
+    let image = link(&[first, second.clone()]).unwrap();
+    assert_eq!(
+        vec![4, 0, 4, 0, 4, 0, 8, 0, 4, 0, 12, 0, 7],
+        image.sections[0].bytes
+    );
+    assert_eq!(
+        vec![LinkError::DuplicateSymbol("second".to_string())],
+        link(&[second.clone(), second]).unwrap_err()
+    );
+}
+
+#[test]
+fn relocated_expressions() {
+    let first = assemble(
+        "
+        loadimm r1 <- #second+2
+        loadimm r1 <- #100 - second
+        loadimm r1 <- #-here
+    here:
+        exit
+    ",
+    )
+    .unwrap();
+    let second = assemble(
+        "
+        .global second
+    second:
+        exit
+    ",
+    )
+    .unwrap();
+    assert!(first.relocations.iter().any(|r| r.negated));
+    let image = link(&[first, second]).unwrap();
+    assert_eq!(
+        vec![4, 1, 15, 0, 4, 1, 87, 0, 4, 1, 0xf4, 0xff, 7, 7],
+        image.sections[0].bytes
+    );
+}
+
+#[test]
+fn link_errors() {
+    let main = assemble(
+        "
+        loadimm r0 <- #missing
+        loadimm r1 <- #missing
+        loadimm r0 <- #other
+    ",
+    )
+    .unwrap();
+    let undefined = |line, name: &str| LinkError::UndefinedSymbol {
+        module: 0,
+        source: String::new(),
+        line: Some(line),
+        name: name.to_string(),
+        suggestion: None,
+    };
+    assert_eq!(
+        vec![
+            undefined(2, "missing"),
+            undefined(3, "missing"),
+            undefined(4, "other")
+        ],
+        link(&[main]).unwrap_err()
+    );
+    let typo = assemble(
+        "
+    loop:
+        loadimm r0 <- #lop
+    ",
+    )
+    .unwrap();
+    assert_eq!(
+        "module 0, line 3: undefined symbol `lop`, did you mean `loop`?",
+        link(std::slice::from_ref(&typo)).unwrap_err()[0].to_string()
+    );
+    let typo = Object {
+        source: "typo.s".to_string(),
+        ..typo
+    };
+    assert_eq!(
+        "typo.s:3: undefined symbol `lop`, did you mean `loop`?",
+        link(&[typo]).unwrap_err()[0].to_string()
+    );
+
+    let object = Object {
+        bytes: vec![4, 1, 0, 0, 7],
+        symbols: vec![ObjectSymbol {
+            name: "end".to_string(),
+            offset: 4,
+            global: false,
+        }],
+        relocations: vec![
+            Relocation {
+                offset: 2,
+                symbol: "end".to_string(),
+                addend: 40000,
+                negated: false,
+            },
+            Relocation {
+                offset: 4,
+                symbol: "end".to_string(),
+                addend: 0,
+                negated: false,
+            },
+        ],
+        ..Object::default()
+    };
+    assert_eq!(
+        vec![
+            LinkError::RelocationOutOfRange {
+                module: 1,
+                symbol: "end".to_string(),
+                value: 40004
+            },
+            LinkError::InvalidRelocation {
+                module: 1,
+                offset: 4
+            }
+        ],
+        link(&[Object::default(), object]).unwrap_err()
+    );
+
+    let large = Object {
+        bytes: vec![0; 3000],
+        ..Object::default()
+    };
+    assert_eq!(
+        vec![LinkError::ProgramTooLarge(6000)],
+        link(&[large.clone(), large]).unwrap_err()
+    );
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use common::strip_listing;
+use interpreter::{assemble, link};
+use std::fs;
+use std::path::Path;
+
+/// Check a `.dis` listing against its binary: the listing, without its
+/// address column, must assemble to the binary, and every instruction
+/// must be listed at the address where it is assembled.
+fn check(listing: &str, binary: &[u8]) -> Result<(), String> {
+    let (source, addresses) = strip_listing(listing);
+    let object = assemble(&source).map_err(|e| format!("{e:?}"))?;
+    for entry in &object.lines {
+        match addresses.get(&entry.line) {
+            Some(&address) if address != entry.offset => {
+                return Err(format!(
+                    "line {} is listed at {address:04} but assembled at {:04}",
+                    entry.line, entry.offset
+                ))
+            }
+            _ => (),
+        }
+    }
+    let image = link(&[object]).map_err(|e| format!("{e:?}"))?;
+    let bytes = image.sections[0].content();
+    if let Some(address) =
+        (0..bytes.len().max(binary.len())).find(|&a| bytes.get(a) != binary.get(a))
+    {
+        return Err(format!(
+            "the listing assembles to {} bytes and the binary has {}, first difference at {address:04}",
+            bytes.len(),
+            binary.len()
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn listings_describe_binaries() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut failures = Vec::new();
+    let mut checked = 0;
+    for directory in ["tests", "examples"] {
+        for entry in fs::read_dir(root.join(directory)).unwrap() {
+            let path = entry.unwrap().path();
+            let other = match path.extension().and_then(|e| e.to_str()) {
+                Some("dis") => path.with_extension("bin"),
+                Some("bin") => path.with_extension("dis"),
+                _ => continue,
+            };
+            if !other.exists() {
+                failures.push(format!("{}: no {}", path.display(), other.display()));
+                continue;
+            }
+            // Each pair is checked once, from its listing
+            if path.extension().unwrap() == "bin" {
+                continue;
+            }
+            let listing = fs::read_to_string(&path).unwrap();
+            if let Err(error) = check(&listing, &fs::read(&other).unwrap()) {
+                failures.push(format!("{}: {error}", path.display()));
+            }
+            checked += 1;
+        }
+    }
+    assert!(checked > 0, "no listings found");
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn stale_listing() {
+    let listing = "  0000   loadimm r1 <- #5\n  0004   exit\n";
+    assert_eq!(check(listing, &[4, 1, 5, 0, 7]), Ok(()));
+    assert!(check(listing, &[4, 1, 6, 0, 7])
+        .unwrap_err()
+        .contains("0002"));
+    let shifted = "  0000   loadimm r1 <- #5\n  0005   exit\n";
+    assert!(check(shifted, &[4, 1, 5, 0, 7])
+        .unwrap_err()
+        .contains("line 2"));
+}
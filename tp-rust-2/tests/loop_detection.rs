@@ -0,0 +1,87 @@
+use interpreter::{Machine, MachineError};
+use std::num::NonZeroU64;
+
+fn detecting(program: &[u8], interval: u64) -> Machine {
+    let mut machine = Machine::new(program);
+    machine.set_loop_detection(NonZeroU64::new(interval));
+    machine
+}
+
+#[test]
+fn disabled_by_default() {
+    assert_eq!(None, Machine::new(&[]).loop_detection());
+}
+
+#[test]
+fn detect_tight_loop() {
+    // 0: loadimm r0 <- #0
+    for interval in [1, 7, 1000] {
+        let mut machine = detecting(&[4, 0, 0, 0], interval);
+        assert_eq!(
+            Err(MachineError::InfiniteLoop { ip: 0, period: 1 }),
+            machine.run_on(&mut Vec::new())
+        );
+    }
+}
+
+#[test]
+fn report_exact_period() {
+    // 0: sub r1 <- r2 - r1
+    // 4: loadimm r0 <- #0
+    // r1 alternates between two values: the state repeats every 4 steps
+    for interval in [1, 2, 3, 5, 64] {
+        let mut machine = detecting(&[5, 1, 2, 1, 4, 0, 0, 0], interval);
+        machine.set_reg(1, 10).unwrap();
+        machine.set_reg(2, 25).unwrap();
+        match machine.run_on(&mut Vec::new()) {
+            Err(MachineError::InfiniteLoop { ip, period }) => {
+                assert!(ip == 0 || ip == 4, "interval {interval}");
+                assert_eq!(4, period, "interval {interval}");
+            }
+            r => panic!("unexpected result {r:?} for interval {interval}"),
+        }
+    }
+}
+
+#[test]
+fn loop_writing_to_memory() {
+    // 0: store [r1] <- r2
+    // 3: sub r2 <- r3 - r2
+    // 7: loadimm r0 <- #0
+    let mut machine = detecting(&[2, 1, 2, 5, 2, 3, 2, 4, 0, 0, 0], 3);
+    machine.set_reg(1, 100).unwrap();
+    machine.set_reg(2, 1).unwrap();
+    machine.set_reg(3, 3).unwrap();
+    assert!(matches!(
+        machine.run_on(&mut Vec::new()),
+        Err(MachineError::InfiniteLoop { period: 6, .. })
+    ));
+}
+
+#[test]
+fn output_is_not_repeated() {
+    // 0: out_number r1
+    // 2: loadimm r0 <- #0
+    let mut machine = detecting(&[8, 1, 4, 0, 0, 0], 1);
+    machine.set_reg(1, 7).unwrap();
+    let mut out = Vec::new();
+    assert!(machine.run_on(&mut out).is_err());
+    assert_eq!(&b"77"[..], &out[..]);
+}
+
+#[test]
+fn terminating_programs_are_not_affected() {
+    for i in 1..13 {
+        let mut machine = detecting(include_bytes!("rfact.bin"), 1);
+        machine.set_reg(10, i).unwrap();
+        machine.run().unwrap();
+        assert_eq!((2..=i).product::<u32>(), machine.regs()[11]);
+    }
+
+    // A long but finite loop
+    let mut machine = detecting(include_bytes!("multiply.bin"), 10);
+    machine.set_reg(11, 3).unwrap();
+    machine.set_reg(12, 100_000).unwrap();
+    machine.run().unwrap();
+    assert_eq!(300_000, machine.regs()[11]);
+}
@@ -0,0 +1,214 @@
+use interpreter::{assemble, link, AssemblyError, AssemblyErrorKind, Machine, StopReason};
+
+fn errors(source: &str) -> Vec<(usize, AssemblyErrorKind)> {
+    assemble(source)
+        .unwrap_err()
+        .into_iter()
+        .map(|AssemblyError { line, kind, .. }| (line, kind))
+        .collect()
+}
+
+/// Run a module on its own and return its exit code.
+fn exit_code(source: &str) -> u32 {
+    let image = link(&[assemble(source).unwrap()]).unwrap();
+    let mut machine = Machine::from_image(&image).unwrap();
+    match machine.run_on(&mut Vec::new()).unwrap() {
+        StopReason::Exited(code) => code,
+        reason => panic!("unexpected stop: {reason:?}"),
+    }
+}
+
+#[test]
+fn count_with_macros() {
+    // examples/count.s, written with a macro and pseudo-instructions
+    let source = r"
+    .macro print_string string, length
+        push r10
+        push r11
+        loadimm r10 <- #\string
+        loadimm r11 <- #\length
+        call print
+        pop r11
+        pop r10
+    .endm
+
+        loadimm r2 <- #4096
+        print_string str_1, 37
+    loop:
+        loadimm r3 <- #-1
+        sub r7 <- r7 - r3
+        out_number r7
+        print_string str_2, 1
+        loadimm r4 <- #10
+        sub r4 <- r7 - r4
+        loadimm r5 <- #ite_then_1
+        move r0 <- r5 if r4 != 0
+        jmp ite_end_1
+    ite_then_1:
+        jmp loop
+    ite_end_1:
+        print_string str_3, 1
+        exit
+    str_1:
+        b'I will count from 1 to 10 (included)\n'
+    str_2:
+        b' '
+    str_3:
+        b'\n'
+    ";
+    let expanded = assemble(source).unwrap();
+    let count = assemble(include_str!("../examples/count.s")).unwrap();
+    assert_eq!(count.bytes, expanded.bytes);
+    assert_eq!(count.symbols, expanded.symbols);
+    assert_eq!(count.relocations, expanded.relocations);
+}
+
+#[test]
+fn pseudo_instructions() {
+    let source = "
+        loadimm r2 <- #4096
+        mov r5 <- #7
+        mov r6 <- r5
+        mov r6 <- r6
+        add r7 <- r6 + #5
+        add r8 <- r7 + r6
+        call double
+        exit r8
+    double:
+        add r8 <- r8 + r8
+        ret
+    ";
+    assert_eq!(38, exit_code(source));
+    // Only the opposite of an added value must fit in 16 bits
+    assert_eq!(
+        32775,
+        exit_code("mov r1 <- #7\nadd r1 <- r1 + #32768\nexit r1")
+    );
+    assert_eq!(
+        vec![AssemblyError {
+            line: 1,
+            column: 17,
+            kind: AssemblyErrorKind::ImmediateOutOfRange(-32768),
+            suggestion: None
+        }],
+        assemble("add r1 <- r1 + #-32768").unwrap_err()
+    );
+    assert_eq!(
+        vec![4, 1, 8, 0, 4, 3, 1, 0, 1, 0, 1, 3, 7],
+        link(&[assemble("mov r1 <- #8\njmp r1\nexit").unwrap()])
+            .unwrap()
+            .sections[0]
+            .bytes
+    );
+}
+
+#[test]
+fn unique_labels() {
+    // Count down from each argument, adding the steps to r8
+    let source = r"
+    .macro count_down register
+    loop\@:
+        add r8 <- r8 + #1
+        add \register <- \register + #-1
+        loadimm r4 <- #loop\@
+        move r0 <- r4 if \register != 0
+    .endm
+        mov r5 <- #3
+        mov r6 <- #4
+        count_down r5
+        count_down r6
+        exit r8
+    ";
+    assert_eq!(7, exit_code(source));
+    let names: Vec<String> = assemble(source)
+        .unwrap()
+        .symbols
+        .into_iter()
+        .map(|s| s.name)
+        .collect();
+    assert_eq!(vec!["loop0", "loop1"], names);
+}
+
+#[test]
+fn return_labels() {
+    // The return labels skip the one written in the source
+    let source = "
+        loadimm r2 <- #4096
+        loadimm r8 <- #1
+        call double
+    return_from_double_1:
+        call double
+        exit r8
+    double:
+        add r8 <- r8 + r8
+        ret
+    ";
+    assert_eq!(4, exit_code(source));
+    let names: Vec<String> = assemble(source)
+        .unwrap()
+        .symbols
+        .into_iter()
+        .map(|s| s.name)
+        .collect();
+    assert_eq!(
+        vec![
+            "return_from_double_2",
+            "return_from_double_1",
+            "return_from_double_3",
+            "double"
+        ],
+        names
+    );
+}
+
+#[test]
+fn macro_errors() {
+    let source = r"
+    .macro twice instruction
+        \instruction
+        \instruction
+    .endm
+    .macro forever
+        forever
+    .endm
+        twice out r1, out r2
+        forever
+    .macro twice
+    .endm
+        .endm
+        push r3
+        twice brk
+    .macro unterminated
+    .macro nested
+    ";
+    assert_eq!(
+        vec![
+            (
+                9,
+                AssemblyErrorKind::MacroArguments {
+                    name: "twice".to_string(),
+                    expected: 1,
+                    found: 2
+                }
+            ),
+            (10, AssemblyErrorKind::MacroRecursion("forever".to_string())),
+            (11, AssemblyErrorKind::DuplicateMacro("twice".to_string())),
+            (13, AssemblyErrorKind::UnmatchedEndm),
+            (14, AssemblyErrorKind::ScratchRegister("push".to_string())),
+            (
+                15,
+                AssemblyErrorKind::MacroArguments {
+                    name: "twice".to_string(),
+                    expected: 0,
+                    found: 1
+                }
+            ),
+            (
+                16,
+                AssemblyErrorKind::UnterminatedMacro("unterminated".to_string())
+            ),
+            (17, AssemblyErrorKind::NestedMacro),
+        ],
+        errors(source)
+    );
+}
@@ -0,0 +1,191 @@
+use interpreter::{Machine, MachineError, MemoryPolicy};
+
+const MEMORY_SIZE: usize = 4096;
+const POLICIES: [MemoryPolicy; 3] = [
+    MemoryPolicy::Strict,
+    MemoryPolicy::Wrap,
+    MemoryPolicy::Aligned,
+];
+
+/// Addresses around the end of memory and of the address space.
+fn boundary_addresses() -> impl Iterator<Item = usize> {
+    (0..8)
+        .chain(MEMORY_SIZE - 8..MEMORY_SIZE + 8)
+        .chain(0xFFFF_FFF8..=0xFFFF_FFFF)
+}
+
+/// The error expected when accessing `length` bytes at `address`, if any.
+fn expected_error(
+    policy: MemoryPolicy,
+    address: usize,
+    length: usize,
+    data: bool,
+) -> Option<MachineError> {
+    match policy {
+        MemoryPolicy::Wrap => None,
+        MemoryPolicy::Aligned if data && !address.is_multiple_of(4) => {
+            Some(MachineError::MisalignedAccess(address))
+        }
+        _ if address + length > MEMORY_SIZE => {
+            Some(MachineError::InvalidMemoryAddress(address.max(MEMORY_SIZE)))
+        }
+        _ => None,
+    }
+}
+
+/// A full memory where byte `i` is `i`, with `code` at the beginning.
+fn memory_with(code: &[u8]) -> Vec<u8> {
+    let mut memory: Vec<u8> = (0..MEMORY_SIZE).map(|i| i as u8).collect();
+    memory[..code.len()].copy_from_slice(code);
+    memory
+}
+
+#[test]
+fn default_policy_is_strict() {
+    assert_eq!(MemoryPolicy::Strict, Machine::new(&[]).memory_policy());
+}
+
+#[test]
+fn load_boundaries() {
+    // 0: load r1 <- [r2]
+    let memory = memory_with(&[3, 1, 2]);
+    for policy in POLICIES {
+        for address in boundary_addresses() {
+            let mut machine = Machine::new(&memory);
+            machine.set_memory_policy(policy);
+            machine.set_reg(2, address as u32).unwrap();
+            match expected_error(policy, address, 4, true) {
+                Some(error) => assert_eq!(Err(error), machine.step(), "{policy:?} {address}"),
+                None => {
+                    assert_eq!(Ok(false), machine.step(), "{policy:?} {address}");
+                    let expected: Vec<u8> = (0..4)
+                        .map(|i| memory[(address + i) % MEMORY_SIZE])
+                        .collect();
+                    assert_eq!(
+                        &expected[..],
+                        &machine.regs()[1].to_le_bytes(),
+                        "{policy:?} {address}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn store_boundaries() {
+    // 0: store [r2] <- r1
+    let memory = memory_with(&[2, 2, 1]);
+    for policy in POLICIES {
+        for address in boundary_addresses() {
+            let mut machine = Machine::new(&memory);
+            machine.set_memory_policy(policy);
+            machine.set_reg(1, 0xAABBCCDD).unwrap();
+            machine.set_reg(2, address as u32).unwrap();
+            match expected_error(policy, address, 4, true) {
+                Some(error) => {
+                    assert_eq!(Err(error), machine.step(), "{policy:?} {address}");
+                    assert_eq!(&memory[..], machine.memory(), "{policy:?} {address}");
+                }
+                None => {
+                    assert_eq!(Ok(false), machine.step(), "{policy:?} {address}");
+                    for (i, b) in 0xAABBCCDDu32.to_le_bytes().iter().enumerate() {
+                        assert_eq!(
+                            *b,
+                            machine.memory()[(address + i) % MEMORY_SIZE],
+                            "{policy:?} {address}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn fetch_boundaries() {
+    for policy in POLICIES {
+        for address in boundary_addresses() {
+            // address: loadimm r1 <- #0x0101
+            let mut memory = vec![1; MEMORY_SIZE];
+            memory[address % MEMORY_SIZE] = 4;
+            let mut machine = Machine::new(&memory);
+            machine.set_memory_policy(policy);
+            machine.set_reg(0, address as u32).unwrap();
+            // Instructions do not need to be aligned
+            match expected_error(policy, address, 4, false) {
+                // The instruction starts in memory and runs past its end
+                Some(_) if address < MEMORY_SIZE => {
+                    assert_eq!(
+                        Err(MachineError::InvalidMemoryAddress(address + 4)),
+                        machine.step(),
+                        "{policy:?} {address}"
+                    );
+                    assert_eq!(
+                        MEMORY_SIZE as u32,
+                        machine.regs()[0],
+                        "{policy:?} {address}"
+                    );
+                }
+                Some(error) => {
+                    assert_eq!(Err(error), machine.step(), "{policy:?} {address}");
+                    assert_eq!(address as u32, machine.regs()[0], "{policy:?} {address}");
+                }
+                None => {
+                    assert_eq!(Ok(false), machine.step(), "{policy:?} {address}");
+                    assert_eq!(0x0101, machine.regs()[1], "{policy:?} {address}");
+                    let next = match policy {
+                        MemoryPolicy::Wrap => (address + 4) % MEMORY_SIZE,
+                        _ => address + 4,
+                    };
+                    assert_eq!(next as u32, machine.regs()[0], "{policy:?} {address}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn last_word_is_accessible() {
+    // 0: store [r2] <- r1
+    // 3: load r3 <- [r2]
+    // 6: exit
+    for policy in POLICIES {
+        let mut machine = Machine::new(&[2, 2, 1, 3, 3, 2, 7]);
+        machine.set_memory_policy(policy);
+        machine.set_reg(1, 42).unwrap();
+        machine.set_reg(2, (MEMORY_SIZE - 4) as u32).unwrap();
+        machine.run().unwrap();
+        assert_eq!(42, machine.regs()[3]);
+    }
+}
+
+#[test]
+fn last_byte_is_executable() {
+    // memory_size-1: exit
+    for policy in POLICIES {
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[MEMORY_SIZE - 1] = 7;
+        let mut machine = Machine::new(&memory);
+        machine.set_memory_policy(policy);
+        machine.set_reg(0, (MEMORY_SIZE - 1) as u32).unwrap();
+        assert_eq!(Ok(true), machine.step());
+    }
+}
+
+#[test]
+fn wrapping_program_runs() {
+    // memory_size-2: out_number r1
+    // 0:             exit
+    let mut memory = vec![0; MEMORY_SIZE];
+    memory[MEMORY_SIZE - 2..].copy_from_slice(&[8, 1]);
+    memory[0] = 7;
+    let mut machine = Machine::new(&memory);
+    machine.set_memory_policy(MemoryPolicy::Wrap);
+    machine.set_reg(0, (MEMORY_SIZE - 2) as u32).unwrap();
+    machine.set_reg(1, 12).unwrap();
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(&b"12"[..], &out[..]);
+    assert_eq!(1, machine.regs()[0]);
+}
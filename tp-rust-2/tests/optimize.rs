@@ -0,0 +1,173 @@
+mod common;
+
+use common::strip_listing;
+use interpreter::{assemble, compile, link, optimize, Machine, Object, StopReason};
+
+/// Assemble a `.dis` listing, without its address column.
+fn assemble_listing(listing: &str) -> Object {
+    assemble(&strip_listing(listing).0).unwrap()
+}
+
+/// What a module prints, how it stops and its final registers but the IP,
+/// starting with some registers set.
+fn run(object: &Object, registers: &[(usize, u32)]) -> (String, StopReason, Vec<u32>) {
+    let image = link(std::slice::from_ref(object)).unwrap();
+    let mut machine = Machine::from_image(&image).unwrap();
+    for &(register, value) in registers {
+        machine.set_reg(register, value).unwrap();
+    }
+    let mut output = Vec::new();
+    let reason = machine.run_on(&mut output).unwrap();
+    let registers = machine.regs()[1..].to_vec();
+    (String::from_utf8(output).unwrap(), reason, registers)
+}
+
+#[test]
+fn test_programs() {
+    // These listings leave nothing to optimize: they have no jump to a
+    // jump or to the next instruction, no consecutive pushes or pops and no
+    // value overwritten before being read. They are left unchanged.
+    let programs: [(&str, &[u8]); 5] = [
+        (include_str!("fact.dis"), include_bytes!("fact.bin")),
+        (include_str!("afact.dis"), include_bytes!("afact.bin")),
+        (include_str!("rfact.dis"), include_bytes!("rfact.bin")),
+        (include_str!("rfact_tr.dis"), include_bytes!("rfact_tr.bin")),
+        (include_str!("multiply.dis"), include_bytes!("multiply.bin")),
+    ];
+    for (listing, binary) in programs {
+        let object = assemble_listing(listing);
+        assert_eq!(
+            link(std::slice::from_ref(&object)).unwrap().sections[0].content(),
+            binary
+        );
+        assert_eq!(optimize(&object), object);
+    }
+}
+
+#[test]
+fn fibo() {
+    // The push of r10 followed by a call loads #4 in r3 only once
+    let object = assemble_listing(include_str!("fibo.dis"));
+    assert_eq!(
+        link(std::slice::from_ref(&object)).unwrap().sections[0].content(),
+        include_bytes!("fibo.bin")
+    );
+    let optimized = optimize(&object);
+    assert_eq!((243, 239), (object.bytes.len(), optimized.bytes.len()));
+    for n in 0..15 {
+        assert_eq!(run(&optimized, &[(10, n)]), run(&object, &[(10, n)]));
+    }
+}
+
+#[test]
+fn compiled_programs() {
+    for source in [
+        include_str!("../examples/factorial.tiny"),
+        include_str!("../examples/fibonacci.tiny"),
+    ] {
+        let object = assemble(&compile(source).unwrap()).unwrap();
+        let optimized = optimize(&object);
+        assert!(optimized.bytes.len() < object.bytes.len());
+        let (printed, reason, _) = run(&object, &[]);
+        assert_eq!(run(&optimized, &[]).0, printed);
+        assert_eq!(run(&optimized, &[]).1, reason);
+    }
+
+    // Every operator, on values covering each sign and branch
+    for (a, b) in [
+        (0, 1),
+        (7, 3),
+        (-7, 3),
+        (7, -3),
+        (-7, -3),
+        (3, 3),
+        (100, -100),
+    ] {
+        let source = format!(
+            r#"
+            fn gcd(x, y) {{
+                while y != 0 {{
+                    var r = x % y;
+                    x = y;
+                    y = r;
+                }}
+                return x;
+            }}
+            fn main() {{
+                var a = {a};
+                var b = {b};
+                print a + b, " ", a - b, " ", a * b, " ", a / b, " ", a % b, "\n";
+                print a < b, a <= b, a > b, a >= b, a == b, a != b, !a, a && b, a || b, "\n";
+                print gcd(a * 12, b * 18), "\n";
+                return a - b;
+            }}
+        "#
+        );
+        let object = assemble(&compile(&source).unwrap()).unwrap();
+        let optimized = optimize(&object);
+        assert!(optimized.bytes.len() < object.bytes.len());
+        let (printed, reason, _) = run(&object, &[]);
+        let (optimized_printed, optimized_reason, _) = run(&optimized, &[]);
+        assert_eq!((optimized_printed, optimized_reason), (printed, reason));
+    }
+}
+
+#[test]
+fn transformations() {
+    let object = assemble(
+        "
+        loadimm r2 <- #4096
+        push r10
+        push r11
+        loadimm r5 <- #7
+        loadimm r5 <- #8
+        jmp first
+        exit
+first:
+        jmp second
+second:
+        out_number r5
+        exit
+",
+    )
+    .unwrap();
+    // The second push reuses r3, the first value of r5 is never read and
+    // the jumps lead straight to `second`
+    let expected = assemble(
+        "
+        loadimm r2 <- #4096
+        push r10
+        sub r2 <- r2 - r3
+        store [r2] <- r11
+        loadimm r5 <- #8
+        jmp second
+        exit
+second:
+        out_number r5
+        exit
+",
+    )
+    .unwrap();
+    let optimized = optimize(&object);
+    assert_eq!(link(&[optimized]).unwrap(), link(&[expected]).unwrap());
+}
+
+#[test]
+fn faulting_load() {
+    // The load stops the machine, which must leave 7 in r5
+    let object = assemble(
+        "
+        loadimm r5 <- #7
+        loadimm r6 <- #5000
+        load r6 <- [r6]
+        loadimm r5 <- #8
+        exit
+",
+    )
+    .unwrap();
+    let optimized = optimize(&object);
+    assert_eq!(optimized, object);
+    let mut machine = Machine::from_image(&link(&[optimized]).unwrap()).unwrap();
+    assert!(machine.run_on(&mut Vec::new()).is_err());
+    assert_eq!(machine.regs()[5], 7);
+}
@@ -0,0 +1,72 @@
+use interpreter::{Access, Machine, MachineError, Permissions};
+
+/// A program and the registers to set before running it.
+type Setup<'a> = (&'a [u8], &'a [(usize, u32)]);
+
+fn run(program: &[u8], regs: &[(usize, u32)], predecoding: bool) -> (Machine, Vec<u8>) {
+    let mut machine = Machine::new(program);
+    machine.set_predecoding(predecoding);
+    for &(reg, value) in regs {
+        machine.set_reg(reg, value).unwrap();
+    }
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    (machine, out)
+}
+
+#[test]
+fn disabled_by_default() {
+    assert!(!Machine::new(&[]).predecoding());
+}
+
+#[test]
+fn same_results_as_interpreter() {
+    let programs: [Setup; 4] = [
+        (include_bytes!("../examples/fibonacci.bin"), &[]),
+        (include_bytes!("../examples/99bottles.bin"), &[]),
+        (include_bytes!("multiply.bin"), &[(11, 13), (12, 50)]),
+        (include_bytes!("rfact.bin"), &[(10, 12)]),
+    ];
+    for (program, regs) in programs {
+        let (interpreted, interpreted_out) = run(program, regs, false);
+        let (predecoded, predecoded_out) = run(program, regs, true);
+        assert!(predecoded.predecoding());
+        assert_eq!(interpreted_out, predecoded_out);
+        assert_eq!(interpreted.regs(), predecoded.regs());
+        assert_eq!(interpreted.memory(), predecoded.memory());
+    }
+}
+
+#[test]
+fn self_modifying_code() {
+    // 0: out_number r1
+    // 2: store [r2] <- r3 (replaces the first instruction by out_number r4)
+    // 5: sub r5 <- r5 - r6
+    // 9: loadimm r7 <- #0
+    // 13: move r0 <- r7 if r5 != 0
+    // 17: exit
+    let program = [8, 1, 2, 2, 3, 5, 5, 5, 6, 4, 7, 0, 0, 1, 0, 7, 5, 7];
+    let regs = [(1, 11), (3, 0x02020408), (4, 44), (5, 2), (6, 1)];
+    for predecoding in [false, true] {
+        let (_, out) = run(&program, &regs, predecoding);
+        assert_eq!(&b"1144"[..], &out[..]);
+    }
+}
+
+#[test]
+fn protection_changes_apply() {
+    // 0: loadimm r1 <- #1
+    // 4: move r0 <- r2 if r1 != 0
+    let mut machine = Machine::new(&[4, 1, 1, 0, 1, 0, 2, 1]);
+    machine.set_predecoding(true);
+    machine.step().unwrap();
+    machine.step().unwrap();
+    machine.protect(0..4, Permissions::RW).unwrap();
+    assert_eq!(
+        Err(MachineError::ProtectionFault {
+            address: 0,
+            access: Access::Execute
+        }),
+        machine.step()
+    );
+}
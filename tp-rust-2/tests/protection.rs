@@ -0,0 +1,131 @@
+use interpreter::{Access, Machine, MachineError, Permissions, Region};
+
+#[test]
+fn unprotected_by_default() {
+    // 0: store [r1] <- r2
+    // 3: exit
+    let mut machine = Machine::new(&[2, 1, 2, 7]);
+    assert!(machine.regions().is_empty());
+    assert_eq!(Permissions::RWX, machine.permissions(0));
+    machine.set_reg(2, 0x07020105).unwrap();
+    machine.run().unwrap();
+    assert_eq!(&[5, 1, 2, 7], &machine.memory()[0..4]);
+}
+
+#[test]
+fn protected_code_is_read_only() {
+    // 0: store [r1] <- r2
+    // 3: exit
+    let mut machine = Machine::new_protected(&[2, 1, 2, 7]);
+    assert_eq!(Permissions::RX, machine.permissions(3));
+    assert_eq!(Permissions::RW, machine.permissions(4));
+    machine.set_reg(1, 1).unwrap();
+    assert!(matches!(
+        machine.step(),
+        Err(MachineError::ProtectionFault {
+            address: 1,
+            access: Access::Write
+        })
+    ));
+    assert_eq!(&[2, 1, 2, 7], &machine.memory()[0..4]);
+
+    // Storing after the code is allowed
+    let mut machine = Machine::new_protected(&[2, 1, 2, 7]);
+    machine.set_reg(1, 4).unwrap();
+    machine.set_reg(2, 42).unwrap();
+    machine.run().unwrap();
+    assert_eq!(42, machine.memory()[4]);
+}
+
+#[test]
+fn protected_data_is_not_executable() {
+    // 0: loadimm r0 <- #4
+    // 4: exit
+    let mut machine = Machine::new(&[4, 0, 4, 0, 7]);
+    machine.protect(4..5, Permissions::RW).unwrap();
+    machine.step().unwrap();
+    assert!(matches!(
+        machine.step(),
+        Err(MachineError::ProtectionFault {
+            address: 4,
+            access: Access::Execute
+        })
+    ));
+    assert_eq!(4, machine.regs()[0]);
+}
+
+#[test]
+fn instruction_partly_outside_code() {
+    // 0: loadimm r1 <- #0 (last byte is not executable)
+    let mut machine = Machine::new(&[4, 1, 0, 0]);
+    machine.protect(3..4, Permissions::R).unwrap();
+    assert!(matches!(
+        machine.step(),
+        Err(MachineError::ProtectionFault {
+            address: 3,
+            access: Access::Execute
+        })
+    ));
+}
+
+#[test]
+fn unreadable_region() {
+    // 0: load r1 <- [r2]
+    // 3: exit
+    let mut machine = Machine::new(&[3, 1, 2, 7]);
+    machine.protect(100..200, Permissions::NONE).unwrap();
+    machine.set_reg(2, 98).unwrap();
+    assert!(matches!(
+        machine.step(),
+        Err(MachineError::ProtectionFault {
+            address: 100,
+            access: Access::Read
+        })
+    ));
+}
+
+#[test]
+fn last_region_wins() {
+    let mut machine = Machine::new(&[]);
+    machine.protect(0..4096, Permissions::RW).unwrap();
+    machine.protect(10..20, Permissions::RX).unwrap();
+    assert_eq!(Permissions::RW, machine.permissions(9));
+    assert_eq!(Permissions::RX, machine.permissions(10));
+    assert_eq!(Permissions::RX, machine.permissions(19));
+    assert_eq!(Permissions::RW, machine.permissions(20));
+    assert_eq!(
+        &[
+            Region {
+                start: 0,
+                end: 4096,
+                permissions: Permissions::RW
+            },
+            Region {
+                start: 10,
+                end: 20,
+                permissions: Permissions::RX
+            }
+        ],
+        machine.regions()
+    );
+}
+
+#[test]
+fn refuse_invalid_region() {
+    let mut machine = Machine::new(&[]);
+    assert!(machine.protect(0..4097, Permissions::RW).is_err());
+    #[allow(clippy::reversed_empty_ranges)]
+    let reversed = 20..10;
+    assert!(machine.protect(reversed, Permissions::RW).is_err());
+    assert!(machine.regions().is_empty());
+}
+
+#[test]
+fn protected_programs_still_run() {
+    for i in 1..13 {
+        let mut machine = Machine::new_protected(include_bytes!("rfact.bin"));
+        machine.set_reg(10, i).unwrap();
+        machine.run().unwrap();
+        assert_eq!((2..=i).product::<u32>(), machine.regs()[11]);
+    }
+}
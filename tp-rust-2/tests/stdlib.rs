@@ -0,0 +1,138 @@
+use interpreter::{assemble, assemble_with_tests, run_test, AssemblyErrorKind, LIBRARY};
+
+#[test]
+fn library_tests() {
+    let (object, tests) = assemble_with_tests(LIBRARY[0].1).unwrap();
+    assert!(!tests.is_empty());
+    for test in tests {
+        assert_eq!(Ok(()), run_test(&object, &test), "{}", test.name);
+    }
+}
+
+#[test]
+fn include() {
+    let source = "
+    loadimm r2 <- #4096
+    loadimm r11 <- #-6
+    loadimm r12 <- #7
+    call mult
+    mov r10 <- r11
+    call print_hex
+    loadimm r10 <- #message
+    call strlen
+    loadimm r10 <- #message
+    call print
+    exit
+message:
+    .asciz \"\\n\"
+    .include \"std.s\"
+.test program
+    .expect r10, message + 1
+    .output \"ffffffd6\\n\"
+.endtest
+";
+    let (object, tests) = assemble_with_tests(source).unwrap();
+    // The tests of the library are left out
+    assert_eq!(1, tests.len());
+    assert_eq!(Ok(()), run_test(&object, &tests[0]));
+    // The whole library is attributed to the line of `.include`
+    let start = object.symbols.iter().find(|s| s.name == "print").unwrap();
+    assert!(object
+        .lines
+        .iter()
+        .filter(|entry| entry.offset >= start.offset)
+        .all(|entry| entry.line == 15));
+}
+
+#[test]
+fn include_errors() {
+    let errors =
+        assemble("  .include \"sdt.s\"\n.include std.s\n.include \"std.s\" 1").unwrap_err();
+    assert_eq!(
+        errors
+            .iter()
+            .map(|e| (e.line, e.kind.clone()))
+            .collect::<Vec<_>>(),
+        [
+            (1, AssemblyErrorKind::UnknownInclude("sdt.s".to_string())),
+            (2, AssemblyErrorKind::Expected("a string")),
+            (3, AssemblyErrorKind::Expected("the end of the line")),
+        ]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "1:12: no library is named `sdt.s`, did you mean `std.s`?"
+    );
+}
+
+#[test]
+fn errors_in_library() {
+    // The errors raised by the lines of the library, here when they are
+    // assembled and when their immediates are computed, point to `.include`
+    let errors = assemble(".equ std_heap_top, 100000\n  .include \"std.s\"\n").unwrap_err();
+    let duplicate = AssemblyErrorKind::DuplicateLabel("std_heap_top".to_string());
+    let out_of_range = AssemblyErrorKind::ImmediateOutOfRange(100000);
+    assert_eq!(
+        errors
+            .iter()
+            .map(|e| (e.line, e.column, e.kind.clone()))
+            .collect::<Vec<_>>(),
+        [
+            (2, 3, duplicate),
+            (2, 3, out_of_range.clone()),
+            (2, 3, out_of_range),
+        ]
+    );
+}
+
+#[test]
+fn large_operands() {
+    // Operands far too large to be handled one unit at a time in the steps
+    // allowed to a test
+    let min = i32::MIN;
+    let pairs = [
+        (123456789, 1000),
+        (-7, 2147483647),
+        (2147483647, -2147483647),
+        (65536, 65536),
+        (46341, -46341),
+        (min, -1),
+        (min, 1),
+        (min, -7),
+        (min, min),
+        (5, min),
+        (-5, min),
+        (min, 2147483647),
+        (2147483647, 3),
+        (-2000000000, 7),
+        (30000, 1),
+    ];
+    let mut source = String::from(".include \"std.s\"\n");
+    for (i, (a, b)) in pairs.into_iter().enumerate() {
+        let (product, quotient, remainder) =
+            (a.wrapping_mul(b), a.wrapping_div(b), a.wrapping_rem(b));
+        source += &format!(
+            "
+.test mult_{i}
+    .call mult
+    .set r11, {a}
+    .set r12, {b}
+    .expect r11, {product}
+.endtest
+.test divmod_{i}
+    .call divmod
+    .set r11, {a}
+    .set r12, {b}
+    .expect r11, {quotient}
+    .expect r12, {remainder}
+    .expect sp, 4096
+.endtest
+"
+        );
+    }
+    let (object, tests) = assemble_with_tests(&source).unwrap();
+    assert_eq!(2 * pairs.len(), tests.len());
+    for test in tests {
+        assert_eq!(Ok(()), run_test(&object, &test), "{}", test.name);
+    }
+}
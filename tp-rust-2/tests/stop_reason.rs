@@ -0,0 +1,123 @@
+use interpreter::{verify, Machine, StopReason, WatchKind};
+
+// 0: out_number r1
+// 2: brk
+// 3: out_number r2
+// 5: brk
+// 6: exit
+const TWO_BREAKPOINTS: [u8; 7] = [8, 1, 9, 8, 2, 9, 7];
+
+#[test]
+fn exited() {
+    let mut machine = Machine::new(&[7]);
+    assert_eq!(
+        StopReason::Exited(0),
+        machine.run_on(&mut Vec::new()).unwrap()
+    );
+    assert_eq!(
+        StopReason::Exited(0),
+        Machine::new(&[7]).run_until_on(&mut Vec::new(), 1).unwrap()
+    );
+}
+
+#[test]
+fn exit_code() {
+    // 0: exit r3
+    let mut machine = Machine::new(&[10, 3]);
+    assert_eq!(None, machine.exit_code());
+    machine.set_reg(3, 12).unwrap();
+    assert_eq!(
+        StopReason::Exited(12),
+        machine.run_on(&mut Vec::new()).unwrap()
+    );
+    assert_eq!(Some(12), machine.exit_code());
+
+    let mut machine = Machine::new(&[7]);
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(Some(0), machine.exit_code());
+
+    // 0: exit r16
+    let mut machine = Machine::new(&[10, 16]);
+    assert!(machine.run_on(&mut Vec::new()).is_err());
+    assert_eq!(None, machine.exit_code());
+}
+
+#[test]
+fn resume_after_breakpoints() {
+    let mut machine = Machine::new(&TWO_BREAKPOINTS);
+    machine.set_reg(1, 1).unwrap();
+    machine.set_reg(2, 2).unwrap();
+    let mut out = Vec::new();
+    assert_eq!(StopReason::Breakpoint(2), machine.run_on(&mut out).unwrap());
+    assert_eq!(3, machine.regs()[0]);
+    assert_eq!(b"1", &out[..]);
+    assert_eq!(StopReason::Breakpoint(5), machine.run_on(&mut out).unwrap());
+    assert_eq!(b"12", &out[..]);
+    assert_eq!(StopReason::Exited(0), machine.run_on(&mut out).unwrap());
+}
+
+#[test]
+fn step_limit() {
+    let mut machine = Machine::new(&TWO_BREAKPOINTS);
+    let mut out = Vec::new();
+    assert_eq!(
+        StopReason::StepLimit,
+        machine.run_until_on(&mut out, 0).unwrap()
+    );
+    assert_eq!(0, machine.regs()[0]);
+    assert_eq!(
+        StopReason::StepLimit,
+        machine.run_until_on(&mut out, 1).unwrap()
+    );
+    assert_eq!(2, machine.regs()[0]);
+    // The breakpoint is the second step
+    assert_eq!(
+        StopReason::Breakpoint(2),
+        machine.run_until_on(&mut out, 1).unwrap()
+    );
+    assert_eq!(
+        StopReason::Breakpoint(5),
+        machine.run_until_on(&mut out, 10).unwrap()
+    );
+}
+
+#[test]
+fn step_limit_in_infinite_loop() {
+    // 0: loadimm r1 <- #0
+    // 4: move r0 <- r1 if r2 != 0
+    let mut machine = Machine::new(&[4, 1, 0, 0, 1, 0, 1, 2]);
+    machine.set_reg(2, 1).unwrap();
+    assert_eq!(
+        StopReason::StepLimit,
+        machine.run_until_on(&mut Vec::new(), 1001).unwrap()
+    );
+    assert_eq!(4, machine.regs()[0]);
+}
+
+#[test]
+fn watchpoint() {
+    // 0: store [r1] <- r2
+    // 3: brk
+    // 4: exit
+    let mut machine = Machine::new(&[2, 1, 2, 9, 7]);
+    machine.set_reg(1, 100).unwrap();
+    machine.add_watchpoint(100..104, WatchKind::Write).unwrap();
+    let mut out = Vec::new();
+    assert!(matches!(
+        machine.run_until_on(&mut out, 10).unwrap(),
+        StopReason::Watchpoint(_)
+    ));
+    assert_eq!(
+        StopReason::Breakpoint(3),
+        machine.run_until_on(&mut out, 10).unwrap()
+    );
+    assert_eq!(
+        StopReason::Exited(0),
+        machine.run_until_on(&mut out, 10).unwrap()
+    );
+}
+
+#[test]
+fn breakpoints_are_verified() {
+    assert_eq!(Ok(()), verify(&TWO_BREAKPOINTS));
+}
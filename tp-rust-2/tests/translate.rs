@@ -0,0 +1,203 @@
+use interpreter::{translate, Diagnostic, DiagnosticKind, Image, Machine, Section, SectionKind};
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A program and the registers to set before running it.
+type Setup<'a> = (&'a [u8], &'a [(usize, u32)]);
+
+// 0: out_number r1
+// 2: store [r2] <- r3 (replaces the first instruction by out_number r4)
+// 5: sub r5 <- r5 - r6
+// 9: loadimm r7 <- #0
+// 13: move r0 <- r7 if r5 != 0
+// 17: exit
+const SELF_MODIFYING: [u8; 18] = [8, 1, 2, 2, 3, 5, 5, 5, 6, 4, 7, 0, 0, 1, 0, 7, 5, 7];
+
+// 0: loadimm r1 <- #42
+// 4: exit r1
+const EXIT_CODE: [u8; 6] = [4, 1, 42, 0, 10, 1];
+
+const PROGRAMS: [Setup; 9] = [
+    (include_bytes!("../examples/fibonacci.bin"), &[]),
+    (include_bytes!("../examples/99bottles.bin"), &[]),
+    (include_bytes!("../examples/count.bin"), &[]),
+    (
+        include_bytes!("multiply.bin"),
+        &[(11, (-23i32) as u32), (12, 50)],
+    ),
+    (include_bytes!("afact.bin"), &[(10, 10)]),
+    (include_bytes!("rfact.bin"), &[(10, 12)]),
+    (include_bytes!("rfact_tr.bin"), &[(10, 7)]),
+    (
+        &SELF_MODIFYING,
+        &[(1, 11), (3, 0x02020408), (4, 44), (5, 2), (6, 1)],
+    ),
+    (&EXIT_CODE, &[]),
+];
+
+/// Compile the translation of every program into one executable taking
+/// the program index and the 16 initial registers as arguments, and
+/// printing the stop reason, the final registers, the final memory and
+/// the program output.
+fn build() -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("translate");
+    fs::create_dir_all(&dir).unwrap();
+    let mut main = String::new();
+    let mut arms = String::new();
+    for (i, (program, _)) in PROGRAMS.iter().enumerate() {
+        fs::write(dir.join(format!("p{i}.rs")), translate(program).unwrap()).unwrap();
+        writeln!(main, "mod p{i} {{ include!(\"p{i}.rs\"); }}").unwrap();
+        writeln!(
+            arms,
+            "        {i} => {{ let mut m = p{i}::memory(); \
+             let s = p{i}::run(&mut regs, &mut m, &mut out).unwrap(); (format!(\"{{s:?}}\"), m) }}"
+        )
+        .unwrap();
+    }
+    write!(
+        main,
+        r#"
+use std::io::Write;
+
+fn main() {{
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut regs = [0u32; 16];
+    for (reg, value) in args[1..].iter().enumerate() {{
+        regs[reg] = value.parse().unwrap();
+    }}
+    let mut out = Vec::new();
+    let (stop, memory) = match args[0].parse::<usize>().unwrap() {{
+{arms}        _ => unreachable!(),
+    }};
+    let regs: Vec<String> = regs.iter().map(|r| r.to_string()).collect();
+    let memory: Vec<String> = memory.iter().map(|b| format!("{{b:02x}}")).collect();
+    println!("{{stop}}");
+    println!("{{}}", regs.join(" "));
+    println!("{{}}", memory.concat());
+    std::io::stdout().write_all(&out).unwrap();
+}}
+"#
+    )
+    .unwrap();
+    fs::write(dir.join("main.rs"), main).unwrap();
+    let executable = dir.join("translated");
+    let status = Command::new(std::env::var("RUSTC").unwrap_or("rustc".to_string()))
+        .args(["--edition", "2021", "-C", "opt-level=1", "-o"])
+        .arg(&executable)
+        .arg(dir.join("main.rs"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    executable
+}
+
+#[test]
+fn same_behaviour_as_interpreter() {
+    let executable = build();
+    for (i, (program, initial)) in PROGRAMS.iter().enumerate() {
+        // Interpreter
+        let mut machine = Machine::new(program);
+        for &(reg, value) in initial.iter() {
+            machine.set_reg(reg, value).unwrap();
+        }
+        let mut expected_out = Vec::new();
+        machine.run_on(&mut expected_out).unwrap();
+
+        // Translation, falling back to the interpreter if needed
+        let mut regs = [0u32; 16];
+        for &(reg, value) in initial.iter() {
+            regs[reg] = value;
+        }
+        let output = Command::new(&executable)
+            .arg(i.to_string())
+            .args(regs.iter().map(|r| r.to_string()))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let mut lines = output.stdout.splitn(4, |&b| b == b'\n');
+        let stop = String::from_utf8(lines.next().unwrap().to_vec()).unwrap();
+        let regs: Vec<u32> = String::from_utf8(lines.next().unwrap().to_vec())
+            .unwrap()
+            .split(' ')
+            .map(|r| r.parse().unwrap())
+            .collect();
+        let memory: Vec<u8> = lines
+            .next()
+            .unwrap()
+            .chunks(2)
+            .map(|b| u8::from_str_radix(std::str::from_utf8(b).unwrap(), 16).unwrap())
+            .collect();
+        let mut out = lines.next().unwrap().to_vec();
+        let (regs, memory) = match stop.as_str() {
+            stop if stop.starts_with("Exit") => {
+                let code = machine.exit_code().unwrap();
+                assert_eq!(format!("Exit({code})"), stop, "program {i}");
+                (regs, memory)
+            }
+            "Fallback" => {
+                let mut resumed = Machine::new(&memory);
+                for (reg, &value) in regs.iter().enumerate() {
+                    resumed.set_reg(reg, value).unwrap();
+                }
+                resumed.run_on(&mut out).unwrap();
+                (resumed.regs().to_vec(), resumed.memory().to_vec())
+            }
+            _ => panic!("unexpected stop reason {stop}"),
+        };
+
+        assert_eq!(expected_out, out, "program {i}");
+        assert_eq!(machine.regs(), &regs[..], "program {i}");
+        assert_eq!(machine.memory(), &memory[..], "program {i}");
+    }
+}
+
+#[test]
+fn refuse_unverified_program() {
+    assert_eq!(
+        Err(vec![Diagnostic {
+            address: 0,
+            kind: DiagnosticKind::InvalidInstruction(0)
+        }]),
+        translate(&[])
+    );
+}
+
+#[test]
+fn one_state_per_block() {
+    // 0: loadimm r1 <- #1
+    // 4: exit
+    let source = translate(&[4, 1, 1, 0, 7]).unwrap();
+    assert!(source.contains("            0 => {"));
+    assert!(!source.contains("            4 => {"));
+
+    // 0: move r0 <- r2 if r1 != 0
+    // 4: exit
+    let source = translate(&[1, 0, 2, 1, 7]).unwrap();
+    assert!(source.contains("            0 => {"));
+    assert!(source.contains("            4 => {"));
+    assert!(source.contains("_ => return Ok(Stop::Fallback),"));
+}
+
+#[test]
+fn image_entry_point() {
+    // 0: data
+    // 1: out_number r1
+    // 3: exit
+    let image = Image {
+        entry: 1,
+        sections: vec![Section {
+            kind: SectionKind::Code,
+            address: 0,
+            size: 4,
+            bytes: vec![0xff, 8, 1, 7],
+        }],
+        symbols: Vec::new(),
+    };
+    let source = translate(&image.to_bytes()).unwrap();
+    assert!(source.contains("pub const PROGRAM: &[u8] = &[255, 8, 1, 7];"));
+    assert!(source.contains("pub const ENTRY: u32 = 1;"));
+    assert!(source.contains("            1 => {"));
+    assert!(!source.contains("            0 => {"));
+}
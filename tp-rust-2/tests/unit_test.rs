@@ -0,0 +1,186 @@
+use interpreter::{
+    assemble, assemble_with_tests, run_test, AssemblyErrorKind, MachineError, TestFailure,
+    UnitTest, MAX_TEST_STEPS,
+};
+
+fn errors(source: &str) -> Vec<(usize, AssemblyErrorKind)> {
+    assemble_with_tests(source)
+        .unwrap_err()
+        .into_iter()
+        .map(|e| (e.line, e.kind))
+        .collect()
+}
+
+#[test]
+fn example_tests() {
+    let source = include_str!("../examples/print.s");
+    let (object, tests) = assemble_with_tests(source).unwrap();
+    assert_eq!(object, assemble(source).unwrap());
+    assert_eq!(2, tests.len());
+    for test in tests {
+        assert_eq!(Ok(()), run_test(&object, &test), "{}", test.name);
+    }
+}
+
+#[test]
+fn directives() {
+    let source = "
+.test first
+    .call double
+    .set r11, -2
+    .set [buffer + 1], 'a', \"bc\", 0x01020304
+    .expect sp, 4096
+    .expect [buffer], 0
+    .output \"x\", \"\\n\"
+.endtest
+.test second
+.endtest
+double:
+    sub r11 <- r11 - r3
+    ret
+buffer: .space 8
+";
+    let (object, tests) = assemble_with_tests(source).unwrap();
+    assert_eq!(object.bytes.len(), 31);
+    assert_eq!(
+        tests,
+        [
+            UnitTest {
+                name: "first".to_string(),
+                line: 2,
+                routine: Some("double".to_string()),
+                registers: vec![(11, -2i32 as u32)],
+                memory: vec![(24, vec![b'a', 0, 0, 0, b'b', b'c', 4, 3, 2, 1])],
+                expected_registers: vec![(2, 4096)],
+                expected_memory: vec![(23, vec![0; 4])],
+                expected_output: Some(b"x\n".to_vec()),
+            },
+            UnitTest {
+                name: "second".to_string(),
+                line: 10,
+                routine: None,
+                registers: Vec::new(),
+                memory: Vec::new(),
+                expected_registers: Vec::new(),
+                expected_memory: Vec::new(),
+                expected_output: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn failures() {
+    let source = "
+double:
+    sub r11 <- r11 - r12
+    ret
+spin:
+    jmp spin
+.test wrong
+    .call double
+    .set r11, 5
+    .set r12, -5
+    .expect r11, 11
+    .expect [text], \"ac\"
+    .output \"x\"
+.endtest
+.test right
+    .call double
+    .set r11, 5
+    .set r12, -5
+    .expect r11, 10
+    .expect [text], \"ab\"
+    .output \"\"
+.endtest
+.test forever
+    .call spin
+.endtest
+.test no_stack
+    .call double
+    .set sp, 0
+.endtest
+text: .ascii \"ab\"
+";
+    let (object, tests) = assemble_with_tests(source).unwrap();
+    let results: Vec<_> = tests.iter().map(|test| run_test(&object, test)).collect();
+    assert_eq!(
+        results,
+        [
+            Err(vec![
+                TestFailure::Register {
+                    register: 11,
+                    expected: 11,
+                    found: 10
+                },
+                TestFailure::Memory {
+                    address: 27,
+                    expected: b"ac".to_vec(),
+                    found: b"ab".to_vec()
+                },
+                TestFailure::Output {
+                    expected: b"x".to_vec(),
+                    found: Vec::new()
+                },
+            ]),
+            Ok(()),
+            Err(vec![TestFailure::StepLimit]),
+            Err(vec![TestFailure::Error {
+                error: MachineError::InvalidMemoryAddress(u32::MAX as usize - 3),
+                location: "double_caller+12".to_string()
+            }]),
+        ]
+    );
+    assert_eq!(
+        "r11 is 10 but 11 was expected",
+        results[0].as_ref().unwrap_err()[0].to_string()
+    );
+    assert_eq!(
+        format!("still running after {MAX_TEST_STEPS} instructions"),
+        TestFailure::StepLimit.to_string()
+    );
+}
+
+#[test]
+fn test_errors() {
+    let source = "
+.test nested
+    .test inner
+    exit
+    .call missing
+.endtest
+.endtest
+.test values
+    .set r16, 1
+    .set [4094], 0
+    .expect r1, 0x100000000
+    .output 1
+.endtest
+.test unterminated
+";
+    assert_eq!(
+        errors(source),
+        [
+            (3, AssemblyErrorKind::NestedTest),
+            (4, AssemblyErrorKind::Expected("a test directive")),
+            (5, AssemblyErrorKind::UndefinedLabel("missing".to_string())),
+            (7, AssemblyErrorKind::UnmatchedEndtest),
+            (9, AssemblyErrorKind::InvalidRegister("r16".to_string())),
+            (10, AssemblyErrorKind::InvalidAddress(4094)),
+            (12, AssemblyErrorKind::Expected("a string")),
+            (
+                14,
+                AssemblyErrorKind::UnterminatedTest("unterminated".to_string())
+            ),
+        ]
+    );
+    // Only the first error of the values of a test is reported
+    assert_eq!(
+        errors(".test t\n.expect r1, 0x100000000\n.expect [x], 1\n.endtest"),
+        [(2, AssemblyErrorKind::WordOutOfRange(0x100000000))]
+    );
+    assert_eq!(
+        assemble_with_tests(".test t\n.sett r1, 1\n.endtest").unwrap_err()[0].to_string(),
+        "2:1: unknown directive `sett`, did you mean `.set`?"
+    );
+}
@@ -0,0 +1,222 @@
+use interpreter::{verify, Diagnostic, DiagnosticKind, Image, ImageError, Section, SectionKind};
+
+fn diagnostics(program: &[u8]) -> Vec<Diagnostic> {
+    verify(program).unwrap_err()
+}
+
+#[test]
+fn accept_shipped_programs() {
+    for program in [
+        &include_bytes!("afact.bin")[..],
+        include_bytes!("fact.bin"),
+        include_bytes!("fibo.bin"),
+        include_bytes!("function.bin"),
+        include_bytes!("multiply.bin"),
+        include_bytes!("push_pop.bin"),
+        include_bytes!("rfact.bin"),
+        include_bytes!("rfact_tr.bin"),
+        include_bytes!("../examples/99bottles.bin"),
+        include_bytes!("../examples/count.bin"),
+        include_bytes!("../examples/factorial.bin"),
+        include_bytes!("../examples/fibonacci.bin"),
+        include_bytes!("../examples/hello_world.bin"),
+    ] {
+        assert_eq!(Ok(()), verify(program));
+    }
+}
+
+#[test]
+fn reject_illegal_instruction() {
+    assert_eq!(
+        vec![Diagnostic {
+            address: 0,
+            kind: DiagnosticKind::InvalidInstruction(0)
+        }],
+        diagnostics(&[])
+    );
+}
+
+#[test]
+fn ignore_unreachable_bytes() {
+    // 0: exit
+    // 1: invalid
+    assert_eq!(Ok(()), verify(&[7, 0xff]));
+
+    // 0: loadimm r0 <- #5
+    // 4: invalid
+    // 5: exit
+    assert_eq!(Ok(()), verify(&[4, 0, 5, 0, 0, 7]));
+}
+
+#[test]
+fn follow_return_address() {
+    // 0: loadimm r1 <- #11
+    // 4: store [r2] <- r1
+    // 7: loadimm r0 <- #12
+    // 11: invalid
+    // 12: exit
+    assert_eq!(
+        vec![Diagnostic {
+            address: 11,
+            kind: DiagnosticKind::InvalidInstruction(0)
+        }],
+        diagnostics(&[4, 1, 11, 0, 2, 2, 1, 4, 0, 12, 0, 0, 7])
+    );
+}
+
+#[test]
+fn reject_invalid_registers() {
+    // 0: move r1 <- r100 if r0 != 0
+    // 4: sub r16 <- r1 - r1
+    assert_eq!(
+        vec![Diagnostic {
+            address: 0,
+            kind: DiagnosticKind::InvalidRegister(100)
+        }],
+        diagnostics(&[1, 1, 100, 0, 5, 16, 1, 1])
+    );
+
+    // 0: sub r16 <- r1 - r17
+    assert_eq!(
+        vec![
+            Diagnostic {
+                address: 0,
+                kind: DiagnosticKind::InvalidRegister(16)
+            },
+            Diagnostic {
+                address: 0,
+                kind: DiagnosticKind::InvalidRegister(17)
+            }
+        ],
+        diagnostics(&[5, 16, 1, 17])
+    );
+
+    // 0: exit r20
+    assert_eq!(
+        vec![Diagnostic {
+            address: 0,
+            kind: DiagnosticKind::InvalidRegister(20)
+        }],
+        diagnostics(&[10, 20])
+    );
+}
+
+#[test]
+fn reject_truncated_instruction() {
+    // 0: loadimm r0 <- #4094
+    // 4094: loadimm (truncated)
+    let mut memory = vec![0; 4096];
+    memory[..4].copy_from_slice(&[4, 0, 0xfe, 0x0f]);
+    memory[4094] = 4;
+    assert_eq!(
+        vec![Diagnostic {
+            address: 4094,
+            kind: DiagnosticKind::TruncatedInstruction { length: 4 }
+        }],
+        diagnostics(&memory)
+    );
+}
+
+#[test]
+fn reject_jump_outside_memory() {
+    // 0: move r0 <- r1 if r1 != 0
+    // 4: loadimm r0 <- #5000
+    let mut memory = vec![1, 0, 1, 1, 4, 0];
+    memory.extend(5000u16.to_le_bytes());
+    assert_eq!(
+        vec![Diagnostic {
+            address: 4,
+            kind: DiagnosticKind::InvalidJumpTarget(5000)
+        }],
+        diagnostics(&memory)
+    );
+
+    // 0: loadimm r0 <- #4092
+    // 4092: loadimm r1 <- #0
+    // 4096:
+    let mut memory = vec![0; 4096];
+    memory[..4].copy_from_slice(&[4, 0, 0xfc, 0x0f]);
+    memory[4092..].copy_from_slice(&[4, 1, 0, 0]);
+    assert_eq!(
+        vec![Diagnostic {
+            address: 4092,
+            kind: DiagnosticKind::InvalidJumpTarget(4096)
+        }],
+        diagnostics(&memory)
+    );
+}
+
+#[test]
+fn reject_store_into_code() {
+    // 0: loadimm r1 <- #6
+    // 4: loadimm r2 <- #10
+    // 8: store [r2] <- r1
+    // 11: exit
+    assert_eq!(
+        vec![Diagnostic {
+            address: 8,
+            kind: DiagnosticKind::StoreIntoCode(10)
+        }],
+        diagnostics(&[4, 1, 6, 0, 4, 2, 10, 0, 2, 2, 1, 7])
+    );
+
+    // Storing right after the code is fine
+    // 0: loadimm r2 <- #8
+    // 4: store [r2] <- r1
+    // 7: exit
+    assert_eq!(Ok(()), verify(&[4, 2, 8, 0, 2, 2, 1, 7]));
+}
+
+#[test]
+fn reject_too_large_program() {
+    assert_eq!(
+        vec![Diagnostic {
+            address: 0,
+            kind: DiagnosticKind::ProgramTooLarge(4097)
+        }],
+        diagnostics(&[7; 4097])
+    );
+}
+
+#[test]
+fn start_at_entry_point() {
+    // 0: invalid opcode
+    // 1: exit
+    let image = Image {
+        entry: 1,
+        sections: vec![Section {
+            kind: SectionKind::Code,
+            address: 0,
+            size: 2,
+            bytes: vec![0xff, 7],
+        }],
+        symbols: Vec::new(),
+    };
+    assert_eq!(Ok(()), verify(&image.to_bytes()));
+    assert_eq!(
+        vec![Diagnostic {
+            address: 0,
+            kind: DiagnosticKind::InvalidInstruction(0xff)
+        }],
+        diagnostics(&image.sections[0].bytes)
+    );
+    let outside = Image {
+        entry: 4096,
+        ..image.clone()
+    };
+    assert_eq!(
+        vec![Diagnostic {
+            address: 4096,
+            kind: DiagnosticKind::InvalidJumpTarget(4096)
+        }],
+        diagnostics(&outside.to_bytes())
+    );
+    let bytes = image.to_bytes();
+    assert_eq!(
+        vec![Diagnostic {
+            address: 0,
+            kind: DiagnosticKind::InvalidImage(ImageError::Truncated)
+        }],
+        diagnostics(&bytes[..bytes.len() - 1])
+    );
+}
@@ -0,0 +1,118 @@
+use interpreter::{Access, Machine, StopReason, WatchKind, Watchpoint, WatchpointHit};
+
+fn afact(n: u32) -> Machine {
+    let mut machine = Machine::new(include_bytes!("afact.bin"));
+    machine.set_reg(10, n).unwrap();
+    machine
+}
+
+#[test]
+fn write_watchpoint_on_return_address() {
+    // The return address of afact is saved at 4092
+    let mut machine = afact(5);
+    let id = machine
+        .add_watchpoint(4092..4096, WatchKind::Write)
+        .unwrap();
+    assert_eq!(
+        StopReason::Watchpoint(WatchpointHit {
+            id,
+            ip: 16,
+            address: 4092,
+            access: Access::Write,
+            value: 23,
+        }),
+        machine.run().unwrap()
+    );
+    assert_eq!(19, machine.regs()[0]);
+    assert_eq!(StopReason::Exited(0), machine.run().unwrap());
+    assert_eq!(120, machine.regs()[11]);
+}
+
+#[test]
+fn read_watchpoint() {
+    let mut machine = afact(3);
+    machine.add_watchpoint(4095..4096, WatchKind::Read).unwrap();
+    assert_eq!(
+        StopReason::Watchpoint(WatchpointHit {
+            id: 0,
+            ip: 183,
+            address: 4092,
+            access: Access::Read,
+            value: 23,
+        }),
+        machine.run().unwrap()
+    );
+    assert_eq!(StopReason::Exited(0), machine.run().unwrap());
+}
+
+#[test]
+fn access_watchpoint() {
+    // acc is the word at 187, written 3 times and read twice
+    let mut machine = afact(3);
+    machine.add_watchpoint(187..191, WatchKind::Access).unwrap();
+    let mut accesses = Vec::new();
+    while let StopReason::Watchpoint(hit) = machine.run().unwrap() {
+        accesses.push((hit.access, hit.value));
+    }
+    assert_eq!(
+        vec![
+            (Access::Write, 1),
+            (Access::Read, 1),
+            (Access::Write, 3),
+            (Access::Read, 3),
+            (Access::Write, 6),
+        ],
+        accesses
+    );
+}
+
+#[test]
+fn add_and_remove_watchpoints() {
+    let mut machine = afact(3);
+    assert_eq!(0, machine.add_watchpoint(0..10, WatchKind::Read).unwrap());
+    assert_eq!(1, machine.add_watchpoint(10..20, WatchKind::Write).unwrap());
+    assert!(machine.add_watchpoint(10..4097, WatchKind::Write).is_err());
+    assert_eq!(
+        Some(Watchpoint {
+            id: 0,
+            start: 0,
+            end: 10,
+            kind: WatchKind::Read
+        }),
+        machine.remove_watchpoint(0)
+    );
+    assert_eq!(None, machine.remove_watchpoint(0));
+    assert_eq!(1, machine.watchpoints().len());
+    assert_eq!(
+        2,
+        machine
+            .add_watchpoint(4092..4096, WatchKind::Write)
+            .unwrap()
+    );
+    machine.remove_watchpoint(2);
+    assert_eq!(StopReason::Exited(0), machine.run().unwrap());
+}
+
+#[test]
+fn step_reports_watchpoint() {
+    // 0: store [r1] <- r2
+    // 3: load r3 <- [r1]
+    let mut machine = Machine::new(&[2, 1, 2, 3, 3, 1]);
+    machine.set_reg(1, 100).unwrap();
+    machine.set_reg(2, 42).unwrap();
+    machine.add_watchpoint(100..101, WatchKind::Write).unwrap();
+    machine.step().unwrap();
+    assert_eq!(
+        Some(WatchpointHit {
+            id: 0,
+            ip: 0,
+            address: 100,
+            access: Access::Write,
+            value: 42,
+        }),
+        machine.take_watchpoint_hit()
+    );
+    assert_eq!(None, machine.take_watchpoint_hit());
+    machine.step().unwrap();
+    assert_eq!(None, machine.take_watchpoint_hit());
+}